@@ -0,0 +1,168 @@
+//! Fixed ACPI description table
+
+use super::Table;
+
+/// Address spaces of a generic address
+pub const SPACE_MEMORY: u8 = 0;
+pub const SPACE_IO: u8 = 1;
+pub const SPACE_PCI: u8 = 2;
+
+/// The reset register is supported
+const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+/// The PM timer counts 32 bits instead of 24
+const FLAG_TMR_VAL_EXT: u32 = 1 << 8;
+/// IA-PC boot architecture flags
+const BOOT_LEGACY_DEVICES: u16 = 1 << 0;
+const BOOT_8042: u16 = 1 << 1;
+const BOOT_NO_MSI: u16 = 1 << 3;
+
+// Offsets
+const FADT_DSDT: u64 = 40;
+const FADT_SCI_INT: u64 = 46;
+const FADT_SMI_CMD: u64 = 48;
+const FADT_ACPI_ENABLE: u64 = 52;
+const FADT_ACPI_DISABLE: u64 = 53;
+const FADT_PM1A_EVT_BLK: u64 = 56;
+const FADT_PM1B_EVT_BLK: u64 = 60;
+const FADT_PM1A_CNT_BLK: u64 = 64;
+const FADT_PM1B_CNT_BLK: u64 = 68;
+const FADT_PM_TMR_BLK: u64 = 76;
+const FADT_GPE0_BLK: u64 = 80;
+const FADT_PM1_EVT_LEN: u64 = 88;
+const FADT_GPE0_BLK_LEN: u64 = 92;
+const FADT_CENTURY: u64 = 108;
+const FADT_IAPC_BOOT_ARCH: u64 = 109;
+const FADT_FLAGS: u64 = 112;
+const FADT_RESET_REG: u64 = 116;
+const FADT_RESET_VALUE: u64 = 128;
+const FADT_X_DSDT: u64 = 140;
+const FADT_X_PM1A_CNT_BLK: u64 = 172;
+const FADT_X_PM1B_CNT_BLK: u64 = 184;
+const FADT_X_PM_TMR_BLK: u64 = 208;
+
+/// A register in some address space
+#[derive(Clone, Copy, Debug)]
+pub struct GenericAddress {
+    pub space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    fn parse(table: &Table, offset: u64) -> Option<GenericAddress> {
+        let address: u64 = match table.read(offset + 4) {
+            None | Some(0) => return None,
+            Some(address) => address,
+        };
+        Some(GenericAddress {
+            space: table.get(offset),
+            bit_width: table.get(offset + 1),
+            bit_offset: table.get(offset + 2),
+            access_size: table.get(offset + 3),
+            address: address,
+        })
+    }
+
+    /// An I/O port register
+    fn io(port: u32, len: u8) -> Option<GenericAddress> {
+        match port {
+            0 => None,
+            port => Some(GenericAddress {
+                space: SPACE_IO,
+                bit_width: len * 8,
+                bit_offset: 0,
+                access_size: 0,
+                address: port as u64,
+            }),
+        }
+    }
+}
+
+pub struct Fadt {
+    /// Physical address of the DSDT
+    pub dsdt: u64,
+    pub sci_interrupt: u16,
+    /// Port written with acpi_enable to switch to ACPI mode, 0 if fixed
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub pm1a_event: u32,
+    pub pm1b_event: u32,
+    pub pm1_event_len: u8,
+    pub pm1a_control: Option<GenericAddress>,
+    pub pm1b_control: Option<GenericAddress>,
+    pub pm_timer: Option<GenericAddress>,
+    pub gpe0: u32,
+    pub gpe0_len: u8,
+    /// CMOS index of the RTC century, 0 if none
+    pub century: u8,
+    pub boot_flags: u16,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+}
+
+impl Fadt {
+    /// Whether the reset register may be used
+    pub fn can_reset(&self) -> bool {
+        self.flags & FLAG_RESET_REG_SUP != 0 && self.reset_register.is_some()
+    }
+
+    /// Whether the PM timer counts 32 bits
+    pub fn pm_timer_32bit(&self) -> bool {
+        self.flags & FLAG_TMR_VAL_EXT != 0
+    }
+
+    /// Whether there are ISA devices such as the RTC and the PIT
+    /// Firmware older than ACPI 2.0 doesn't say, they're assumed.
+    pub fn legacy_devices(&self) -> bool {
+        self.boot_flags == 0 || self.boot_flags & BOOT_LEGACY_DEVICES != 0
+    }
+
+    /// Whether there's a PS/2 controller
+    pub fn has_8042(&self) -> bool {
+        self.boot_flags == 0 || self.boot_flags & BOOT_8042 != 0
+    }
+
+    /// Whether MSI must not be used
+    pub fn msi_disabled(&self) -> bool {
+        self.boot_flags & BOOT_NO_MSI != 0
+    }
+}
+
+pub fn parse(table: &Table) -> Option<Fadt> {
+    if table.len() < FADT_GPE0_BLK_LEN + 1 {
+        return None;
+    }
+    let field = |offset: u64| table.read::<u8>(offset).unwrap_or(0);
+    let dsdt = match table.read::<u64>(FADT_X_DSDT) {
+        None | Some(0) => table.get::<u32>(FADT_DSDT) as u64,
+        Some(dsdt) => dsdt,
+    };
+    let pm1_control_len = table.get::<u8>(FADT_PM1_EVT_LEN + 1);
+    let extended = |offset: u64, port: u64, len: u8| {
+        GenericAddress::parse(table, offset).or_else(|| GenericAddress::io(table.get(port), len))
+    };
+    Some(Fadt {
+        dsdt: dsdt,
+        sci_interrupt: table.get(FADT_SCI_INT),
+        smi_command: table.get(FADT_SMI_CMD),
+        acpi_enable: table.get(FADT_ACPI_ENABLE),
+        acpi_disable: table.get(FADT_ACPI_DISABLE),
+        pm1a_event: table.get(FADT_PM1A_EVT_BLK),
+        pm1b_event: table.get(FADT_PM1B_EVT_BLK),
+        pm1_event_len: table.get(FADT_PM1_EVT_LEN),
+        pm1a_control: extended(FADT_X_PM1A_CNT_BLK, FADT_PM1A_CNT_BLK, pm1_control_len),
+        pm1b_control: extended(FADT_X_PM1B_CNT_BLK, FADT_PM1B_CNT_BLK, pm1_control_len),
+        pm_timer: extended(FADT_X_PM_TMR_BLK, FADT_PM_TMR_BLK, 4),
+        gpe0: table.get(FADT_GPE0_BLK),
+        gpe0_len: table.get(FADT_GPE0_BLK_LEN),
+        century: field(FADT_CENTURY),
+        boot_flags: table.read(FADT_IAPC_BOOT_ARCH).unwrap_or(0),
+        flags: table.read(FADT_FLAGS).unwrap_or(0),
+        reset_register: GenericAddress::parse(table, FADT_RESET_REG),
+        reset_value: field(FADT_RESET_VALUE),
+    })
+}
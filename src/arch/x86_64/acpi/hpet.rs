@@ -0,0 +1,46 @@
+//! HPET description table
+
+use super::{GenericAddress, Table, SDT_HEADER_SIZE};
+
+pub struct Hpet {
+    /// Hardware revision, comparator count and vendor of the first block
+    pub block_id: u32,
+    pub address: GenericAddress,
+    /// Sequence number of this HPET
+    pub number: u8,
+    /// Smallest periodic tick the hardware supports, in main counter ticks
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    /// Number of comparators
+    pub fn comparators(&self) -> u32 {
+        (self.block_id >> 8 & 0x1f) + 1
+    }
+
+    /// Whether the main counter is 64 bits wide
+    pub fn counter_64bit(&self) -> bool {
+        self.block_id & (1 << 13) != 0
+    }
+
+    /// Whether it can replace the PIT and RTC interrupts
+    pub fn legacy_replacement(&self) -> bool {
+        self.block_id & (1 << 15) != 0
+    }
+}
+
+pub fn parse(table: &Table) -> Option<Hpet> {
+    if table.len() < SDT_HEADER_SIZE + 20 {
+        return None;
+    }
+    let address = match GenericAddress::parse(table, SDT_HEADER_SIZE + 4) {
+        None => return None,
+        Some(address) => address,
+    };
+    Some(Hpet {
+        block_id: table.get(SDT_HEADER_SIZE),
+        address: address,
+        number: table.get(SDT_HEADER_SIZE + 16),
+        minimum_tick: table.get(SDT_HEADER_SIZE + 17),
+    })
+}
@@ -0,0 +1,162 @@
+//! Multiple APIC description table
+
+use alloc::vec::Vec;
+
+use super::{Table, SDT_HEADER_SIZE};
+
+// Entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_NMI: u8 = 4;
+const MADT_LOCAL_APIC_ADDRESS: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+/// Legacy 8259 PICs are installed
+pub const MADT_PCAT_COMPAT: u32 = 1;
+/// Processor flag, usable
+const LAPIC_ENABLED: u32 = 1;
+/// Processor flag, can be enabled at runtime
+const LAPIC_ONLINE_CAPABLE: u32 = 2;
+
+/// Override flags, polarity and trigger mode
+pub const POLARITY_MASK: u16 = 0x3;
+pub const POLARITY_ACTIVE_HIGH: u16 = 0x1;
+pub const POLARITY_ACTIVE_LOW: u16 = 0x3;
+pub const TRIGGER_MASK: u16 = 0xc;
+pub const TRIGGER_EDGE: u16 = 0x4;
+pub const TRIGGER_LEVEL: u16 = 0xc;
+
+/// A processor's local APIC
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApic {
+    pub processor_id: u32,
+    pub apic_id: u32,
+    pub flags: u32,
+}
+
+impl LocalApic {
+    pub fn usable(&self) -> bool {
+        self.flags & (LAPIC_ENABLED | LAPIC_ONLINE_CAPABLE) != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u64,
+    /// First global system interrupt it serves
+    pub gsi_base: u32,
+}
+
+/// An ISA IRQ wired to another global system interrupt
+#[derive(Clone, Copy, Debug)]
+pub struct Override {
+    pub irq: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+/// LINT pin of local APICs wired to NMI
+#[derive(Clone, Copy, Debug)]
+pub struct LocalNmi {
+    /// 0xff stands for all processors
+    pub processor_id: u8,
+    pub flags: u16,
+    pub lint: u8,
+}
+
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub flags: u32,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<Override>,
+    pub local_nmis: Vec<LocalNmi>,
+}
+
+impl Madt {
+    /// Global system interrupt and flags an ISA IRQ is wired to
+    pub fn isa_irq(&self, irq: u8) -> (u32, u16) {
+        match self.overrides.iter().find(|o| o.irq == irq) {
+            Some(o) => (o.gsi, o.flags),
+            None => (irq as u32, 0),
+        }
+    }
+
+    /// Local APICs of the processors which can run
+    pub fn cpus(&self) -> Vec<LocalApic> {
+        self.local_apics.iter().filter(|apic| apic.usable()).cloned().collect()
+    }
+
+    /// LINT pins wired to NMI on a processor, with their flags
+    pub fn nmi_lints(&self, apic_id: u32) -> Vec<(u8, u16)> {
+        let processor_id = self
+            .local_apics
+            .iter()
+            .find(|apic| apic.apic_id == apic_id)
+            .map(|apic| apic.processor_id);
+        self.local_nmis
+            .iter()
+            .filter(|nmi| nmi.processor_id == 0xff || Some(nmi.processor_id as u32) == processor_id)
+            .map(|nmi| (nmi.lint, nmi.flags))
+            .collect()
+    }
+}
+
+pub fn parse(table: &Table) -> Option<Madt> {
+    if table.len() < SDT_HEADER_SIZE + 8 {
+        return None;
+    }
+    let mut madt = Madt {
+        local_apic_address: table.get::<u32>(SDT_HEADER_SIZE) as u64,
+        flags: table.get(SDT_HEADER_SIZE + 4),
+        local_apics: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+        local_nmis: Vec::new(),
+    };
+
+    let mut offset = SDT_HEADER_SIZE + 8;
+    while offset + 2 <= table.len() {
+        let kind: u8 = table.get(offset);
+        let size = table.get::<u8>(offset + 1) as u64;
+        if size < 2 || offset + size > table.len() {
+            break;
+        }
+        let field = |at: u64| offset + at;
+        match kind {
+            MADT_LOCAL_APIC if size >= 8 => madt.local_apics.push(LocalApic {
+                processor_id: table.get::<u8>(field(2)) as u32,
+                apic_id: table.get::<u8>(field(3)) as u32,
+                flags: table.get(field(4)),
+            }),
+            MADT_LOCAL_X2APIC if size >= 16 => madt.local_apics.push(LocalApic {
+                apic_id: table.get(field(4)),
+                flags: table.get(field(8)),
+                processor_id: table.get(field(12)),
+            }),
+            MADT_IO_APIC if size >= 12 => madt.io_apics.push(IoApic {
+                id: table.get(field(2)),
+                address: table.get::<u32>(field(4)) as u64,
+                gsi_base: table.get(field(8)),
+            }),
+            MADT_OVERRIDE if size >= 10 => madt.overrides.push(Override {
+                irq: table.get(field(3)),
+                gsi: table.get(field(4)),
+                flags: table.get(field(8)),
+            }),
+            MADT_LOCAL_APIC_NMI if size >= 6 => madt.local_nmis.push(LocalNmi {
+                processor_id: table.get(field(2)),
+                flags: table.get(field(3)),
+                lint: table.get(field(5)),
+            }),
+            MADT_LOCAL_APIC_ADDRESS if size >= 12 => {
+                madt.local_apic_address = table.get(field(4));
+            }
+            _ => {}
+        }
+        offset += size;
+    }
+    Some(madt)
+}
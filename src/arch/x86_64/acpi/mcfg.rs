@@ -0,0 +1,47 @@
+//! PCI Express memory mapped configuration table
+
+use alloc::vec::Vec;
+
+use super::{Table, SDT_HEADER_SIZE};
+
+/// Entries follow a reserved field
+const MCFG_ENTRIES: u64 = SDT_HEADER_SIZE + 8;
+const MCFG_ENTRY_SIZE: u64 = 16;
+
+/// Configuration space of a range of buses
+#[derive(Clone, Copy, Debug)]
+pub struct McfgEntry {
+    /// Physical address of bus 0, even if start_bus isn't 0
+    pub address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    /// Find the area holding a bus
+    pub fn find(&self, segment: u16, bus: u8) -> Option<&McfgEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.segment == segment && bus >= entry.start_bus && bus <= entry.end_bus)
+    }
+}
+
+pub fn parse(table: &Table) -> Option<Mcfg> {
+    let mut entries = Vec::new();
+    let mut offset = MCFG_ENTRIES;
+    while offset + MCFG_ENTRY_SIZE <= table.len() {
+        entries.push(McfgEntry {
+            address: table.get(offset),
+            segment: table.get(offset + 8),
+            start_bus: table.get(offset + 10),
+            end_bus: table.get(offset + 11),
+        });
+        offset += MCFG_ENTRY_SIZE;
+    }
+    Some(Mcfg { entries: entries })
+}
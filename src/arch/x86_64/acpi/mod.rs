@@ -0,0 +1,336 @@
+//! ACPI tables
+//!
+//! The RSDP is looked up in the EBDA and the BIOS area unless the boot
+//! loader handed one over. Tables are mapped through the physical
+//! window of the MMU, checked, and parsed into typed views on first use.
+
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+
+use alloc::vec::Vec;
+use core::ptr;
+use spin::Once;
+
+use super::mmu::{KERNEL_BASE, MMU};
+
+pub use self::fadt::{Fadt, GenericAddress, SPACE_IO, SPACE_MEMORY, SPACE_PCI};
+pub use self::hpet::Hpet;
+pub use self::madt::{
+    IoApic, LocalApic, LocalNmi, Madt, Override, MADT_PCAT_COMPAT, POLARITY_ACTIVE_HIGH,
+    POLARITY_ACTIVE_LOW, POLARITY_MASK, TRIGGER_EDGE, TRIGGER_LEVEL, TRIGGER_MASK,
+};
+pub use self::mcfg::{Mcfg, McfgEntry};
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Length of the ACPI 1.0 RSDP, covered by its checksum
+const RSDP_V1_SIZE: u64 = 20;
+/// Real mode segment of the EBDA is kept here
+const EBDA_POINTER: u64 = 0x40e;
+const BIOS_AREA_START: u64 = 0xe0000;
+const BIOS_AREA_END: u64 = 0x100000;
+/// Size of the common table header
+const SDT_HEADER_SIZE: u64 = 36;
+
+// AML opcodes
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_NAME_OP: u8 = 0x08;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+const AML_PACKAGE_OP: u8 = 0x12;
+
+/// Common header of the system description tables
+#[derive(Clone, Copy, Debug)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+}
+
+/// A mapped and checked table
+#[derive(Clone, Copy)]
+pub struct Table {
+    /// Kernel address of the header
+    address: u64,
+    length: u64,
+}
+
+impl Table {
+    pub fn header(&self) -> SdtHeader {
+        unsafe {
+            SdtHeader {
+                signature: read(self.address),
+                length: read(self.address + 4),
+                revision: read(self.address + 8),
+                oem_id: read(self.address + 10),
+                oem_table_id: read(self.address + 16),
+            }
+        }
+    }
+
+    /// Length of the table, header included
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    /// Read a field at an offset from the header, None past the end
+    pub fn read<T: Copy>(&self, offset: u64) -> Option<T> {
+        if offset + ::core::mem::size_of::<T>() as u64 > self.length {
+            return None;
+        }
+        Some(unsafe { read(self.address + offset) })
+    }
+
+    /// Read a field known to be within the table
+    fn get<T: Copy>(&self, offset: u64) -> T {
+        self.read(offset).expect("ACPI field past the table")
+    }
+}
+
+/// Physical address of the RSDP from the boot loader
+static mut RSDP_ADDRESS: u64 = 0;
+/// Root table address and whether it's an XSDT
+static ROOT: Once<Option<(u64, bool)>> = Once::new();
+static TABLES: Once<Vec<Table>> = Once::new();
+static MADT: Once<Option<Madt>> = Once::new();
+static FADT: Once<Option<Fadt>> = Once::new();
+static HPET: Once<Option<Hpet>> = Once::new();
+static MCFG: Once<Option<Mcfg>> = Once::new();
+
+/// Read a value at a kernel address, possibly unaligned
+unsafe fn read<T: Copy>(address: u64) -> T {
+    ptr::read_unaligned(address as *const T)
+}
+
+fn checksum(address: u64, len: u64) -> bool {
+    let mut sum: u8 = 0;
+    for i in 0..len {
+        sum = sum.wrapping_add(unsafe { read::<u8>(address + i) });
+    }
+    sum == 0
+}
+
+/// Use the RSDP at a physical address, as found by the boot loader
+/// Must be called before the tables are first used.
+pub fn set_rsdp(address: u64) {
+    unsafe {
+        RSDP_ADDRESS = address;
+    }
+}
+
+/// Check an RSDP at a kernel address
+fn valid_rsdp(rsdp: u64) -> bool {
+    let signature: [u8; 8] = unsafe { read(rsdp) };
+    &signature == RSDP_SIGNATURE && checksum(rsdp, RSDP_V1_SIZE)
+}
+
+/// Look for the RSDP in a range of low memory
+fn scan_rsdp(start: u64, end: u64) -> Option<u64> {
+    let mut address = start & !0xf;
+    while address + RSDP_V1_SIZE <= end {
+        if valid_rsdp(address + KERNEL_BASE) {
+            return Some(address + KERNEL_BASE);
+        }
+        address += 16;
+    }
+    None
+}
+
+fn find_rsdp() -> Option<u64> {
+    let given = unsafe { RSDP_ADDRESS };
+    if given != 0 {
+        return MMU::get()
+            .map_physical(given, RSDP_V1_SIZE, false)
+            .ok()
+            .map(Into::<u64>::into)
+            .filter(|&rsdp| valid_rsdp(rsdp));
+    }
+    let ebda = (unsafe { read::<u16>(EBDA_POINTER + KERNEL_BASE) } as u64) << 4;
+    let rsdp = match ebda {
+        0 => None,
+        ebda => scan_rsdp(ebda, ebda + 1024),
+    };
+    rsdp.or_else(|| scan_rsdp(BIOS_AREA_START, BIOS_AREA_END))
+}
+
+/// Find the root table, returns its physical address and whether it's an XSDT
+fn find_root() -> Option<(u64, bool)> {
+    let rsdp = match find_rsdp() {
+        None => return None,
+        Some(rsdp) => rsdp,
+    };
+    unsafe {
+        let revision: u8 = read(rsdp + 15);
+        if revision >= 2 {
+            let length = read::<u32>(rsdp + 20) as u64;
+            let xsdt: u64 = read(rsdp + 24);
+            if xsdt != 0 && length >= 33 && length <= 64 && checksum(rsdp, length) {
+                return Some((xsdt, true));
+            }
+        }
+        Some((read::<u32>(rsdp + 16) as u64, false))
+    }
+}
+
+/// Map a table at a physical address and check it
+fn map_table(address: u64) -> Option<Table> {
+    let header: u64 = match MMU::get().map_physical(address, SDT_HEADER_SIZE, false) {
+        Err(_) => return None,
+        Ok(header) => header.into(),
+    };
+    let length = unsafe { read::<u32>(header + 4) } as u64;
+    if length < SDT_HEADER_SIZE {
+        return None;
+    }
+    let table: u64 = match MMU::get().map_physical(address, length, false) {
+        Err(_) => return None,
+        Ok(table) => table.into(),
+    };
+    if !checksum(table, length) {
+        return None;
+    }
+    Some(Table {
+        address: table,
+        length: length,
+    })
+}
+
+fn load_tables() -> Vec<Table> {
+    let mut tables = Vec::new();
+    let (root, extended) = match *ROOT.call_once(find_root) {
+        None => return tables,
+        Some(root) => root,
+    };
+    let root = match map_table(root) {
+        None => return tables,
+        Some(root) => root,
+    };
+    let entry_size = if extended { 8 } else { 4 };
+    let count = (root.len() - SDT_HEADER_SIZE) / entry_size;
+    for i in 0..count {
+        let offset = SDT_HEADER_SIZE + i * entry_size;
+        let address = if extended {
+            root.read::<u64>(offset)
+        } else {
+            root.read::<u32>(offset).map(|address| address as u64)
+        };
+        if let Some(table) = address.and_then(map_table) {
+            tables.push(table);
+        }
+    }
+    tables
+}
+
+/// Get every valid table listed by the root table
+pub fn tables() -> &'static [Table] {
+    TABLES.call_once(load_tables)
+}
+
+/// Find a table by its signature
+pub fn find_table(signature: &[u8; 4]) -> Option<Table> {
+    tables()
+        .iter()
+        .find(|table| &table.header().signature == signature)
+        .cloned()
+}
+
+/// Get the DSDT, which isn't listed by the root table
+pub fn dsdt() -> Option<Table> {
+    fadt().and_then(|fadt| map_table(fadt.dsdt))
+}
+
+/// Find the SLP_TYPa and SLP_TYPb values of a sleep state in the DSDT
+/// name is the state's object, such as b"_S5_". Only the plain
+/// package of constants firmware uses for it is understood.
+pub fn sleep_type(name: &[u8; 4]) -> Option<(u8, u8)> {
+    let dsdt = match dsdt() {
+        None => return None,
+        Some(dsdt) => dsdt,
+    };
+    let byte = |offset: u64| dsdt.read::<u8>(offset);
+    let mut offset = SDT_HEADER_SIZE;
+    while offset + 4 < dsdt.len() {
+        let found = (0..4).all(|i| byte(offset + i) == Some(name[i as usize]));
+        // NameOp, possibly with a root prefix, then PackageOp
+        let named = byte(offset - 1) == Some(AML_NAME_OP)
+            || (byte(offset - 1) == Some(b'\\') && byte(offset - 2) == Some(AML_NAME_OP));
+        if !found || !named || byte(offset + 4) != Some(AML_PACKAGE_OP) {
+            offset += 1;
+            continue;
+        }
+
+        // Skip the package length and element count
+        let mut at = offset + 5;
+        let length_bytes = match byte(at) {
+            None => return None,
+            Some(lead) => (lead >> 6) as u64,
+        };
+        at += length_bytes + 2;
+        let mut element = || {
+            let value = match byte(at) {
+                Some(AML_BYTE_PREFIX) => {
+                    at += 1;
+                    byte(at)
+                }
+                Some(AML_ZERO_OP) => Some(0),
+                Some(AML_ONE_OP) => Some(1),
+                other => other,
+            };
+            at += 1;
+            value
+        };
+        return match (element(), element()) {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Get the MADT, None without ACPI or APICs
+pub fn madt() -> Option<&'static Madt> {
+    MADT.call_once(|| find_table(b"APIC").and_then(|table| madt::parse(&table)))
+        .as_ref()
+}
+
+/// Get the FADT
+pub fn fadt() -> Option<&'static Fadt> {
+    FADT.call_once(|| {
+        find_table(b"FACP").and_then(|table| fadt::parse(&table))
+    }).as_ref()
+}
+
+/// Get the HPET description
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.call_once(|| {
+        find_table(b"HPET").and_then(|table| hpet::parse(&table))
+    }).as_ref()
+}
+
+/// Get the PCI Express configuration areas
+pub fn mcfg() -> Option<&'static Mcfg> {
+    MCFG.call_once(|| find_table(b"MCFG").and_then(|table| mcfg::parse(&table)))
+        .as_ref()
+}
+
+/// Load the tables and list them
+/// Needs the heap.
+pub fn init() {
+    let tables = tables();
+    if tables.is_empty() {
+        println!("No ACPI tables found");
+        return;
+    }
+    for table in tables.iter() {
+        let header = table.header();
+        println!(
+            "ACPI {} rev {} {}",
+            ::core::str::from_utf8(&header.signature).unwrap_or("????"),
+            header.revision,
+            ::core::str::from_utf8(&header.oem_id).unwrap_or("")
+        );
+    }
+}
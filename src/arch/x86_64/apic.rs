@@ -0,0 +1,283 @@
+//! Local APIC
+
+use arch::fpu::cpuid;
+use arch::idt::{IrqGuard, IDT};
+
+use super::acpi::{POLARITY_ACTIVE_LOW, POLARITY_MASK, TRIGGER_LEVEL, TRIGGER_MASK};
+use super::mmu::MMU;
+use super::msr::{self, IA32_APIC_BASE, IA32_TSC_DEADLINE};
+use super::timer::{self, CALIBRATE_CYCLES, PIT_FREQUENCY};
+
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_MASK: u64 = 0xffff_f000;
+const APIC_SIZE: u64 = 0x1000;
+
+// Registers, offsets from the base
+const LAPIC_ID: u32 = 0x20;
+const LAPIC_TPR: u32 = 0x80;
+const LAPIC_EOI: u32 = 0xb0;
+const LAPIC_SVR: u32 = 0xf0;
+const LAPIC_ESR: u32 = 0x280;
+const LAPIC_ICR_LOW: u32 = 0x300;
+const LAPIC_ICR_HIGH: u32 = 0x310;
+const LAPIC_LVT_TIMER: u32 = 0x320;
+const LAPIC_LVT_LINT0: u32 = 0x350;
+const LAPIC_LVT_LINT1: u32 = 0x360;
+const LAPIC_LVT_ERROR: u32 = 0x370;
+const LAPIC_TIMER_INITIAL: u32 = 0x380;
+const LAPIC_TIMER_CURRENT: u32 = 0x390;
+const LAPIC_TIMER_DIVIDE: u32 = 0x3e0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_NMI: u32 = 0x4 << 8;
+const LVT_ACTIVE_LOW: u32 = 1 << 13;
+const LVT_LEVEL: u32 = 1 << 15;
+const TIMER_ONESHOT: u32 = 0;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_TSC_DEADLINE: u32 = 2 << 17;
+const ICR_INIT: u32 = 0x5 << 8;
+const ICR_STARTUP: u32 = 0x6 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_LEVEL: u32 = 1 << 15;
+/// Divide the bus clock by 16
+const TIMER_DIVIDE_16: u32 = 0x3;
+
+/// Vectors of interrupts raised by the local APIC itself
+pub const TIMER_VECTOR: usize = 0xf0;
+/// Vectors of interprocessor interrupts
+pub const RESCHEDULE_VECTOR: usize = 0xf1;
+pub const TLB_VECTOR: usize = 0xf2;
+pub const ERROR_VECTOR: usize = 0xfe;
+pub const SPURIOUS_VECTOR: usize = 0xff;
+
+/// Kernel address of the registers, 0 until init()
+static mut BASE: u64 = 0;
+/// Physical address of the registers
+static mut ADDRESS: u64 = 0;
+/// Timer counts per second, with the clock divided by 16
+static mut TIMER_FREQUENCY: u64 = 0;
+
+/// How the timer fires
+#[derive(Clone, Copy, Debug)]
+pub enum TimerMode {
+    /// Every 1/hz seconds
+    Periodic(u64),
+    /// Once, after a number of nanoseconds
+    OneShot(u64),
+    /// Once, when the TSC reaches a value
+    Deadline(u64),
+}
+
+unsafe fn read(register: u32) -> u32 {
+    ((BASE + register as u64) as *const u32).read_volatile()
+}
+
+unsafe fn write(register: u32, value: u32) {
+    ((BASE + register as u64) as *mut u32).write_volatile(value)
+}
+
+/// Whether the CPU has a local APIC
+pub fn present() -> bool {
+    let (_, _, _, edx) = unsafe { cpuid(1, 0) };
+    edx & (1 << 9) != 0
+}
+
+/// Whether the timer supports TSC deadlines
+pub fn tsc_deadline() -> bool {
+    let (_, _, ecx, _) = unsafe { cpuid(1, 0) };
+    ecx & (1 << 24) != 0
+}
+
+/// Whether init() enabled the local APIC
+pub fn enabled() -> bool {
+    unsafe { BASE != 0 }
+}
+
+/// Get the ID of this CPU's local APIC
+pub fn id() -> u32 {
+    unsafe { read(LAPIC_ID) >> 24 }
+}
+
+/// Signal the end of an interrupt
+pub fn eoi() {
+    unsafe {
+        write(LAPIC_EOI, 0);
+    }
+}
+
+fn spurious(_vector: u64, _error_code: u64) {
+    // Spurious interrupts must not be acknowledged
+}
+
+fn error(_vector: u64, _error_code: u64) {
+    unsafe {
+        // Writing latches the errors into the register
+        write(LAPIC_ESR, 0);
+        println!("Local APIC error {:#x}", read(LAPIC_ESR));
+    }
+    eoi();
+}
+
+/// Count the timer against the PIT
+unsafe fn calibrate() {
+    write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write(LAPIC_LVT_TIMER, LVT_MASKED | TIMER_ONESHOT | TIMER_VECTOR as u32);
+    write(LAPIC_TIMER_INITIAL, !0);
+    timer::pit_wait(CALIBRATE_CYCLES);
+    let counted = !0 - read(LAPIC_TIMER_CURRENT);
+    write(LAPIC_TIMER_INITIAL, 0);
+    TIMER_FREQUENCY = counted as u64 * PIT_FREQUENCY / CALIBRATE_CYCLES;
+}
+
+/// Enable the local APIC of this CPU through the mapped registers
+unsafe fn setup(address: u64, nmi: &[(u8, u16)]) {
+    let msr = msr::rdmsr(IA32_APIC_BASE);
+    msr::wrmsr(IA32_APIC_BASE, (msr & !APIC_BASE_MASK) | address | APIC_BASE_ENABLE);
+
+    // External interrupts come from I/O APICs, not LINT0
+    write(LAPIC_LVT_LINT0, LVT_MASKED);
+    write(LAPIC_LVT_LINT1, LVT_MASKED);
+    for &(lint, flags) in nmi {
+        let mut lvt = LVT_NMI;
+        if flags & POLARITY_MASK == POLARITY_ACTIVE_LOW {
+            lvt |= LVT_ACTIVE_LOW;
+        }
+        if flags & TRIGGER_MASK == TRIGGER_LEVEL {
+            lvt |= LVT_LEVEL;
+        }
+        match lint {
+            0 => write(LAPIC_LVT_LINT0, lvt),
+            1 => write(LAPIC_LVT_LINT1, lvt),
+            _ => {}
+        }
+    }
+    write(LAPIC_LVT_TIMER, LVT_MASKED);
+    write(LAPIC_LVT_ERROR, ERROR_VECTOR as u32);
+    write(LAPIC_ESR, 0);
+    write(LAPIC_ESR, 0);
+    write(LAPIC_TPR, 0);
+    write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    eoi();
+}
+
+/// Enable the local APIC at a physical address
+/// nmi lists the LINT pins wired to NMI with their MADT flags.
+pub fn init(address: u64, nmi: &[(u8, u16)]) -> Result<(), ::common::error::Error> {
+    if !present() {
+        return Err(err!(ENODEV));
+    }
+    let base = try!(MMU::get().map_physical(address, APIC_SIZE, true));
+    assert!(IDT::get().register_isr(SPURIOUS_VECTOR, spurious));
+    assert!(IDT::get().register_isr(ERROR_VECTOR, error));
+    unsafe {
+        BASE = base.into();
+        ADDRESS = address;
+        setup(address, nmi);
+        calibrate();
+    }
+    Ok(())
+}
+
+/// Enable the local APIC of an application processor
+/// Its timer runs at the frequency measured on the boot CPU.
+pub fn init_ap(nmi: &[(u8, u16)]) -> Result<(), ::common::error::Error> {
+    if !enabled() {
+        return Err(err!(ENODEV));
+    }
+    unsafe {
+        setup(ADDRESS, nmi);
+    }
+    Ok(())
+}
+
+/// Send an interprocessor interrupt, low is the low half of the ICR
+unsafe fn send_icr(apic_id: u32, low: u32) {
+    write(LAPIC_ICR_HIGH, apic_id << 24);
+    write(LAPIC_ICR_LOW, low);
+    while read(LAPIC_ICR_LOW) & ICR_PENDING != 0 {}
+}
+
+/// Raise a fixed interrupt on another CPU
+pub fn send_ipi(apic_id: u32, vector: usize) {
+    if !enabled() {
+        return;
+    }
+    let _irq = IrqGuard::new();
+    unsafe {
+        send_icr(apic_id, vector as u32);
+    }
+}
+
+/// Wake up an application processor with INIT-SIPI-SIPI
+/// It starts in real mode at page * 0x1000.
+pub fn start_ap(apic_id: u32, page: u8) -> Result<(), ::common::error::Error> {
+    if !enabled() {
+        return Err(err!(ENODEV));
+    }
+    unsafe {
+        send_icr(apic_id, ICR_INIT | ICR_ASSERT | ICR_LEVEL);
+        send_icr(apic_id, ICR_INIT | ICR_LEVEL);
+        // 10ms
+        timer::pit_wait(PIT_FREQUENCY / 100);
+        for _ in 0..2 {
+            write(LAPIC_ESR, 0);
+            send_icr(apic_id, ICR_STARTUP | ICR_ASSERT | page as u32);
+            // 200us
+            timer::pit_wait(PIT_FREQUENCY / 5000);
+        }
+    }
+    Ok(())
+}
+
+/// Start the timer, raising vector
+pub fn start_timer(vector: usize, mode: TimerMode) -> Result<(), ::common::error::Error> {
+    if !enabled() {
+        return Err(err!(ENODEV));
+    }
+    let frequency = unsafe { TIMER_FREQUENCY };
+    unsafe {
+        match mode {
+            TimerMode::Periodic(hz) => {
+                if hz == 0 || frequency / hz == 0 || frequency / hz > !0u32 as u64 {
+                    return Err(err!(EINVAL));
+                }
+                write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+                write(LAPIC_LVT_TIMER, TIMER_PERIODIC | vector as u32);
+                write(LAPIC_TIMER_INITIAL, (frequency / hz) as u32);
+            }
+            TimerMode::OneShot(ns) => {
+                let count = ns / 1000 * frequency / 1_000_000;
+                if count == 0 || count > !0u32 as u64 {
+                    return Err(err!(EINVAL));
+                }
+                write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+                write(LAPIC_LVT_TIMER, TIMER_ONESHOT | vector as u32);
+                write(LAPIC_TIMER_INITIAL, count as u32);
+            }
+            TimerMode::Deadline(tsc) => {
+                if !tsc_deadline() {
+                    return Err(err!(ENODEV));
+                }
+                write(LAPIC_LVT_TIMER, TIMER_TSC_DEADLINE | vector as u32);
+                msr::wrmsr(IA32_TSC_DEADLINE, tsc);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stop the timer
+pub fn stop_timer() {
+    if !enabled() {
+        return;
+    }
+    unsafe {
+        write(LAPIC_LVT_TIMER, LVT_MASKED);
+        write(LAPIC_TIMER_INITIAL, 0);
+        if tsc_deadline() {
+            msr::wrmsr(IA32_TSC_DEADLINE, 0);
+        }
+    }
+}
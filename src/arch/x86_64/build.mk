@@ -2,4 +2,5 @@ OBJECTS := \
 	build/interrupt.o \
 	build/startup.o \
 	build/pgdir.o \
-	build/save_context.o
+	build/switch.o \
+	build/trampoline.o
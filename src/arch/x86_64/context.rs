@@ -1,90 +1,192 @@
+use alloc::vec::Vec;
+use arch::fpu;
+use arch::fpu::FpuState;
 use arch::gdt;
+use arch::idt::IrqGuard;
 use arch::mmu::{PageTable, PhysicalAddress, VirtualAddress, KERNEL_BASE, MMU, PAGE_SIZE};
+use arch::percpu;
+use arch::tlb;
+use arch::tss;
+use core::mem::size_of;
 use core::ops::Drop;
-use task::tasks;
-
-/// General purpose registers
-#[repr(C, packed)]
-#[derive(Clone, Copy, Debug)]
-pub struct GPR {
-    rax: u64,
-    rcx: u64,
-    rdx: u64,
-    rbx: u64,
-    rsi: u64,
-    rdi: u64,
-    r8: u64,
-    r9: u64,
-    r10: u64,
-    r11: u64,
-    r12: u64,
-    r13: u64,
-    r14: u64,
-    r15: u64,
+use core::ptr::copy_nonoverlapping;
+
+/// Number of pages used by a kernel stack
+const KERNEL_STACK_PAGES: usize = 4;
+/// End of user space, covered by the only page table of a context
+pub const USER_LIMIT: u64 = 0x200000;
+/// Available PTE bit marking pages mapped by map_shared()
+const PTE_SHARED: u64 = 1 << 9;
+
+extern "C" {
+    /// Save callee-saved registers on the current stack,
+    /// store the stack pointer to `prev` and resume from `next`
+    fn context_switch(prev: *mut u64, next: u64);
+    /// First code run by a new context, finishes the switch
+    /// and leaves through the trap frame on top of the stack
+    fn context_entry();
 }
 
-/// Segment registers
-/// We use 64bit unsigned numbers here for convenience
-#[repr(C, packed)]
-#[derive(Clone, Copy, Debug)]
-pub struct SR {
-    cs: u64,
-    ds: u64,
-    es: u64,
-    fs: u64,
-    gs: u64,
-    ss: u64,
+/// Entry point of kernel threads
+/// Runs the thread function then terminates the thread.
+extern "C" fn kthread_start(entry: fn(usize), arg: usize) -> ! {
+    entry(arg);
+    ::task::exit(0);
 }
 
-/// Structure representing architecture-dependent context
-#[repr(C, packed)]
-pub struct Context {
+/// Registers saved on interrupt, laid out
+/// as int_common_entry pushes them
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TrapFrame {
+    pub gs: u64,
+    pub fs: u64,
+    pub es: u64,
+    pub ds: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    // pushed by processor
+    pub rip: u64,
+    pub cs: u64,
     pub rflags: u64,
-    pub cr3: u64,
     pub rsp: u64,
-    pub rip: u64,
-    pub rbp: u64,
-
-    page_table: VirtualAddress,
-    kernel_stack: VirtualAddress,
-
-    pub gpr: GPR,
-    pub sr: SR,
+    pub ss: u64,
 }
 
-impl Context {
-    /// Create a new context
-    pub fn new() -> Self {
-        // Starts at usermode
-        let user_cs: u64 = (gdt::GDT_64_USER_CODE | 3).into();
-        let user_ds: u64 = (gdt::GDT_64_USER_DATA | 3).into();
-        let user_ss: u64 = (gdt::GDT_64_USER_DATA | 3).into();
-        let sr = SR {
-            cs: user_cs,
-            ds: user_ds,
-            es: user_ds,
-            fs: user_ds,
-            gs: user_ds,
-            ss: user_ss,
+impl TrapFrame {
+    /// Create a frame which returns to the given privilege level
+    fn new(user: bool) -> Self {
+        let (cs, ds): (u64, u64) = if user {
+            (
+                (gdt::GDT_64_USER_CODE | 3).into(),
+                (gdt::GDT_64_USER_DATA | 3).into(),
+            )
+        } else {
+            (gdt::GDT_64_CODE.into(), gdt::GDT_64_DATA.into())
         };
 
-        let gpr = GPR {
-            rax: 0,
+        TrapFrame {
+            gs: ds,
+            fs: ds,
+            es: ds,
+            ds: ds,
+            r15: 0,
+            r14: 0,
+            r13: 0,
+            r12: 0,
+            r11: 0,
+            r10: 0,
+            r9: 0,
+            r8: 0,
+            rdi: 0,
+            rsi: 0,
+            rbp: 0,
+            rbx: 0,
             rcx: 0,
             rdx: 0,
-            rbx: 0,
-            rsi: 0,
-            rdi: 0,
-            r8: 0,
-            r9: 0,
-            r10: 0,
-            r11: 0,
-            r12: 0,
-            r13: 0,
-            r14: 0,
-            r15: 0,
-        };
+            rax: 0,
+            vector: 0,
+            error_code: 0,
+            rip: 0,
+            cs: cs,
+            rflags: (0 << 12 | 1 << 9), // IOPL & IF
+            rsp: 0,
+            ss: ds,
+        }
+    }
+
+    /// Check whether the interrupted code was running in user mode
+    pub fn user_mode(&self) -> bool {
+        self.cs & 3 == 3
+    }
+
+    /// Force the frame to return to user mode
+    /// Used when the frame was restored from user memory.
+    pub fn make_user(&mut self) {
+        let user = TrapFrame::new(true);
+        self.cs = user.cs;
+        self.ss = user.ss;
+        self.ds = user.ds;
+        self.es = user.es;
+        self.fs = user.fs;
+        self.gs = user.gs;
+        // Keep arithmetic flags only, interrupts always on
+        self.rflags = (self.rflags & 0xCD5) | user.rflags;
+    }
+}
+
+/// Pages taken out of an address space by Context::unmap()
+pub struct Unmapped {
+    space: u64,
+    address: u64,
+    count: usize,
+    frames: Vec<u64>,
+}
+
+impl Unmapped {
+    /// Flush the pages from every CPU and drop their references
+    /// Shooting down waits for the other CPUs, no spin lock may be held.
+    pub fn finish(self) {
+        tlb::shootdown(self.space, self.address, self.count);
+        let mmu = MMU::get();
+        for frame in self.frames.iter() {
+            mmu.free_phys(PhysicalAddress::new(*frame))
+                .expect("Invalid physical page");
+        }
+    }
+}
+
+/// Registers saved by context_switch
+#[repr(C)]
+struct SwitchFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbx: u64,
+    rbp: u64,
+    rflags: u64,
+    ret: u64,
+}
+
+/// Structure representing architecture-dependent context
+/// Registers of a switched-out context live on its kernel stack,
+/// only the stack pointer is kept here.
+pub struct Context {
+    cr3: u64,
+    // saved by context_switch
+    kernel_rsp: u64,
+
+    page_table: VirtualAddress,
+    // None if running on the boot stack
+    kernel_stack: Option<VirtualAddress>,
+    stack_top: u64,
+    kernel_thread: bool,
+    // resident user pages, current and peak
+    pages: u64,
+    peak_pages: u64,
+    // FPU/SSE state, switched lazily
+    fpu: FpuState,
+}
 
+impl Context {
+    /// Create a user context with an empty address space
+    fn new_user() -> Self {
         // Creates a page table for context
         // Currently we use only one page table in page directory
         // Since we only have 64Mb, one page table could essentially hold
@@ -107,40 +209,284 @@ impl Context {
 
         // Creates kernel stack
         let kernel_stack = mmu
-            .alloc_contiguous(4)
+            .alloc_contiguous(KERNEL_STACK_PAGES)
             .expect("Failed to allocate kernel stack");
 
-        let mut context = Context {
-            rflags: (0 << 12 | 1 << 9), // IOPL & IF
+        Context {
             cr3: cr3,
-            rsp: 0,
-            rip: 0,
-            rbp: 0,
+            kernel_rsp: 0,
 
             page_table: pml4_vaddr,
-            kernel_stack: kernel_stack,
+            kernel_stack: Some(kernel_stack),
+            stack_top: kernel_stack.add(KERNEL_STACK_PAGES as u64 * PAGE_SIZE),
+            kernel_thread: false,
+            pages: 0,
+            peak_pages: 0,
+            fpu: FpuState::new(),
+        }
+    }
 
-            gpr: gpr,
-            sr: sr,
-        };
+    /// Create a new context
+    pub fn new() -> Self {
+        let mut context = Context::new_user();
 
         // Creates user stack
-        let pt: *mut PageTable = pt_vaddr.as_ptr();
+        let mmu = MMU::get();
+        let pt = unsafe { context.user_table() }.expect("Corrupted page table");
         for i in 0..4 {
             let phys = mmu.alloc_phys().expect("Failed to allocate user stack");
             unsafe {
                 (*pt).map(511 - i, phys, true, true, false);
             }
         }
-        context.rsp = 0x1FF000;
+        context.account_pages(4);
+
+        drop(mmu);
+
+        // Starts at usermode
+        let mut frame = TrapFrame::new(true);
+        frame.rsp = 0x1FF000;
+        context.prepare(frame);
+
+        context
+    }
+
+    /// Create a copy of this context for a child task
+    /// Private pages are copied, shared pages stay shared.
+    /// Must be called from this context, the child resumes from frame.
+    pub fn fork(&self, frame: &TrapFrame) -> Result<Context, ::common::error::Error> {
+        if self.kernel_thread {
+            return Err(err!(EINVAL));
+        }
+
+        let mut child = Context::new_user();
+        child.fpu = self.fpu.fork();
+        let mut buffer = vec![0u8; PAGE_SIZE as usize];
+        {
+            let mmu = MMU::get();
+            unsafe {
+                let pt = try!(self.user_table());
+                let child_pt = try!(child.user_table());
+                for idx in 0..512 {
+                    let entry = (*pt).get(idx);
+                    if entry & 1 == 0 {
+                        continue;
+                    }
+                    let paddr = PhysicalAddress::new(entry).mask(12);
+                    let rw = entry & 0x2 != 0;
+                    if entry & PTE_SHARED != 0 {
+                        try!(mmu.ref_phys(paddr.into()));
+                        (*child_pt).map(idx, paddr.into(), rw, true, false);
+                        (*child_pt).set_bits(idx, PTE_SHARED);
+                    } else {
+                        let phys = try!(mmu.alloc_phys());
+                        (*child_pt).map(idx, phys, rw, true, false);
+                        // Bounce the page through the kernel heap
+                        let address = idx as u64 * PAGE_SIZE;
+                        self.copy_out(address, &mut buffer);
+                        child.copy_in(address, &buffer);
+                    }
+                    child.account_pages(1);
+                }
+            }
+        }
+
+        child.prepare(*frame);
+        Ok(child)
+    }
+
+    /// Get the page table covering user space
+    unsafe fn user_table(&self) -> Result<*mut PageTable, ::common::error::Error> {
+        let pml4: *mut PageTable = self.page_table.as_ptr();
+        let pdpt = try!((*pml4).next(0));
+        let pd = try!((*pdpt).next(0));
+        (*pd).next(0)
+    }
+
+    /// Copy user memory of this context out to a kernel buffer
+    unsafe fn copy_out(&self, address: u64, buffer: &mut [u8]) {
+        let _irq = IrqGuard::new();
+        let saved_cr3 = super::mmu::cr3();
+        if saved_cr3 != self.cr3 {
+            super::mmu::set_cr3(self.cr3);
+        }
+        copy_nonoverlapping(address as *const u8, buffer.as_mut_ptr(), buffer.len());
+        if saved_cr3 != self.cr3 {
+            super::mmu::set_cr3(saved_cr3);
+        }
+    }
+
+    /// Copy a kernel buffer into user memory of this context
+    unsafe fn copy_in(&self, address: u64, buffer: &[u8]) {
+        let _irq = IrqGuard::new();
+        let saved_cr3 = super::mmu::cr3();
+        if saved_cr3 != self.cr3 {
+            super::mmu::set_cr3(self.cr3);
+        }
+        copy_nonoverlapping(buffer.as_ptr(), address as *mut u8, buffer.len());
+        if saved_cr3 != self.cr3 {
+            super::mmu::set_cr3(saved_cr3);
+        }
+    }
+
+    /// Create a kernel thread context
+    /// The thread runs in ring 0 on its own kernel stack
+    /// and shares the kernel address space.
+    pub fn new_kernel(entry: fn(usize), arg: usize) -> Self {
+        let mmu = MMU::get();
+        let pml4_vaddr = mmu.kernel_pml4();
+        let cr3 = pml4_vaddr.sub(KERNEL_BASE);
+        let kernel_stack = mmu
+            .alloc_contiguous(KERNEL_STACK_PAGES)
+            .expect("Failed to allocate kernel stack");
+
+        let mut context = Context {
+            cr3: cr3,
+            kernel_rsp: 0,
+
+            page_table: pml4_vaddr,
+            kernel_stack: Some(kernel_stack),
+            stack_top: kernel_stack.add(KERNEL_STACK_PAGES as u64 * PAGE_SIZE),
+            kernel_thread: true,
+            pages: 0,
+            peak_pages: 0,
+            fpu: FpuState::new(),
+        };
+
+        // The thread starts below its own trap frame,
+        // leaving a null return address on top,
+        // kthread_start() never returns anyway.
+        // Arguments of kthread_start() are passed in rdi and rsi
+        let mut frame = TrapFrame::new(false);
+        frame.rip = kthread_start as usize as u64;
+        frame.rdi = entry as usize as u64;
+        frame.rsi = arg as u64;
+        frame.rsp = context.stack_top - size_of::<TrapFrame>() as u64 - 16 - 8;
+        context.prepare(frame);
+        unsafe {
+            *(frame.rsp as *mut u64) = 0;
+        }
 
         context
     }
 
+    /// Create the context of the code already running on the boot stack of this CPU
+    /// Its registers are filled in when it's switched out for the first time.
+    pub fn new_idle() -> Self {
+        let pml4_vaddr = MMU::get().kernel_pml4();
+        Context {
+            cr3: pml4_vaddr.sub(KERNEL_BASE),
+            kernel_rsp: 0,
+
+            page_table: pml4_vaddr,
+            kernel_stack: None,
+            stack_top: percpu::current().stack_top,
+            kernel_thread: true,
+            pages: 0,
+            peak_pages: 0,
+            fpu: FpuState::new(),
+        }
+    }
+
+    /// Lay out the initial trap frame and switch frame on the kernel stack
+    /// so the first switch to this context leaves through the trap frame.
+    fn prepare(&mut self, frame: TrapFrame) {
+        let frame_ptr = (self.stack_top - size_of::<TrapFrame>() as u64) as *mut TrapFrame;
+        let switch_ptr = (frame_ptr as u64 - size_of::<SwitchFrame>() as u64) as *mut SwitchFrame;
+        unsafe {
+            *frame_ptr = frame;
+            *switch_ptr = SwitchFrame {
+                r15: 0,
+                r14: 0,
+                r13: 0,
+                r12: 0,
+                rbx: 0,
+                rbp: 0,
+                rflags: 0x2, // interrupts stay off until iretq
+                ret: context_entry as usize as u64,
+            };
+        }
+        self.kernel_rsp = switch_ptr as u64;
+    }
+
+    /// The trap frame taken when the context entered the kernel
+    /// from user mode, or the initial frame of a new context
+    pub fn frame(&mut self) -> &mut TrapFrame {
+        assert!(self.kernel_stack.is_some());
+        let frame_ptr = (self.stack_top - size_of::<TrapFrame>() as u64) as *mut TrapFrame;
+        unsafe { &mut *frame_ptr }
+    }
+
+    /// Check whether this is a kernel thread context
+    pub fn is_kernel(&self) -> bool {
+        self.kernel_thread
+    }
+
+    /// Check whether [address, address + len) is mapped in user space
+    pub fn user_accessible(&self, address: u64, len: u64, write: bool) -> bool {
+        if self.kernel_thread {
+            return false;
+        }
+        if len == 0 {
+            return true;
+        }
+        let end = match address.checked_add(len) {
+            None => return false,
+            Some(end) => end,
+        };
+        if end > USER_LIMIT {
+            return false;
+        }
+
+        unsafe {
+            let pml4: *mut PageTable = self.page_table.as_ptr();
+            let pt = match (*pml4)
+                .next(0)
+                .and_then(|pdpt| (*pdpt).next(0))
+                .and_then(|pd| (*pd).next(0))
+            {
+                Err(_) => return false,
+                Ok(pt) => pt,
+            };
+            let first = VirtualAddress::new(address).table_index();
+            let last = VirtualAddress::new(end - 1).table_index();
+            for idx in first..last + 1 {
+                let entry = (*pt).get(idx);
+                // present, user and writable if needed
+                if entry & 0x5 != 0x5 || (write && entry & 0x2 == 0) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Resident user pages
+    pub fn pages(&self) -> u64 {
+        self.pages
+    }
+
+    /// Peak resident user pages
+    pub fn peak_pages(&self) -> u64 {
+        self.peak_pages
+    }
+
+    fn account_pages(&mut self, count: u64) {
+        self.pages += count;
+        if self.pages > self.peak_pages {
+            self.peak_pages = self.pages;
+        }
+    }
+
     /// Allocate a physical page
     /// and maps it to the current
     /// task environment
-    pub fn map(&self, address: u64) -> Result<u64, ::common::error::Error> {
+    pub fn map(&mut self, address: u64) -> Result<u64, ::common::error::Error> {
+        // Kernel threads don't own a user address space
+        if self.kernel_thread {
+            return Err(err!(EINVAL));
+        }
+
         let mmu = MMU::get();
         unsafe {
             let pml4: *mut PageTable = self.page_table.as_ptr();
@@ -171,11 +517,111 @@ impl Context {
             if !(*pt).map(idx, phys, true, true, false) {
                 return Err(err!(EFAULT));
             }
+            self.account_pages(1);
 
             return Ok(idx as u64 * PAGE_SIZE);
         }
     }
 
+    /// Check a page range of user space, returns the index of its first page
+    fn user_range(&self, address: u64, count: usize) -> Result<usize, ::common::error::Error> {
+        if self.kernel_thread || address == 0 || address % PAGE_SIZE != 0 {
+            return Err(err!(EINVAL));
+        }
+        let end = (count as u64)
+            .checked_mul(PAGE_SIZE)
+            .and_then(|len| address.checked_add(len));
+        match end {
+            Some(end) if end <= USER_LIMIT => Ok(VirtualAddress::new(address).table_index()),
+            _ => Err(err!(EINVAL)),
+        }
+    }
+
+    /// Map physical pages owned elsewhere at address
+    /// Each mapping takes a reference to its page, so the pages
+    /// outlive their owner as long as they stay mapped.
+    pub fn map_shared(
+        &mut self,
+        address: u64,
+        frames: &[u64],
+        writable: bool,
+    ) -> Result<u64, ::common::error::Error> {
+        let first = try!(self.user_range(address, frames.len()));
+
+        let mmu = MMU::get();
+        unsafe {
+            let pml4: *mut PageTable = self.page_table.as_ptr();
+            let pdpt = try!((*pml4).next(0));
+            let pd = try!((*pdpt).next(0));
+            let pt = try!((*pd).next(0));
+
+            for idx in first..first + frames.len() {
+                if (*pt).present(idx) {
+                    return Err(err!(EAGAIN));
+                }
+            }
+            for (i, frame) in frames.iter().enumerate() {
+                let paddr = PhysicalAddress::new(*frame);
+                if let Err(e) = mmu.ref_phys(paddr) {
+                    // Roll back what's mapped so far
+                    for idx in first..first + i {
+                        (*pt).unmap(idx);
+                        let _ = mmu.free_phys(PhysicalAddress::new(frames[idx - first]));
+                    }
+                    return Err(e);
+                }
+                (*pt).map(first + i, paddr, writable, true, false);
+                (*pt).set_bits(first + i, PTE_SHARED);
+            }
+        }
+        self.account_pages(frames.len() as u64);
+        Ok(address)
+    }
+
+    /// Unmap count pages from address, skipping holes
+    /// Nothing is unmapped if any page in the range can't be.
+    /// The pages stay in the TLBs and allocated until the result is
+    /// finished, which must happen with no spin lock held.
+    pub fn unmap(&mut self, address: u64, count: usize) -> Result<Unmapped, ::common::error::Error> {
+        let first = try!(self.user_range(address, count));
+
+        let mut frames = Vec::new();
+        unsafe {
+            let pml4: *mut PageTable = self.page_table.as_ptr();
+            let pdpt = try!((*pml4).next(0));
+            let pd = try!((*pdpt).next(0));
+            let pt = try!((*pd).next(0));
+
+            // Each page must hold a reference for finish() to drop
+            {
+                let mmu = MMU::get();
+                for idx in first..first + count {
+                    if !(*pt).present(idx) {
+                        continue;
+                    }
+                    let paddr = PhysicalAddress::new((*pt).get(idx)).mask(12);
+                    if mmu.phys_refs(PhysicalAddress::new(paddr)) == 0 {
+                        return Err(err!(EFAULT));
+                    }
+                }
+            }
+            for idx in first..first + count {
+                if !(*pt).present(idx) {
+                    continue;
+                }
+                frames.push(PhysicalAddress::new((*pt).get(idx)).mask(12));
+                (*pt).unmap(idx);
+            }
+        }
+        self.pages -= frames.len() as u64;
+        Ok(Unmapped {
+            space: self.cr3,
+            address: address,
+            count: count,
+            frames: frames,
+        })
+    }
+
     /// Write on behalf of this context
     pub unsafe fn write<T: Sized>(&self, ptr: *mut T, val: T) {
         let mut saved_cr3: u64 = 0xFFF;
@@ -207,68 +653,19 @@ impl Context {
 
         val
     }
+}
 
-    /// Switch to this context
-    /// This should only be called in kernel mode
-    #[naked]
-    #[inline(never)]
-    pub unsafe fn switch_to(&self) -> ! {
-        asm!(r#"
-        /* push interrupt stack frame */
-        push qword ptr [rbx+0x28]   /* ss */ 
-        push qword ptr [rcx+0x10]   /* rsp */ 
-        push qword ptr [rcx+0x0]    /* rflags */ 
-        push qword ptr [rbx+0x0]    /* cs */ 
-        push qword ptr [rcx+0x18]   /* rip */
-
-        /* switch page table */
-        mov rdx, [rcx+0x8]
-        mov rsi, cr3
-        cmp rdx, rsi
-        je skip_cr3
-        mov cr3, rdx
-    skip_cr3:
-
-        /* set gpr & sr */
-        mov rsi, [rax+0x20]
-        mov rdi, [rax+0x28]
-        mov r8, [rax+0x30]
-        mov r9, [rax+0x38]
-        mov r10, [rax+0x40]
-        mov r11, [rax+0x48]
-        mov r12, [rax+0x50]
-        mov r13, [rax+0x58]
-        mov r14, [rax+0x60]
-        mov r15, [rax+0x68]
-        mov rdx, [rbx+0x8]
-        mov ds, dx
-        mov rdx, [rbx+0x10]
-        mov es, dx
-        mov rdx, [rbx+0x18]
-        mov fs, dx
-        mov rdx, [rbx+0x20]
-        mov gs, dx 
-        mov rbp, [rcx+0x20]
-        mov rcx, [rax+0x8]
-        mov rdx, [rax+0x10]
-        mov rbx, [rax+0x18]
-        push [rax]
-        pop rax 
-
-        /* go back to work */
-        iretq 
-        "#
-        :
-        :
-        "{rax}" (&self.gpr as *const GPR),
-        "{rbx}" (&self.sr as *const SR),
-        "{rcx}" (self as *const Context)
-        :   // No clobbers
-        :
-        "intel", "volatile" 
-            );
-        unreachable!();
+/// Switch from prev to next
+/// Must be called with interrupts disabled, both contexts
+/// must stay alive until the switch is done.
+/// Returns when prev is switched back.
+pub unsafe fn switch(prev: *mut Context, next: *const Context) {
+    tss::set_kernel_stack((*next).stack_top);
+    fpu::switch(&(*prev).fpu, &(*next).fpu);
+    if super::mmu::cr3() != (*next).cr3 {
+        super::mmu::set_cr3((*next).cr3);
     }
+    context_switch(&mut (*prev).kernel_rsp as *mut u64, (*next).kernel_rsp);
 }
 
 // drop(context) should be called in dispatcher
@@ -280,10 +677,20 @@ impl Drop for Context {
         let mmu = MMU::get();
 
         // Free kernel stack
-        mmu.free_contiguous(self.kernel_stack, 4)
-            .expect("Failed to free kernel stack");
+        match self.kernel_stack {
+            Some(kernel_stack) => mmu
+                .free_contiguous(kernel_stack, KERNEL_STACK_PAGES)
+                .expect("Failed to free kernel stack"),
+            None => {}
+        };
 
-        // Free physical pages allocated
+        // Kernel threads borrow the kernel page table
+        if self.kernel_thread {
+            return;
+        }
+
+        // Drop the references of the mapped pages,
+        // shared pages are freed by their last mapping
         unsafe {
             let pml4: *mut PageTable = self.page_table.as_ptr();
             let pdpt = (*pml4).next(0).expect("Corrupted page table");
@@ -307,64 +714,3 @@ impl Drop for Context {
         }
     }
 }
-
-#[repr(C, packed)]
-#[derive(Clone, Copy, Debug)]
-pub struct CapturedContext {
-    cr3: u64,
-    rbp: u64,
-    gs: u64,
-    fs: u64,
-    es: u64,
-    ds: u64,
-    gpr: GPR,
-    reserved_0: u64, //  return address
-    reserved_1: u64, //  interrupt vector
-    reserved_2: u64, //  error code
-    rip: u64,
-    cs: u64,
-    rflags: u64,
-    rsp: u64,
-    ss: u64,
-}
-
-/// This is called by save_context
-/// which is called upon interrupt
-/// happens.
-#[no_mangle]
-pub extern "C" fn store_context(ctx: *const CapturedContext) {
-    let tasks = tasks();
-    {
-        let current_lock = tasks.current();
-        if !current_lock.is_none() {
-            let mut current = current_lock.unwrap().write();
-            unsafe {
-                current.context.rsp = (*ctx).rsp;
-                current.context.rbp = (*ctx).rbp;
-                current.context.cr3 = (*ctx).cr3;
-                current.context.rip = (*ctx).rip;
-                current.context.rflags = (*ctx).rflags;
-                current.context.sr.cs = (*ctx).cs;
-                current.context.sr.ds = (*ctx).ds;
-                current.context.sr.es = (*ctx).es;
-                current.context.sr.fs = (*ctx).fs;
-                current.context.sr.gs = (*ctx).gs;
-                current.context.sr.ss = (*ctx).ss;
-                current.context.gpr.rax = (*ctx).gpr.rax;
-                current.context.gpr.rcx = (*ctx).gpr.rcx;
-                current.context.gpr.rdx = (*ctx).gpr.rdx;
-                current.context.gpr.rbx = (*ctx).gpr.rbx;
-                current.context.gpr.rsi = (*ctx).gpr.rsi;
-                current.context.gpr.rdi = (*ctx).gpr.rdi;
-                current.context.gpr.r8 = (*ctx).gpr.r8;
-                current.context.gpr.r9 = (*ctx).gpr.r9;
-                current.context.gpr.r10 = (*ctx).gpr.r10;
-                current.context.gpr.r11 = (*ctx).gpr.r11;
-                current.context.gpr.r12 = (*ctx).gpr.r12;
-                current.context.gpr.r13 = (*ctx).gpr.r13;
-                current.context.gpr.r14 = (*ctx).gpr.r14;
-                current.context.gpr.r15 = (*ctx).gpr.r15;
-            }
-        }
-    }
-}
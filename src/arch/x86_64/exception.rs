@@ -0,0 +1,57 @@
+//! Processor exceptions
+//!
+//! Faults raised in user mode are turned into signals of
+//! the running task, faults in kernel mode are fatal except
+//! on the iretq to user mode, which kills the task.
+//! Page faults are handled by the MMU.
+
+use arch::idt;
+use arch::idt::IDT;
+use task::{self, signal};
+
+extern "C" {
+    /// The iretq back to user mode
+    static user_iret: u8;
+}
+
+/// Signal sent for an exception vector
+fn signal_of(vector: u64) -> Option<u32> {
+    match vector {
+        0 => Some(signal::SIGFPE),   // Divide error
+        4 => Some(signal::SIGSEGV),  // Overflow
+        5 => Some(signal::SIGSEGV),  // BOUND range exceeded
+        6 => Some(signal::SIGILL),   // Invalid opcode
+        11 => Some(signal::SIGBUS),  // Segment not present
+        12 => Some(signal::SIGBUS),  // Stack segment fault
+        13 => Some(signal::SIGSEGV), // General protection
+        16 => Some(signal::SIGFPE),  // x87 FPU Floating-Point error
+        17 => Some(signal::SIGBUS),  // Alignment check
+        19 => Some(signal::SIGFPE),  // SIMD Floating-Point exception
+        _ => None,
+    }
+}
+
+fn fault(vector: u64, error_code: u64) {
+    let frame = unsafe { idt::trap_frame() }.expect("Exception without trap frame");
+    let sig = signal_of(vector).expect("Unexpected exception");
+    if !frame.user_mode() {
+        // The frame restored for user mode is bad, the task can't go back
+        if frame.rip == unsafe { &user_iret as *const u8 as u64 } {
+            task::exit(128 + sig as u64);
+        }
+        panic!(
+            "Exception {} in kernel mode, error code {:#x}, rip {:#x}",
+            vector, error_code, frame.rip
+        );
+    }
+    signal::force(sig);
+}
+
+pub fn init() {
+    let idt = IDT::get();
+    for vector in 0..32 {
+        if signal_of(vector).is_some() {
+            assert!(idt.register_isr(vector as usize, fault));
+        }
+    }
+}
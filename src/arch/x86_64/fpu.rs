@@ -0,0 +1,255 @@
+//! FPU/SSE/AVX state
+//!
+//! The state is loaded lazily: switching contexts sets CR0.TS,
+//! the first FPU instruction of the incoming context raises #NM,
+//! and the handler loads the state of the running context.
+//! The outgoing state is saved on the switch when it's loaded,
+//! so the context may resume on another CPU.
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use arch::idt::IDT;
+use arch::mmu::{cr0, set_cr0};
+use arch::percpu;
+use core::alloc::Layout;
+use core::ops::Drop;
+use core::ptr::{copy_nonoverlapping, null_mut};
+
+const CR0_MP: u64 = 1 << 1;
+const CR0_EM: u64 = 1 << 2;
+const CR0_TS: u64 = 1 << 3;
+const CR0_NE: u64 = 1 << 5;
+const CR4_OSFXSR: u64 = 1 << 9;
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+const CR4_OSXSAVE: u64 = 1 << 18;
+
+const CPUID_EDX_FXSR: u32 = 1 << 24;
+const CPUID_ECX_XSAVE: u32 = 1 << 26;
+const CPUID_ECX_AVX: u32 = 1 << 28;
+
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+/// Size of the legacy FXSAVE area
+const FXSAVE_SIZE: usize = 512;
+/// Save areas must be 64 bytes aligned for XSAVE
+const SAVE_ALIGN: usize = 64;
+
+// Initial control words
+const FCW_INIT: u16 = 0x037F;
+const MXCSR_INIT: u32 = 0x1F80;
+
+/// Size of the save area, detected in init()
+static mut SAVE_SIZE: usize = FXSAVE_SIZE;
+/// Whether XSAVE/XRSTOR are used
+static mut USE_XSAVE: bool = false;
+/// Features enabled in XCR0
+static mut XCR0: u64 = XCR0_X87 | XCR0_SSE;
+
+/// Execute CPUID
+pub unsafe fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let eax: u32;
+    let ebx: u32;
+    let ecx: u32;
+    let edx: u32;
+    asm!("cpuid"
+         : "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx), "={edx}"(edx)
+         : "{eax}"(leaf), "{ecx}"(subleaf)
+         :
+         : "volatile");
+    (eax, ebx, ecx, edx)
+}
+
+#[inline]
+unsafe fn cr4() -> u64 {
+    let result: u64;
+    asm!("mov %cr4, $0" : "=r"(result) : : );
+    result
+}
+
+#[inline]
+unsafe fn set_cr4(value: u64) {
+    asm!("mov $0, %cr4" : : "r"(value) : "memory" : "volatile");
+}
+
+#[inline]
+unsafe fn xsetbv(reg: u32, value: u64) {
+    asm!("xsetbv"
+         :
+         : "{ecx}"(reg), "{eax}"(value as u32), "{edx}"((value >> 32) as u32)
+         :
+         : "volatile");
+}
+
+#[inline]
+unsafe fn clts() {
+    asm!("clts" : : : : "volatile");
+}
+
+unsafe fn save(area: *mut u8) {
+    if USE_XSAVE {
+        asm!("xsave64 ($0)"
+             :
+             : "r"(area), "{eax}"(XCR0 as u32), "{edx}"((XCR0 >> 32) as u32)
+             : "memory"
+             : "volatile");
+    } else {
+        asm!("fxsave64 ($0)" : : "r"(area) : "memory" : "volatile");
+    }
+}
+
+unsafe fn restore(area: *const u8) {
+    if USE_XSAVE {
+        asm!("xrstor64 ($0)"
+             :
+             : "r"(area), "{eax}"(XCR0 as u32), "{edx}"((XCR0 >> 32) as u32)
+             : "memory"
+             : "volatile");
+    } else {
+        asm!("fxrstor64 ($0)" : : "r"(area) : "memory" : "volatile");
+    }
+}
+
+/// Saved FPU state of a context
+pub struct FpuState {
+    area: *mut u8,
+}
+
+unsafe impl Send for FpuState {}
+unsafe impl Sync for FpuState {}
+
+impl FpuState {
+    /// Create the initial state, as after FNINIT
+    pub fn new() -> Self {
+        unsafe {
+            let area = alloc_zeroed(Self::layout());
+            if area.is_null() {
+                panic!("Failed to allocate FPU state");
+            }
+            *(area as *mut u16) = FCW_INIT;
+            *(area.offset(24) as *mut u32) = MXCSR_INIT;
+            if USE_XSAVE {
+                // XSTATE_BV in the XSAVE header, the x87 and SSE
+                // states are taken from the legacy area, others
+                // are left in their initial configuration
+                *(area.offset(FXSAVE_SIZE as isize) as *mut u64) = XCR0_X87 | XCR0_SSE;
+            }
+            FpuState { area: area }
+        }
+    }
+
+    /// Copy the state of the running context
+    pub fn fork(&self) -> Self {
+        let state = FpuState::new();
+        unsafe {
+            // The running context has TS clear if it owns the FPU
+            if percpu::current().fpu_owner == self.area {
+                save(self.area);
+            }
+            copy_nonoverlapping(self.area, state.area, SAVE_SIZE);
+        }
+        state
+    }
+
+    fn layout() -> Layout {
+        unsafe { Layout::from_size_align_unchecked(SAVE_SIZE, SAVE_ALIGN) }
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe {
+            // Contexts are only freed once switched out,
+            // so the state can't be loaded in another CPU
+            let cpu = percpu::current();
+            if cpu.fpu_owner == self.area {
+                cpu.fpu_owner = null_mut();
+            }
+            if cpu.fpu_current == self.area {
+                cpu.fpu_current = null_mut();
+            }
+            dealloc(self.area, Self::layout());
+        }
+    }
+}
+
+/// Called on context switch, with interrupts disabled
+/// Saves the outgoing state if it's loaded and traps
+/// the next FPU instruction to load the incoming one.
+pub unsafe fn switch(prev: &FpuState, next: &FpuState) {
+    let cpu = percpu::current();
+    if cpu.fpu_owner == prev.area {
+        save(prev.area);
+        cpu.fpu_owner = null_mut();
+    }
+    cpu.fpu_current = next.area;
+    set_cr0(cr0() | CR0_TS);
+}
+
+/// Device not available (#NM) handler
+fn device_not_available(_vector: u64, _error_code: u64) {
+    unsafe {
+        clts();
+        let cpu = percpu::current();
+        if cpu.fpu_owner == cpu.fpu_current {
+            return;
+        }
+        if !cpu.fpu_owner.is_null() {
+            save(cpu.fpu_owner);
+        }
+        if !cpu.fpu_current.is_null() {
+            restore(cpu.fpu_current);
+        }
+        cpu.fpu_owner = cpu.fpu_current;
+    }
+}
+
+/// Enable the FPU of this CPU with the features detected by init()
+pub fn init_cpu() {
+    unsafe {
+        // Use the FPU natively and trap on first use
+        set_cr0((cr0() & !CR0_EM) | CR0_MP | CR0_NE | CR0_TS);
+        let cr4_value = cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if USE_XSAVE {
+            set_cr4(cr4_value | CR4_OSXSAVE);
+            xsetbv(0, XCR0);
+        } else {
+            set_cr4(cr4_value);
+        }
+    }
+}
+
+pub fn init() {
+    unsafe {
+        let (_, _, ecx, edx) = cpuid(1, 0);
+        if edx & CPUID_EDX_FXSR == 0 {
+            panic!("FXSAVE is not supported");
+        }
+
+        if ecx & CPUID_ECX_XSAVE != 0 {
+            XCR0 = XCR0_X87 | XCR0_SSE;
+            if ecx & CPUID_ECX_AVX != 0 {
+                XCR0 |= XCR0_AVX;
+            }
+            USE_XSAVE = true;
+        }
+    }
+
+    init_cpu();
+
+    unsafe {
+        if USE_XSAVE {
+            // EBX reports the size needed for the features enabled in XCR0
+            let (_, ebx, _, _) = cpuid(0xD, 0);
+            SAVE_SIZE = align!(ebx as usize, SAVE_ALIGN);
+        }
+    }
+
+    assert!(IDT::get().register_isr(7, device_not_available));
+    unsafe {
+        println!(
+            "FPU: {} bytes state, xsave {}, xcr0 {:#x}",
+            SAVE_SIZE, USE_XSAVE, XCR0
+        );
+    }
+}
@@ -1,11 +1,13 @@
 use super::tss::TSS;
 use core::mem::size_of;
-use core::ops::{DerefMut, Drop};
-use core::sync::atomic;
+use core::ptr::copy_nonoverlapping;
+
+/// Number of descriptors in a GDT
+pub const GDT_ENTRIES: usize = 10;
 
 extern "C" {
-    static mut gdt64: GdtDescriptor;
-    static mut gdt: [GdtEntry; 10];
+    /// Boot GDT, copied into the GDT of each CPU
+    static gdt: [GdtEntry; GDT_ENTRIES];
 }
 
 // Currently available segments
@@ -42,96 +44,66 @@ unsafe fn lgdt(ptr: *const GdtDescriptor) {
     asm!("lgdt ($0)" : : "r"(ptr) : "memory");
 }
 
-static GDT_LOCK: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
-
-/// An instance of GDT
-pub struct GDT(bool);
-
-impl GDT {
-    pub fn get() -> Self {
-        while !GDT_LOCK.compare_and_swap(false, true, atomic::Ordering::Relaxed) {}
-        atomic::fence(atomic::Ordering::Acquire);
-
-        GDT(true)
+/// Fill a descriptor
+unsafe fn set_entry(
+    entry: &mut GdtEntry,
+    limit: u32,
+    base: u32,
+    dtype: u8,
+    dpl: u8,
+    user: bool,
+    avl: bool,
+    long: bool,
+    db: bool,
+    g: bool,
+) {
+    entry.low_limit = (limit & 0xffff) as u16;
+    entry.low_base = (base & 0xffff) as u16;
+    entry.mid_base = ((base >> 16) & 0xff) as u8;
+    entry.attribute = (dtype & 0xf) | ((dpl & 0x3) << 5) | (0x80);
+    if user {
+        entry.attribute |= 1 << 4;
     }
-
-    pub unsafe fn set_entry(
-        &self,
-        idx: usize,
-        limit: u32,
-        base: u32,
-        dtype: u8,
-        dpl: u8,
-        user: bool,
-        avl: bool,
-        long: bool,
-        db: bool,
-        g: bool,
-    ) {
-        let entry = &mut gdt[idx];
-
-        entry.low_limit = (limit & 0xffff) as u16;
-        entry.low_base = (base & 0xffff) as u16;
-        entry.mid_base = ((base >> 16) & 0xff) as u8;
-        entry.attribute = (dtype & 0xf) | ((dpl & 0x3) << 5) | (0x80);
-        if user {
-            entry.attribute |= 1 << 4;
-        }
-        entry.mixed = ((limit >> 16) & 0xf) as u8;
-        if avl {
-            entry.mixed |= 1 << 4;
-        }
-        if long {
-            entry.mixed |= 1 << 5;
-        }
-        if db {
-            entry.mixed |= 1 << 6;
-        }
-        if g {
-            entry.mixed |= 1 << 7;
-        }
-        entry.hi_base = ((base >> 24) & 0xff) as u8;
+    entry.mixed = ((limit >> 16) & 0xf) as u8;
+    if avl {
+        entry.mixed |= 1 << 4;
     }
-
-    pub unsafe fn set(&self, idx: usize, v: u64) {
-        let entry = (&mut gdt[idx] as *mut _ as u64) as *mut u64;
-
-        *entry = v;
+    if long {
+        entry.mixed |= 1 << 5;
     }
-
-    pub fn flush(&self) {
-        unsafe {
-            lgdt(&gdt64 as *const GdtDescriptor);
-        }
+    if db {
+        entry.mixed |= 1 << 6;
     }
-}
-
-impl Drop for GDT {
-    fn drop(&mut self) {
-        GDT_LOCK.store(false, atomic::Ordering::Release);
+    if g {
+        entry.mixed |= 1 << 7;
     }
+    entry.hi_base = ((base >> 24) & 0xff) as u8;
 }
 
-pub fn init() {
-    let mut tss = super::tss::TSS_SC.write();
-    unsafe {
-        let g = GDT::get();
-        let limit = size_of::<TSS>() as u32 - 1;
-        let base = tss.deref_mut() as *mut TSS as u64;
-
-        g.set_entry(
-            7,
-            limit,
-            (base & 0xFFFFFFFF) as u32,
-            GDT_TSS_AVAIL,
-            0,
-            false,
-            false,
-            false,
-            false,
-            false,
-        );
-        g.set(8, (base >> 32) & 0xFFFFFFFF);
-        g.flush();
-    }
+/// Fill the GDT of a CPU with the boot segments
+/// and a descriptor for its TSS, then load it
+pub unsafe fn load(table: &mut [u64; GDT_ENTRIES], tss: &TSS) {
+    copy_nonoverlapping(&gdt as *const _ as *const u64, table.as_mut_ptr(), GDT_ENTRIES);
+
+    let limit = size_of::<TSS>() as u32 - 1;
+    let base = tss as *const TSS as u64;
+    set_entry(
+        &mut *(&mut table[7] as *mut u64 as *mut GdtEntry),
+        limit,
+        (base & 0xFFFFFFFF) as u32,
+        GDT_TSS_AVAIL,
+        0,
+        false,
+        false,
+        false,
+        false,
+        false,
+    );
+    table[8] = (base >> 32) & 0xFFFFFFFF;
+
+    let descriptor = GdtDescriptor {
+        limit: (size_of::<[u64; GDT_ENTRIES]>() - 1) as u16,
+        base: table.as_ptr() as u64,
+    };
+    lgdt(&descriptor as *const GdtDescriptor);
 }
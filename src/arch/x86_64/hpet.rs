@@ -0,0 +1,226 @@
+//! High Precision Event Timer
+//!
+//! The main counter runs at a fixed rate given in femtoseconds per
+//! count and serves as a clock source. Each comparator raises an
+//! interrupt once or periodically when the counter reaches it.
+
+use arch::acpi::{self, POLARITY_ACTIVE_HIGH, SPACE_MEMORY, TRIGGER_EDGE};
+use arch::irq::{self, MAX_IRQS};
+use arch::mmu::MMU;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const HPET_SIZE: u64 = 0x400;
+
+// Registers, offsets from the base
+const HPET_CAPABILITIES: u64 = 0x0;
+const HPET_CONFIG: u64 = 0x10;
+const HPET_COUNTER: u64 = 0xf0;
+
+/// Registers of comparator n
+fn timer_config(n: usize) -> u64 {
+    0x100 + 0x20 * n as u64
+}
+fn timer_comparator(n: usize) -> u64 {
+    0x108 + 0x20 * n as u64
+}
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY: u64 = 1 << 1;
+
+const TIMER_LEVEL: u64 = 1 << 1;
+const TIMER_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAP: u64 = 1 << 4;
+const TIMER_VALUE_SET: u64 = 1 << 6;
+const TIMER_32BIT: u64 = 1 << 8;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1f << TIMER_ROUTE_SHIFT;
+const TIMER_FSB: u64 = 1 << 14;
+
+/// Longest period the counter may have, 100ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+const FS_PER_NS: u64 = 1_000_000;
+
+/// Kernel address of the registers, 0 until init()
+static BASE: AtomicUsize = AtomicUsize::new(0);
+/// Femtoseconds per count
+static PERIOD_FS: AtomicUsize = AtomicUsize::new(0);
+
+/// How a comparator fires
+#[derive(Clone, Copy, Debug)]
+pub enum TimerMode {
+    /// Every 1/hz seconds
+    Periodic(u64),
+    /// Once, after a number of nanoseconds
+    OneShot(u64),
+}
+
+unsafe fn read(register: u64) -> u64 {
+    ((BASE.load(Ordering::Relaxed) as u64 + register) as *const u64).read_volatile()
+}
+
+unsafe fn write(register: u64, value: u64) {
+    ((BASE.load(Ordering::Relaxed) as u64 + register) as *mut u64).write_volatile(value)
+}
+
+/// Whether init() found an HPET
+pub fn present() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Femtoseconds per count of the main counter, 0 without an HPET
+pub fn period_fs() -> u64 {
+    PERIOD_FS.load(Ordering::Relaxed) as u64
+}
+
+/// Counts per second
+pub fn frequency() -> u64 {
+    match period_fs() {
+        0 => 0,
+        period => 1_000_000_000_000_000 / period,
+    }
+}
+
+/// Read the main counter
+pub fn counter() -> u64 {
+    if !present() {
+        return 0;
+    }
+    unsafe { read(HPET_COUNTER) }
+}
+
+/// Nanoseconds counted by the main counter since it was enabled
+pub fn clock_ns() -> u64 {
+    let count = counter();
+    let period = period_fs();
+    count / FS_PER_NS * period + count % FS_PER_NS * period / FS_PER_NS
+}
+
+/// Number of comparators
+pub fn timers() -> usize {
+    if !present() {
+        return 0;
+    }
+    unsafe { (read(HPET_CAPABILITIES) >> 8 & 0x1f) as usize + 1 }
+}
+
+/// IRQ a comparator is wired to
+/// An I/O APIC input is preferred, the legacy replacement
+/// route stands in for comparators 0 and 1 without one.
+pub fn timer_irq(timer: usize) -> Result<u8, ::common::error::Error> {
+    if timer >= timers() {
+        return Err(err!(ENODEV));
+    }
+    unsafe {
+        let config = read(timer_config(timer));
+        let routes = config >> 32;
+        if irq::apic_enabled() {
+            // ISA IRQs are taken, the first ones are shared with the PIT and RTC
+            if let Some(gsi) = (16..MAX_IRQS).find(|&gsi| routes & 1 << gsi != 0) {
+                try!(irq::set_mode(gsi as u8, POLARITY_ACTIVE_HIGH | TRIGGER_EDGE));
+                write(
+                    timer_config(timer),
+                    config & !(TIMER_ROUTE_MASK | TIMER_FSB) | (gsi as u64) << TIMER_ROUTE_SHIFT,
+                );
+                return Ok(gsi as u8);
+            }
+        }
+        if timer < 2 && read(HPET_CAPABILITIES) & (1 << 15) != 0 {
+            write(HPET_CONFIG, read(HPET_CONFIG) | CONFIG_LEGACY);
+            return Ok(if timer == 0 { 0 } else { 8 });
+        }
+    }
+    Err(err!(ENODEV))
+}
+
+/// Start a comparator, which raises the IRQ from timer_irq()
+/// Returns the period or delay actually programmed, in nanoseconds.
+pub fn start_timer(timer: usize, mode: TimerMode) -> Result<u64, ::common::error::Error> {
+    if timer >= timers() {
+        return Err(err!(ENODEV));
+    }
+    let period = period_fs();
+    unsafe {
+        let config = read(timer_config(timer))
+            & !(TIMER_LEVEL | TIMER_PERIODIC | TIMER_VALUE_SET | TIMER_32BIT | TIMER_FSB);
+        match mode {
+            TimerMode::Periodic(hz) => {
+                if config & TIMER_PERIODIC_CAP == 0 {
+                    return Err(err!(ENODEV));
+                }
+                let counts = if hz == 0 { 0 } else { frequency() / hz };
+                if counts == 0 {
+                    return Err(err!(EINVAL));
+                }
+                // With VALUE_SET, the second write sets the period
+                write(
+                    timer_config(timer),
+                    config | TIMER_ENABLE | TIMER_PERIODIC | TIMER_VALUE_SET,
+                );
+                write(timer_comparator(timer), read(HPET_COUNTER) + counts);
+                write(timer_comparator(timer), counts);
+                Ok(counts * period / FS_PER_NS)
+            }
+            TimerMode::OneShot(ns) => {
+                let counts = ns / period * FS_PER_NS + ns % period * FS_PER_NS / period;
+                if counts == 0 {
+                    return Err(err!(EINVAL));
+                }
+                write(timer_comparator(timer), read(HPET_COUNTER) + counts);
+                write(timer_config(timer), config | TIMER_ENABLE);
+                Ok(counts * period / FS_PER_NS)
+            }
+        }
+    }
+}
+
+/// Stop a comparator
+/// The legacy replacement route is dropped once comparators 0 and 1 are off,
+/// giving IRQ 0 and 8 back to the PIT and RTC.
+pub fn stop_timer(timer: usize) {
+    if timer >= timers() {
+        return;
+    }
+    unsafe {
+        let config = read(timer_config(timer));
+        write(timer_config(timer), config & !(TIMER_ENABLE | TIMER_PERIODIC));
+
+        let legacy_off = (0..timers().min(2)).all(|n| read(timer_config(n)) & TIMER_ENABLE == 0);
+        if legacy_off {
+            write(HPET_CONFIG, read(HPET_CONFIG) & !CONFIG_LEGACY);
+        }
+    }
+}
+
+/// Map the HPET described by ACPI and start its main counter
+pub fn init() {
+    let hpet = match acpi::hpet() {
+        Some(hpet) if hpet.address.space == SPACE_MEMORY => hpet,
+        _ => return,
+    };
+    let base = match MMU::get().map_physical(hpet.address.address, HPET_SIZE, true) {
+        Ok(base) => base,
+        Err(e) => {
+            println!("Failed to map the HPET: {:?}", e);
+            return;
+        }
+    };
+    BASE.store(Into::<u64>::into(base) as usize, Ordering::Relaxed);
+
+    unsafe {
+        let period = read(HPET_CAPABILITIES) >> 32;
+        if period == 0 || period > MAX_PERIOD_FS {
+            println!("HPET reports an invalid period of {}fs", period);
+            BASE.store(0, Ordering::Relaxed);
+            return;
+        }
+        PERIOD_FS.store(period as usize, Ordering::Relaxed);
+
+        // Comparators stay off until started
+        for timer in 0..timers() {
+            stop_timer(timer);
+        }
+        write(HPET_CONFIG, read(HPET_CONFIG) & !CONFIG_LEGACY | CONFIG_ENABLE);
+    }
+    println!("HPET: {} comparators, {}fs period", timers(), period_fs());
+}
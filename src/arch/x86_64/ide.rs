@@ -1,11 +1,27 @@
+use alloc::string::String;
 use arch::io;
-use common::consts::*;
+use arch::irq::{self, IRQ_VECTOR_BASE};
+use arch::mmu::{KERNEL_BASE, MMU, PAGE_SIZE};
+use arch::pci::{self, Bar, Driver, Match};
+use arch::timer;
+use core::cmp::min;
+use core::ptr::copy_nonoverlapping;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::marker::{Send, Sync};
 use dev::{devices_mut, Device};
+use sync::{IrqSpinLock, Mutex, WaitQueue};
 
 pub struct IDEDevice {
-    diskno: u8,
-    offset: u64,
+    channel: Channel,
+    /// 0 for the master, 1 for the slave
+    drive: u8,
+    /// Capacity in sectors
+    sectors: u64,
+    lba48: bool,
+    /// Transfers go through the channel's bus master
+    dma: bool,
+    model: String,
+    serial: String,
 }
 
 unsafe impl Sync for IDEDevice {}
@@ -20,218 +36,792 @@ const ATA_SR_CORR: u8 = 0x4;
 const ATA_SR_IDX: u8 = 0x2;
 const ATA_SR_ERR: u8 = 0x1;
 
+// Command block registers, from the channel base
+const ATA_REG_DATA: u16 = 0;
+const ATA_REG_SECCOUNT: u16 = 2;
+const ATA_REG_LBA0: u16 = 3;
+const ATA_REG_LBA1: u16 = 4;
+const ATA_REG_LBA2: u16 = 5;
+const ATA_REG_HDDEVSEL: u16 = 6;
+const ATA_REG_STATUS: u16 = 7;
+const ATA_REG_COMMAND: u16 = 7;
+// Control block register, reads as the status without side effects
+// and takes the device control when written
+const ATA_REG_ALTSTATUS: u16 = 0;
+const ATA_REG_CONTROL: u16 = 0;
+
+const ATA_CMD_READ_PIO: u8 = 0x20;
+const ATA_CMD_READ_PIO_EXT: u8 = 0x24;
+const ATA_CMD_WRITE_PIO: u8 = 0x30;
+const ATA_CMD_WRITE_PIO_EXT: u8 = 0x34;
+const ATA_CMD_READ_DMA: u8 = 0xC8;
+const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
+const ATA_CMD_WRITE_DMA: u8 = 0xCA;
+const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_CMD_IDENTIFY: u8 = 0xEC;
+
+/// In the device control, interrupts are off
+const ATA_CONTROL_NIEN: u8 = 0x2;
+/// In the device control, the drives are held in reset
+const ATA_CONTROL_SRST: u8 = 0x4;
+/// Alternate status reads the reset is held for, at least 5us
+const RESET_POLLS: usize = 50;
+
+// Bus master registers, from the channel's block in BAR4
+const BM_COMMAND: u16 = 0;
+const BM_STATUS: u16 = 2;
+const BM_PRD: u16 = 4;
+/// Size of the register block of a channel
+const BM_CHANNEL_SIZE: u16 = 8;
+
+const BM_CMD_START: u8 = 0x1;
+/// The bus master writes to memory, for reads from the disk
+const BM_CMD_READ: u8 = 0x8;
+const BM_SR_ACTIVE: u8 = 0x1;
+const BM_SR_ERR: u8 = 0x2;
+const BM_SR_IRQ: u8 = 0x4;
+
+/// Last entry of a PRD table
+const PRD_EOT: u64 = 1 << 63;
+/// Pages of the DMA buffer of a channel, each taking a PRD entry
+const DMA_PAGES: usize = 16;
+/// Most sectors moved by one command
+const MAX_SECTORS: usize = DMA_PAGES * PAGE_SIZE as usize / SECTOR_SIZE;
+/// How long a command may take, in seconds
+const COMMAND_TIMEOUT: u64 = 5;
+
+/// Selects LBA addressing in the drive register
+const ATA_HDDEVSEL_LBA: u8 = 0xE0;
+
+// IDENTIFY DEVICE words
+const IDENT_SERIAL: usize = 10;
+const IDENT_CAPABILITIES: usize = 49;
+const IDENT_SERIAL_WORDS: usize = 10;
+const IDENT_MODEL: usize = 27;
+const IDENT_MODEL_WORDS: usize = 20;
+const IDENT_LBA28_SECTORS: usize = 60;
+const IDENT_COMMAND_SETS: usize = 83;
+const IDENT_LBA48_SECTORS: usize = 100;
+/// In the capabilities word, DMA is supported
+const IDENT_DMA: u16 = 1 << 8;
+/// In the command sets word, 48-bit addressing is supported
+const IDENT_LBA48: u16 = 1 << 10;
+
+/// Highest sector LBA28 reaches, plus one
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Polls before a drive is taken for absent
+const PROBE_TIMEOUT: u32 = 100000;
+
 const SECTOR_SIZE: usize = 512;
 
-/// Switch to diskno and wait
-unsafe fn wait_disk() -> bool {
-    let mut r: u8;
-    loop {
-        r = io::inb(0x1F7);
-        if r & (ATA_SR_BUSY | ATA_SR_DRDY) == ATA_SR_DRDY {
-            break;
-        }
+/// Mass storage controllers of the IDE subclass
+const PCI_CLASS_STORAGE: u8 = 0x01;
+const PCI_SUBCLASS_IDE: u8 = 0x01;
+/// In the programming interface, channel n uses PCI native mode
+const PROG_IF_NATIVE: [u8; 2] = [1 << 0, 1 << 2];
+/// In the programming interface, the controller is a bus master
+const PROG_IF_BUS_MASTER: u8 = 1 << 7;
+/// Interrupt line of a function without one
+const NO_IRQ: u8 = 0xFF;
+
+/// Ports and IRQs of the channels in compatibility mode
+const LEGACY_CHANNELS: [Channel; 2] = [
+    Channel {
+        index: 0,
+        base: 0x1F0,
+        control: 0x3F6,
+        irq: 14,
+        bus_master: 0,
+    },
+    Channel {
+        index: 1,
+        base: 0x170,
+        control: 0x376,
+        irq: 15,
+        bus_master: 0,
+    },
+];
+
+static MATCHES: [Match; 1] = [Match::class(PCI_CLASS_STORAGE, PCI_SUBCLASS_IDE)];
+
+/// Serializes commands on a channel, its two drives share the registers
+/// and the DMA buffer.
+static CHANNEL_LOCKS: [Mutex<Dma>; 2] = [Mutex::new(Dma::none()), Mutex::new(Dma::none())];
+/// Channels taking interrupts, locked around sleeping on WAITERS
+static INTERRUPTS: [IrqSpinLock<Option<Channel>>; 2] =
+    [IrqSpinLock::new(None), IrqSpinLock::new(None)];
+/// Tasks waiting for a command to finish on a channel
+static WAITERS: [WaitQueue; 2] = [WaitQueue::new(), WaitQueue::new()];
+/// Channels running with interrupts off in the device control
+static POLLED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+/// Registers of an IDE channel
+#[derive(Clone, Copy, Debug)]
+struct Channel {
+    index: usize,
+    /// Command block
+    base: u16,
+    /// Control block
+    control: u16,
+    irq: u8,
+    /// Bus master registers, 0 without
+    bus_master: u16,
+}
+
+/// DMA memory of a channel, physically contiguous
+struct Dma {
+    /// Kernel address of the PRD table, a page
+    prd: u64,
+    /// Kernel address of DMA_PAGES pages data is bounced through
+    buffer: u64,
+}
+
+impl Dma {
+    const fn none() -> Self {
+        Dma { prd: 0, buffer: 0 }
     }
-    match r & (ATA_SR_DF | ATA_SR_ERR) {
-        0 => true,
-        _ => false,
+
+    fn allocate(&mut self) -> Result<(), ::common::error::Error> {
+        let mmu = MMU::get();
+        let prd = try!(mmu.alloc_page());
+        let buffer = match mmu.alloc_contiguous(DMA_PAGES) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                let _ = mmu.free_page(prd);
+                return Err(e);
+            }
+        };
+        self.prd = prd.into();
+        self.buffer = buffer.into();
+        Ok(())
     }
-}
 
-// MIT 6.828
-unsafe fn probe_disk(no: u8) -> bool {
-    let mut x: u32 = 0;
-    io::outb(0x1F6, 0xE0 | (no << 4));
-    loop {
-        if x >= 1000 || io::inb(0x1F7) & (ATA_SR_BUSY | ATA_SR_ERR | ATA_SR_DF) == 0 {
-            break;
+    fn free(&mut self) {
+        if self.buffer == 0 {
+            return;
         }
-        x += 1;
+        let mmu = MMU::get();
+        let _ = mmu.free_page(self.prd.into());
+        let _ = mmu.free_contiguous(self.buffer.into(), DMA_PAGES);
+        *self = Dma::none();
     }
-    return x < 1000;
 }
 
-impl IDEDevice {
-    pub fn new(diskno: u8) -> Self {
-        unsafe {
-            probe_disk(diskno);
+impl Channel {
+    unsafe fn read(&self, reg: u16) -> u8 {
+        io::inb(self.base + reg)
+    }
+
+    unsafe fn write(&self, reg: u16, value: u8) {
+        io::outb(self.base + reg, value)
+    }
+
+    /// Give the drive 400ns to put its status up
+    unsafe fn delay(&self) {
+        for _ in 0..4 {
+            io::inb(self.control + ATA_REG_ALTSTATUS);
         }
-        Self {
-            diskno: diskno,
-            offset: 0,
+    }
+
+    /// Device control value, with interrupts off if the channel polls
+    fn device_control(&self) -> u8 {
+        if POLLED[self.index].load(Ordering::Relaxed) {
+            ATA_CONTROL_NIEN
+        } else {
+            0
         }
     }
-}
 
-/// Read from IDE disk
-unsafe fn ide_read(diskno: u8, secno: u32, data: &mut [u8], nsecs: u8) -> bool {
-    assert!(data.len() >= (nsecs as usize) * SECTOR_SIZE);
+    /// Soft reset both drives, so a hung one doesn't block later commands
+    /// The master is selected afterwards.
+    unsafe fn reset(&self) {
+        let control = self.device_control();
+        io::outb(self.control + ATA_REG_CONTROL, control | ATA_CONTROL_SRST);
+        for _ in 0..RESET_POLLS {
+            io::inb(self.control + ATA_REG_ALTSTATUS);
+        }
+        io::outb(self.control + ATA_REG_CONTROL, control);
+        self.delay();
+    }
+
+    /// Sleep until done holds, woken by the channel's interrupt
+    /// Polls every tick in case the interrupt is lost, and busily
+    /// before tasks run. False if the command timed out, the channel
+    /// is reset then.
+    fn wait_for(&self, done: unsafe fn(&Channel) -> bool) -> bool {
+        let deadline = timer::ticks() + COMMAND_TIMEOUT * timer::hz();
+        loop {
+            let interrupts = INTERRUPTS[self.index].lock();
+            if unsafe { done(self) } {
+                return true;
+            }
+            if timer::ticks() >= deadline {
+                unsafe { self.reset() };
+                return false;
+            }
+            WAITERS[self.index].wait_timeout(interrupts, 1);
+        }
+    }
 
-    wait_disk();
-    io::outb(0x1F2, nsecs);
-    io::outb(0x1F3, (secno & 0xFF) as u8);
-    io::outb(0x1F4, ((secno >> 8) & 0xFF) as u8);
-    io::outb(0x1F5, ((secno >> 16) & 0xFF) as u8);
-    io::outb(
-        0x1F6,
-        0xE0 | ((diskno & 1) << 4) | (((secno >> 24) & 0xF) as u8),
-    );
-    io::outb(0x1F7, 0x20);
+    /// The drive is done with a data block or a command, false on error
+    /// Reading the status acknowledges the interrupt.
+    unsafe fn finish(&self) -> bool {
+        self.read(ATA_REG_STATUS) & (ATA_SR_DF | ATA_SR_ERR) == 0
+    }
 
-    let mut ptr = data.as_mut_ptr();
-    for _i in 0..nsecs {
-        if wait_disk() == false {
+    /// Wait for a data block, false on error
+    unsafe fn wait_data(&self) -> bool {
+        if !self.wait_for(data_ready) {
             return false;
         }
-        io::insl(0x1F0, ptr as *mut u32, (SECTOR_SIZE / 4) as u64);
-        ptr = ptr.offset(SECTOR_SIZE as isize);
+        let status = self.read(ATA_REG_STATUS);
+        status & ATA_SR_DRQ != 0 && status & (ATA_SR_DF | ATA_SR_ERR) == 0
     }
-    true
-}
 
-/// Write IDE disk
-unsafe fn ide_write(diskno: u8, secno: u32, data: &[u8], nsecs: u8) -> bool {
-    assert!(data.len() >= (nsecs as usize) * SECTOR_SIZE);
+    unsafe fn select(&self, drive: u8, head: u8) {
+        self.write(ATA_REG_HDDEVSEL, ATA_HDDEVSEL_LBA | (drive << 4) | head);
+        self.delay();
+    }
 
-    wait_disk();
-    io::outb(0x1F2, nsecs);
-    io::outb(0x1F3, (secno & 0xFF) as u8);
-    io::outb(0x1F4, ((secno >> 8) & 0xFF) as u8);
-    io::outb(0x1F5, ((secno >> 16) & 0xFF) as u8);
-    io::outb(
-        0x1F6,
-        0xE0 | ((diskno & 1) << 4) | (((secno >> 24) & 0xF) as u8),
-    );
-    io::outb(0x1F7, 0x30);
+    /// Run IDENTIFY DEVICE, None if no ATA drive answers
+    unsafe fn identify(&self, drive: u8) -> Option<[u16; 256]> {
+        self.select(drive, 0);
+        self.write(ATA_REG_SECCOUNT, 0);
+        self.write(ATA_REG_LBA0, 0);
+        self.write(ATA_REG_LBA1, 0);
+        self.write(ATA_REG_LBA2, 0);
+        self.write(ATA_REG_COMMAND, ATA_CMD_IDENTIFY);
+        self.delay();
 
-    let mut ptr = data.as_ptr();
-    for _i in 0..nsecs {
-        if wait_disk() == false {
-            return false;
+        // Nothing on the cable, or a floating bus
+        let status = self.read(ATA_REG_STATUS);
+        if status == 0 || status == 0xFF {
+            return None;
         }
-        io::outsl(0x1F0, ptr as *const u32, (SECTOR_SIZE / 4) as u64);
-        ptr = ptr.offset(SECTOR_SIZE as isize);
+        let mut polls = 0;
+        loop {
+            let status = self.read(ATA_REG_STATUS);
+            if status & ATA_SR_BUSY == 0 {
+                break;
+            }
+            polls += 1;
+            if polls >= PROBE_TIMEOUT {
+                return None;
+            }
+        }
+        // ATAPI and SATA devices set a signature instead
+        if self.read(ATA_REG_LBA1) != 0 || self.read(ATA_REG_LBA2) != 0 {
+            return None;
+        }
+        loop {
+            let status = self.read(ATA_REG_STATUS);
+            if status & ATA_SR_ERR != 0 {
+                return None;
+            }
+            if status & ATA_SR_DRQ != 0 {
+                break;
+            }
+            polls += 1;
+            if polls >= PROBE_TIMEOUT {
+                return None;
+            }
+        }
+
+        let mut words = [0u16; 256];
+        io::insl(
+            self.base + ATA_REG_DATA,
+            words.as_mut_ptr() as *mut u32,
+            (SECTOR_SIZE / 4) as u64,
+        );
+        Some(words)
     }
-    true
 }
 
-impl Device for IDEDevice {
-    fn read(&mut self, data: &mut [u8]) -> Result<usize, ::common::error::Error> {
-        let mut sector: [u8; 1024] = [0; 1024];
-        let mut secno: u32 = (self.offset / SECTOR_SIZE as u64) as u32;
-        let mut offset: usize = (self.offset as usize) % SECTOR_SIZE;
-        let mut remaining = data.len();
-        let mut index: usize = 0;
+/// The selected drive can take a command
+unsafe fn ready(channel: &Channel) -> bool {
+    let status = io::inb(channel.control + ATA_REG_ALTSTATUS);
+    status & (ATA_SR_BUSY | ATA_SR_DRDY) == ATA_SR_DRDY
+}
 
-        while remaining > 0 {
-            if !unsafe { ide_read(self.diskno, secno, &mut sector, 1) } {
-                return Err(err!(EIO));
+/// A data block is ready, or the command failed
+unsafe fn data_ready(channel: &Channel) -> bool {
+    let status = io::inb(channel.control + ATA_REG_ALTSTATUS);
+    status & ATA_SR_BUSY == 0 && status & (ATA_SR_DRQ | ATA_SR_ERR | ATA_SR_DF) != 0
+}
+
+/// The drive finished the command
+unsafe fn idle(channel: &Channel) -> bool {
+    io::inb(channel.control + ATA_REG_ALTSTATUS) & ATA_SR_BUSY == 0
+}
+
+/// The drive and the bus master are done with a DMA transfer
+unsafe fn dma_done(channel: &Channel) -> bool {
+    let status = io::inb(channel.control + ATA_REG_ALTSTATUS);
+    let bm_status = io::inb(channel.bus_master + BM_STATUS);
+    status & ATA_SR_BUSY == 0
+        && (bm_status & BM_SR_ACTIVE == 0 || status & (ATA_SR_ERR | ATA_SR_DF) != 0)
+}
+
+/// IRQ of IDE channels, wakes the tasks waiting on them
+fn interrupt(vector: u64, _error_code: u64) {
+    let irq = (vector as usize - IRQ_VECTOR_BASE) as u8;
+    for index in 0..2 {
+        let channel = INTERRUPTS[index].lock();
+        if let Some(ref channel) = *channel {
+            if channel.irq != irq {
+                continue;
             }
-            for idx in offset..SECTOR_SIZE {
-                if remaining == 0 {
-                    break;
+            unsafe {
+                channel.read(ATA_REG_STATUS);
+                if channel.bus_master != 0 {
+                    let bm_status = io::inb(channel.bus_master + BM_STATUS);
+                    io::outb(channel.bus_master + BM_STATUS, bm_status | BM_SR_IRQ);
                 }
-                data[index] = sector[idx];
-                index += 1;
-                remaining -= 1;
             }
-            secno += 1;
-            offset = 0;
+            WAITERS[index].wake_all();
         }
-        self.offset += data.len() as u64;
-        Ok(data.len())
     }
+    irq::eoi(irq);
+}
 
-    fn write(&mut self, data: &[u8]) -> Result<usize, ::common::error::Error> {
-        let mut sector: [u8; 1024] = [0; 1024];
-        let mut secno: u32 = (self.offset / SECTOR_SIZE as u64) as u32;
-        let mut offset: usize = (self.offset as usize) % SECTOR_SIZE;
-        let mut remaining = data.len();
-        let mut index: usize = 0;
+/// ATA strings hold two characters per word, the first in the high byte
+fn ata_string(words: &[u16]) -> String {
+    let mut s = String::new();
+    for word in words {
+        s.push((word >> 8) as u8 as char);
+        s.push(*word as u8 as char);
+    }
+    String::from(s.trim())
+}
 
-        while remaining > 0 {
-            if offset != 0 || remaining < SECTOR_SIZE {
-                if !unsafe { ide_read(self.diskno, secno, &mut sector, 1) } {
+impl IDEDevice {
+    /// Identify a drive, None if it doesn't answer
+    /// DMA is used if the drive and the channel support it.
+    fn new(channel: Channel, drive: u8) -> Option<Self> {
+        let words = {
+            let _channel = CHANNEL_LOCKS[channel.index].lock();
+            match unsafe { channel.identify(drive) } {
+                Some(words) => words,
+                None => return None,
+            }
+        };
+        let lba48 = words[IDENT_COMMAND_SETS] & IDENT_LBA48 != 0;
+        let dma = words[IDENT_CAPABILITIES] & IDENT_DMA != 0
+            && CHANNEL_LOCKS[channel.index].lock().buffer != 0;
+        let sectors = if lba48 {
+            (0..4).fold(0u64, |sectors, i| {
+                sectors | (words[IDENT_LBA48_SECTORS + i] as u64) << (16 * i)
+            })
+        } else {
+            words[IDENT_LBA28_SECTORS] as u64 | (words[IDENT_LBA28_SECTORS + 1] as u64) << 16
+        };
+        Some(IDEDevice {
+            channel: channel,
+            drive: drive,
+            sectors: sectors,
+            lba48: lba48,
+            dma: dma,
+            model: ata_string(&words[IDENT_MODEL..IDENT_MODEL + IDENT_MODEL_WORDS]),
+            serial: ata_string(&words[IDENT_SERIAL..IDENT_SERIAL + IDENT_SERIAL_WORDS]),
+        })
+    }
+
+    /// Capacity in bytes
+    pub fn size(&self) -> u64 {
+        self.sectors * SECTOR_SIZE as u64
+    }
+
+    /// Issue a read or write of nsecs sectors at secno
+    /// commands holds the LBA28 and LBA48 variants.
+    unsafe fn command(&self, secno: u64, nsecs: u16, commands: (u8, u8)) -> bool {
+        let channel = &self.channel;
+        let lba48 = secno + nsecs as u64 > LBA28_LIMIT || nsecs > 256;
+        // The status is the selected drive's, which may be the other one
+        let head = if lba48 { 0 } else { (secno >> 24) as u8 & 0xF };
+        channel.select(self.drive, head);
+        if !channel.wait_for(ready) || !channel.finish() {
+            return false;
+        }
+        if lba48 {
+            // High bytes go first, the registers keep two bytes each
+            channel.write(ATA_REG_SECCOUNT, (nsecs >> 8) as u8);
+            channel.write(ATA_REG_LBA0, (secno >> 24) as u8);
+            channel.write(ATA_REG_LBA1, (secno >> 32) as u8);
+            channel.write(ATA_REG_LBA2, (secno >> 40) as u8);
+            channel.write(ATA_REG_SECCOUNT, nsecs as u8);
+            channel.write(ATA_REG_LBA0, secno as u8);
+            channel.write(ATA_REG_LBA1, (secno >> 8) as u8);
+            channel.write(ATA_REG_LBA2, (secno >> 16) as u8);
+            channel.write(ATA_REG_COMMAND, commands.1);
+        } else {
+            channel.write(ATA_REG_SECCOUNT, nsecs as u8);
+            channel.write(ATA_REG_LBA0, secno as u8);
+            channel.write(ATA_REG_LBA1, (secno >> 8) as u8);
+            channel.write(ATA_REG_LBA2, (secno >> 16) as u8);
+            channel.write(ATA_REG_COMMAND, commands.0);
+        }
+        channel.delay();
+        true
+    }
+
+    /// Move nsecs sectors between the DMA buffer and the disk
+    unsafe fn dma_transfer(
+        &self,
+        dma: &Dma,
+        secno: u64,
+        nsecs: u16,
+        write: bool,
+    ) -> Result<(), ::common::error::Error> {
+        let channel = &self.channel;
+        let bm = channel.bus_master;
+
+        // An entry per page keeps each within a 64KB boundary
+        let bytes = nsecs as usize * SECTOR_SIZE;
+        let pages = (bytes + PAGE_SIZE as usize - 1) / PAGE_SIZE as usize;
+        let prd = dma.prd as *mut u64;
+        for i in 0..pages {
+            let length = min(PAGE_SIZE as usize, bytes - i * PAGE_SIZE as usize) as u64;
+            let address = dma.buffer - KERNEL_BASE + i as u64 * PAGE_SIZE;
+            let eot = if i + 1 == pages { PRD_EOT } else { 0 };
+            prd.offset(i as isize).write_volatile(address | length << 32 | eot);
+        }
+
+        io::outl(bm + BM_PRD, (dma.prd - KERNEL_BASE) as u32);
+        io::outb(bm + BM_COMMAND, if write { 0 } else { BM_CMD_READ });
+        let bm_status = io::inb(bm + BM_STATUS);
+        io::outb(bm + BM_STATUS, bm_status | BM_SR_IRQ | BM_SR_ERR);
+
+        let commands = if write {
+            (ATA_CMD_WRITE_DMA, ATA_CMD_WRITE_DMA_EXT)
+        } else {
+            (ATA_CMD_READ_DMA, ATA_CMD_READ_DMA_EXT)
+        };
+        if !self.command(secno, nsecs, commands) {
+            return Err(err!(EIO));
+        }
+        let command = io::inb(bm + BM_COMMAND);
+        io::outb(bm + BM_COMMAND, command | BM_CMD_START);
+
+        let finished = channel.wait_for(dma_done);
+
+        io::outb(bm + BM_COMMAND, command & !BM_CMD_START);
+        let bm_status = io::inb(bm + BM_STATUS);
+        io::outb(bm + BM_STATUS, bm_status | BM_SR_IRQ | BM_SR_ERR);
+        if !finished || !channel.finish() || bm_status & BM_SR_ERR != 0 {
+            return Err(err!(EIO));
+        }
+        Ok(())
+    }
+
+    /// Bytes of a transfer at position which lie on the drive
+    fn clamp(&self, position: u64, len: usize) -> usize {
+        let size = self.sectors * SECTOR_SIZE as u64;
+        if position >= size {
+            return 0;
+        }
+        min(len as u64, size - position) as usize
+    }
+
+    /// Check a transfer fits the drive and its addressing
+    fn check(&self, secno: u64, nsecs: u16) -> Result<(), ::common::error::Error> {
+        let end = secno + nsecs as u64;
+        if end > self.sectors || (!self.lba48 && end > LBA28_LIMIT) {
+            return Err(err!(EINVAL));
+        }
+        Ok(())
+    }
+
+    /// Read sectors
+    fn read_sectors(
+        &self,
+        secno: u64,
+        data: &mut [u8],
+        nsecs: u16,
+    ) -> Result<(), ::common::error::Error> {
+        assert!(data.len() >= (nsecs as usize) * SECTOR_SIZE);
+        try!(self.check(secno, nsecs));
+
+        let dma = CHANNEL_LOCKS[self.channel.index].lock();
+        unsafe {
+            if self.dma {
+                try!(self.dma_transfer(&dma, secno, nsecs, false));
+                let bytes = nsecs as usize * SECTOR_SIZE;
+                copy_nonoverlapping(dma.buffer as *const u8, data.as_mut_ptr(), bytes);
+                return Ok(());
+            }
+
+            if !self.command(secno, nsecs, (ATA_CMD_READ_PIO, ATA_CMD_READ_PIO_EXT)) {
+                return Err(err!(EIO));
+            }
+            let mut ptr = data.as_mut_ptr();
+            for _i in 0..nsecs {
+                if !self.channel.wait_data() {
                     return Err(err!(EIO));
                 }
+                io::insl(
+                    self.channel.base + ATA_REG_DATA,
+                    ptr as *mut u32,
+                    (SECTOR_SIZE / 4) as u64,
+                );
+                ptr = ptr.offset(SECTOR_SIZE as isize);
+            }
+        }
+        Ok(())
+    }
 
-                for idx in offset..SECTOR_SIZE {
-                    if remaining == 0 {
-                        break;
-                    }
-                    sector[idx] = data[index];
-                    index += 1;
-                    remaining -= 1;
-                }
+    /// Write sectors
+    fn write_sectors(
+        &self,
+        secno: u64,
+        data: &[u8],
+        nsecs: u16,
+    ) -> Result<(), ::common::error::Error> {
+        assert!(data.len() >= (nsecs as usize) * SECTOR_SIZE);
+        try!(self.check(secno, nsecs));
 
-                if !unsafe { ide_write(self.diskno, secno, &sector, 1) } {
+        let dma = CHANNEL_LOCKS[self.channel.index].lock();
+        unsafe {
+            if self.dma {
+                let bytes = nsecs as usize * SECTOR_SIZE;
+                copy_nonoverlapping(data.as_ptr(), dma.buffer as *mut u8, bytes);
+                return self.dma_transfer(&dma, secno, nsecs, true);
+            }
+
+            if !self.command(secno, nsecs, (ATA_CMD_WRITE_PIO, ATA_CMD_WRITE_PIO_EXT)) {
+                return Err(err!(EIO));
+            }
+            let mut ptr = data.as_ptr();
+            for _i in 0..nsecs {
+                if !self.channel.wait_data() {
                     return Err(err!(EIO));
                 }
+                io::outsl(
+                    self.channel.base + ATA_REG_DATA,
+                    ptr as *const u32,
+                    (SECTOR_SIZE / 4) as u64,
+                );
+                ptr = ptr.offset(SECTOR_SIZE as isize);
+            }
+            if !self.channel.wait_for(idle) || !self.channel.finish() {
+                return Err(err!(EIO));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Device for IDEDevice {
+    /// Read up to the end of the drive, 0 at the end
+    fn read(&mut self, data: &mut [u8], position: u64) -> Result<usize, ::common::error::Error> {
+        let len = self.clamp(position, data.len());
+        let data = &mut data[..len];
+        let mut sector: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
+        let mut secno: u64 = position / SECTOR_SIZE as u64;
+        let mut offset: usize = (position % SECTOR_SIZE as u64) as usize;
+        let mut index: usize = 0;
+
+        while index < data.len() {
+            let remaining = data.len() - index;
+            if offset != 0 || remaining < SECTOR_SIZE {
+                try!(self.read_sectors(secno, &mut sector, 1));
+                let count = min(SECTOR_SIZE - offset, remaining);
+                data[index..index + count].copy_from_slice(&sector[offset..offset + count]);
+                index += count;
+                secno += 1;
                 offset = 0;
             } else {
-                if !unsafe { ide_write(self.diskno, secno, &data[index..], 1) } {
-                    return Err(err!(EIO));
-                }
-                index += SECTOR_SIZE;
-                remaining -= SECTOR_SIZE;
+                // Whole sectors go straight into the buffer
+                let nsecs = min(remaining / SECTOR_SIZE, MAX_SECTORS);
+                let bytes = nsecs * SECTOR_SIZE;
+                try!(self.read_sectors(secno, &mut data[index..index + bytes], nsecs as u16));
+                index += bytes;
+                secno += nsecs as u64;
+            }
+        }
+        Ok(data.len())
+    }
+
+    /// Write up to the end of the drive, 0 at the end
+    fn write(&mut self, data: &[u8], position: u64) -> Result<usize, ::common::error::Error> {
+        let data = &data[..self.clamp(position, data.len())];
+        let mut sector: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
+        let mut secno: u64 = position / SECTOR_SIZE as u64;
+        let mut offset: usize = (position % SECTOR_SIZE as u64) as usize;
+        let mut index: usize = 0;
+
+        while index < data.len() {
+            let remaining = data.len() - index;
+            if offset != 0 || remaining < SECTOR_SIZE {
+                try!(self.read_sectors(secno, &mut sector, 1));
+                let count = min(SECTOR_SIZE - offset, remaining);
+                sector[offset..offset + count].copy_from_slice(&data[index..index + count]);
+                try!(self.write_sectors(secno, &sector, 1));
+                index += count;
+                secno += 1;
+                offset = 0;
+            } else {
+                let nsecs = min(remaining / SECTOR_SIZE, MAX_SECTORS);
+                let bytes = nsecs * SECTOR_SIZE;
+                try!(self.write_sectors(secno, &data[index..index + bytes], nsecs as u16));
+                index += bytes;
+                secno += nsecs as u64;
             }
-            secno += 1;
         }
-        self.offset += data.len() as u64;
         Ok(data.len())
     }
 
     fn ioctl(&mut self, _ops: u64, _data: usize) -> Result<usize, ::common::error::Error> {
         Err(err!(EFAIL))
     }
+}
 
-    fn seek(&mut self, whence: u32, offset: u64) -> Result<u64, ::common::error::Error> {
-        match whence {
-            SEEK_SET => {
-                self.offset = offset;
-                Ok(self.offset)
-            }
-            SEEK_CUR => {
-                self.offset += offset;
-                Ok(self.offset)
-            }
-            _ => Err(err!(EFAIL)),
+/// Ports and IRQ of a channel, from the BARs in PCI native mode
+fn channel(device: &pci::Device, index: usize) -> Option<Channel> {
+    let mut channel = if device.prog_if & PROG_IF_NATIVE[index] == 0 {
+        LEGACY_CHANNELS[index]
+    } else {
+        match (device.bars[index * 2], device.bars[index * 2 + 1]) {
+            (Some(Bar::Io { port: base, .. }), Some(Bar::Io { port: control, .. })) => Channel {
+                index: index,
+                base: base,
+                // The alternate status is the third port of the block
+                control: control + 2,
+                irq: device.interrupt_line,
+                bus_master: 0,
+            },
+            _ => return None,
+        }
+    };
+    if device.prog_if & PROG_IF_BUS_MASTER != 0 {
+        if let Some(Bar::Io { port, .. }) = device.bars[4] {
+            channel.bus_master = port + index as u16 * BM_CHANNEL_SIZE;
         }
     }
+    Some(channel)
 }
 
-#[cfg(test)]
-pub unsafe fn test() {
-    use common::utility::hexdump;
-    /*
-    if !probe_disk(1) {
-        panic!("Disk 1 failed");
+/// Set up the DMA buffer and the interrupt of a channel
+/// Without an interrupt, commands are polled.
+fn start_channel(channel: &Channel) {
+    if channel.bus_master != 0 {
+        if let Err(e) = CHANNEL_LOCKS[channel.index].lock().allocate() {
+            println!("IDE channel {} runs without DMA: {:?}", channel.index, e);
+        }
     }
 
-    let mut buffer = [1u8; 512];
-    if !ide_write(1, 0, &mut buffer, 1) {
-        println!("Failed read sector");
-    } else {
-        hexdump(&buffer);
+    // Both channels share the IRQ in PCI native mode
+    let other = INTERRUPTS[1 - channel.index]
+        .lock()
+        .map_or(false, |other| other.irq == channel.irq);
+    let interrupts = channel.irq != NO_IRQ && (other || irq::register(channel.irq, interrupt));
+    *INTERRUPTS[channel.index].lock() = Some(*channel);
+    POLLED[channel.index].store(!interrupts, Ordering::Relaxed);
+    unsafe {
+        if interrupts {
+            irq::unmask(channel.irq);
+        }
+        io::outb(channel.control + ATA_REG_CONTROL, channel.device_control());
+    }
+}
+
+/// Undo start_channel()
+fn stop_channel(index: usize) {
+    let channel = match INTERRUPTS[index].lock().take() {
+        Some(channel) => channel,
+        None => return,
+    };
+    POLLED[index].store(true, Ordering::Relaxed);
+    unsafe {
+        io::outb(channel.control + ATA_REG_CONTROL, ATA_CONTROL_NIEN);
+    }
+    let shared = INTERRUPTS[1 - index]
+        .lock()
+        .map_or(false, |other| other.irq == channel.irq);
+    if channel.irq != NO_IRQ && !shared {
+        irq::mask(channel.irq);
+        irq::unregister(channel.irq);
     }
-    */
+    CHANNEL_LOCKS[index].lock().free();
+}
+
+/// Driver of IDE controllers
+struct IDEDriver;
+
+static DRIVER: IDEDriver = IDEDriver;
+
+impl Driver for IDEDriver {
+    fn name(&self) -> &'static str {
+        "ide"
+    }
+
+    fn matches(&self) -> &'static [Match] {
+        &MATCHES
+    }
+
+    /// Register the drives which answer IDENTIFY, ide0 to ide3
+    /// in the order primary master, primary slave, secondary master, secondary slave
+    fn probe(&self, device: &pci::Device) -> Result<(), ::common::error::Error> {
+        use alloc::sync::Arc;
+        let mut devlist = devices_mut();
+        if devlist.contains_key("ide0") || devlist.contains_key("ide2") {
+            // Only one controller is driven, the drive names are fixed
+            return Err(err!(EAGAIN));
+        }
+        device.enable(pci::COMMAND_IO);
+        if device.prog_if & PROG_IF_BUS_MASTER != 0 {
+            device.enable(pci::COMMAND_BUS_MASTER);
+        }
 
-    let mut device = IDEDevice::new(1);
-    let mut r1 = [0u8; 5];
-    device.read(&mut r1).unwrap();
-    hexdump(&r1);
-    /*
-    let mut r2 = [0u8; 1024];
-    device.read(&mut r2).unwrap();
-    hexdump(&r2);
-    */
-    let mut r2 = [5u8; 1030];
-    device.write(&mut r2).unwrap();
+        let mut found = 0;
+        for index in 0..2 {
+            let channel = match channel(device, index) {
+                Some(channel) => channel,
+                None => continue,
+            };
+            start_channel(&channel);
+            let before = found;
+            for drive in 0..2 {
+                let disk = match IDEDevice::new(channel, drive) {
+                    Some(disk) => disk,
+                    None => continue,
+                };
+                let name = format!("ide{}", index * 2 + drive as usize);
+                println!(
+                    "{}: {} ({}), {} sectors{}{}",
+                    name,
+                    disk.model,
+                    disk.serial,
+                    disk.sectors,
+                    if disk.lba48 { ", LBA48" } else { "" },
+                    if disk.dma { ", DMA" } else { "" }
+                );
+                devlist.insert(name, Arc::new(Mutex::new(disk)));
+                found += 1;
+            }
+            if found == before {
+                stop_channel(index);
+            }
+        }
+        if found == 0 {
+            return Err(err!(ENODEV));
+        }
+        Ok(())
+    }
+
+    fn remove(&self, _device: &pci::Device) {
+        let mut devlist = devices_mut();
+        for name in ["ide0", "ide1", "ide2", "ide3"].iter() {
+            devlist.remove(*name);
+        }
+        for index in 0..2 {
+            stop_channel(index);
+        }
+    }
 }
 
-/// Initialization
+/// Initialization, controllers are bound as the PCI scan finds them
 pub fn init() {
-    use alloc::prelude::*;
-    let mut devlist = devices_mut();
-    devlist.insert("ide0".to_string(), Box::new(IDEDevice::new(0)));
-    devlist.insert("ide1".to_string(), Box::new(IDEDevice::new(1)));
+    if let Err(e) = pci::register(&DRIVER) {
+        println!("Failed to register the IDE driver: {:?}", e);
+    }
 }
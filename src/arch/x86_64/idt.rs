@@ -1,5 +1,8 @@
+use arch::context::TrapFrame;
+use arch::percpu;
 use core::ops::Drop;
-use core::sync::atomic;
+use core::ptr::null_mut;
+use sync::{IrqSpinLock, IrqSpinLockGuard};
 
 extern "C" {
     static mut idt_entry: [IdtEntry; 256];
@@ -9,6 +12,9 @@ extern "C" {
     fn idt_init();
 }
 
+/// Vector of the system call gate
+pub const SYSCALL_VECTOR: usize = 0x80;
+
 pub const IDT_INTERRUPT_16: u8 = 0x6;
 pub const IDT_TRAP_16: u8 = 0x7;
 pub const IDT_INTERRUPT_64: u8 = 0xE;
@@ -43,12 +49,24 @@ pub unsafe fn cli() {
     asm!("cli");
 }
 
+/// Halt the processor until next interrupt
+#[inline]
+pub unsafe fn hlt() {
+    asm!("hlt");
+}
+
 /// Load IDT descriptor
 #[inline]
 unsafe fn lidt(ptr: *const IdtDescriptor) {
     asm!("lidt [rax]" : : "{rax}"(ptr) : : "intel", "volatile");
 }
 
+/// Load an empty IDT, any interrupt then triple faults
+pub unsafe fn load_null() {
+    let descriptor = IdtDescriptor { limit: 0, base: 0 };
+    lidt(&descriptor as *const IdtDescriptor);
+}
+
 /// Breakpoint
 #[inline]
 pub unsafe fn int3() {
@@ -66,33 +84,69 @@ pub unsafe fn check_int() -> bool {
     return flags & (1 << 9) != 0;
 }
 
+/// Disables interrupts until dropped,
+/// then restores the previous interrupt flag
+pub struct IrqGuard(bool);
+
+impl IrqGuard {
+    pub fn new() -> Self {
+        unsafe {
+            let enabled = check_int();
+            cli();
+            IrqGuard(enabled)
+        }
+    }
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        if self.0 {
+            unsafe {
+                sti();
+            }
+        }
+    }
+}
+
 type Handler = Option<fn(u64, u64)>;
 
 static mut INTERRUPT_HANDLERS: [Handler; 256] = [None; 256];
 
+/// Get the trap frame of the interrupt being handled
+/// Only valid inside an ISR handler, before it switches tasks.
+pub unsafe fn trap_frame() -> Option<&'static mut TrapFrame> {
+    percpu::current().trap_frame.as_mut()
+}
+
 /// interrupt handler dispatcher
 #[no_mangle]
-pub extern "C" fn int_handler(vector: u64, error_code: u64) {
-    println!("vector: {}, error_code: {}", vector, error_code);
+pub extern "C" fn int_handler(frame: *mut TrapFrame) {
     unsafe {
+        let vector = (*frame).vector;
+        let error_code = (*frame).error_code;
+        let saved = percpu::current().trap_frame;
+        percpu::current().trap_frame = frame;
         if let Some(ref handler) = INTERRUPT_HANDLERS[vector as usize] {
             handler(vector, error_code);
         }
+        percpu::current().trap_frame = saved;
+
+        // Handle pending signals before going back to user mode
+        if (*frame).user_mode() {
+            ::task::signal::deliver(&mut *frame);
+        }
     }
 }
 
-static IDT_LOCK: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+static IDT_LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
 
 /// A locked instance of IDT
-pub struct IDT(bool);
+pub struct IDT(IrqSpinLockGuard<'static, ()>);
 
 impl IDT {
     /// Get an IDT instance
     pub fn get() -> Self {
-        while !IDT_LOCK.compare_and_swap(false, true, atomic::Ordering::Relaxed) {}
-        atomic::fence(atomic::Ordering::Acquire);
-
-        IDT(true)
+        IDT(IDT_LOCK.lock())
     }
 
     pub unsafe fn set_entry(&self, idx: usize, handler: u64, selector: u16, dpl: u8, etype: u8) {
@@ -151,18 +205,12 @@ impl IDT {
     }
 }
 
-impl Drop for IDT {
-    fn drop(&mut self) {
-        IDT_LOCK.store(false, atomic::Ordering::Release);
-    }
-}
-
 /// Initialize IDT
 pub fn init() {
     let idt = IDT::get();
 
     for i in 0..256 {
-        if i == 3 {
+        if i == 3 || i == SYSCALL_VECTOR {
             unsafe {
                 idt.set_entry(
                     i,
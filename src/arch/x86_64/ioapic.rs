@@ -0,0 +1,140 @@
+//! I/O APIC
+
+use alloc::vec::Vec;
+use spin::Once;
+use sync::IrqSpinLock;
+
+use super::acpi::{self, POLARITY_ACTIVE_LOW, POLARITY_MASK, TRIGGER_LEVEL, TRIGGER_MASK};
+use super::mmu::MMU;
+
+const IOAPIC_SIZE: u64 = 0x20;
+// Register window
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+// Registers
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+struct IoApic {
+    /// Kernel address of the registers
+    base: u64,
+    gsi_base: u32,
+    count: u32,
+}
+
+impl IoApic {
+    unsafe fn read(&self, register: u32) -> u32 {
+        ((self.base + IOREGSEL) as *mut u32).write_volatile(register);
+        ((self.base + IOWIN) as *const u32).read_volatile()
+    }
+
+    unsafe fn write(&self, register: u32, value: u32) {
+        ((self.base + IOREGSEL) as *mut u32).write_volatile(register);
+        ((self.base + IOWIN) as *mut u32).write_volatile(value);
+    }
+
+    unsafe fn redirection(&self, pin: u32) -> u64 {
+        let low = self.read(IOAPIC_REDIRECTION + pin * 2) as u64;
+        let high = self.read(IOAPIC_REDIRECTION + pin * 2 + 1) as u64;
+        high << 32 | low
+    }
+
+    unsafe fn set_redirection(&self, pin: u32, entry: u64) {
+        // Masked while half written
+        self.write(IOAPIC_REDIRECTION + pin * 2, REDIRECTION_MASKED as u32);
+        self.write(IOAPIC_REDIRECTION + pin * 2 + 1, (entry >> 32) as u32);
+        self.write(IOAPIC_REDIRECTION + pin * 2, entry as u32);
+    }
+}
+
+static IOAPICS: Once<Vec<IoApic>> = Once::new();
+/// Serializes use of the register windows
+static IOAPIC_LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
+
+/// Find the I/O APIC and pin serving a global system interrupt
+fn find(gsi: u32) -> Option<(&'static IoApic, u32)> {
+    IOAPICS.try().and_then(|ioapics| {
+        ioapics
+            .iter()
+            .find(|ioapic| gsi >= ioapic.gsi_base && gsi < ioapic.gsi_base + ioapic.count)
+            .map(|ioapic| (ioapic, gsi - ioapic.gsi_base))
+    })
+}
+
+/// Route a global system interrupt to vector on a local APIC, masked
+/// flags are the polarity and trigger mode as in the MADT.
+pub fn route(gsi: u32, vector: u8, flags: u16, apic_id: u8) -> Result<(), ::common::error::Error> {
+    let (ioapic, pin) = match find(gsi) {
+        None => return Err(err!(ENOENT)),
+        Some(found) => found,
+    };
+    let mut entry = vector as u64 | REDIRECTION_MASKED | (apic_id as u64) << 56;
+    if flags & POLARITY_MASK == POLARITY_ACTIVE_LOW {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    if flags & TRIGGER_MASK == TRIGGER_LEVEL {
+        entry |= REDIRECTION_LEVEL;
+    }
+    let _lock = IOAPIC_LOCK.lock();
+    unsafe {
+        ioapic.set_redirection(pin, entry);
+    }
+    Ok(())
+}
+
+fn set_masked(gsi: u32, masked: bool) -> Result<(), ::common::error::Error> {
+    let (ioapic, pin) = match find(gsi) {
+        None => return Err(err!(ENOENT)),
+        Some(found) => found,
+    };
+    let _lock = IOAPIC_LOCK.lock();
+    unsafe {
+        let entry = ioapic.redirection(pin);
+        let entry = if masked {
+            entry | REDIRECTION_MASKED
+        } else {
+            entry & !REDIRECTION_MASKED
+        };
+        ioapic.set_redirection(pin, entry);
+    }
+    Ok(())
+}
+
+/// Mask a global system interrupt
+pub fn mask(gsi: u32) -> Result<(), ::common::error::Error> {
+    set_masked(gsi, true)
+}
+
+/// Unmask a global system interrupt
+pub fn unmask(gsi: u32) -> Result<(), ::common::error::Error> {
+    set_masked(gsi, false)
+}
+
+/// Map the I/O APICs listed in the MADT, with all pins masked
+pub fn init(madt: &acpi::Madt) -> Result<(), ::common::error::Error> {
+    let mut ioapics = Vec::new();
+    for info in madt.io_apics.iter() {
+        let base = try!(MMU::get().map_physical(info.address, IOAPIC_SIZE, true));
+        let mut ioapic = IoApic {
+            base: base.into(),
+            gsi_base: info.gsi_base,
+            count: 0,
+        };
+        unsafe {
+            ioapic.count = (ioapic.read(IOAPIC_VERSION) >> 16 & 0xff) + 1;
+            for pin in 0..ioapic.count {
+                ioapic.set_redirection(pin, REDIRECTION_MASKED);
+            }
+        }
+        ioapics.push(ioapic);
+    }
+    if ioapics.is_empty() {
+        return Err(err!(ENODEV));
+    }
+    IOAPICS.call_once(|| ioapics);
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! Interrupt requests
+//!
+//! IRQ n raises vector IRQ_VECTOR_BASE + n, whether it's routed by
+//! the legacy PICs or, once init() found them, by the APICs. ISA IRQs
+//! keep their numbers when the MADT wires them to another pin.
+
+use arch::idt::{IrqGuard, IDT};
+use arch::pic::PIC;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::acpi;
+use super::apic;
+use super::ioapic;
+
+pub const IRQ_VECTOR_BASE: usize = 32;
+/// IRQs numbered from 0, ISA IRQs first
+pub const MAX_IRQS: usize = 48;
+const ISA_IRQS: u8 = 16;
+/// Slave PIC is cascaded on this master line
+const PIC_CASCADE: u8 = 2;
+
+static USE_APIC: AtomicBool = AtomicBool::new(false);
+/// Local APIC ID IRQs are delivered to
+static DESTINATION: AtomicUsize = AtomicUsize::new(0);
+/// IRQs unmasked, to carry over when switching controllers
+static UNMASKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether interrupts are routed by the APICs
+pub fn apic_enabled() -> bool {
+    USE_APIC.load(Ordering::Relaxed)
+}
+
+/// Global system interrupt an IRQ arrives on
+fn gsi(irq: u8) -> u32 {
+    match acpi::madt() {
+        Some(madt) if irq < ISA_IRQS => madt.isa_irq(irq).0,
+        _ => irq as u32,
+    }
+}
+
+/// Register the handler of an IRQ
+pub fn register(irq: u8, handler: fn(u64, u64)) -> bool {
+    if irq as usize >= MAX_IRQS {
+        return false;
+    }
+    IDT::get().register_isr(IRQ_VECTOR_BASE + irq as usize, handler)
+}
+
+/// Unregister the handler of an IRQ
+pub fn unregister(irq: u8) -> bool {
+    if irq as usize >= MAX_IRQS {
+        return false;
+    }
+    IDT::get().unregister_isr(IRQ_VECTOR_BASE + irq as usize)
+}
+
+/// Let an IRQ through
+pub fn unmask(irq: u8) {
+    if irq as usize >= MAX_IRQS {
+        return;
+    }
+    UNMASKED.fetch_or(1 << irq, Ordering::Relaxed);
+    if apic_enabled() {
+        if ioapic::unmask(gsi(irq)).is_err() {
+            println!("IRQ {} isn't wired to an I/O APIC", irq);
+        }
+    } else if irq < ISA_IRQS {
+        let pic = PIC::get();
+        unsafe {
+            if irq >= 8 {
+                pic.unmask(irq - 8, true);
+                pic.unmask(PIC_CASCADE, false);
+            } else {
+                pic.unmask(irq, false);
+            }
+        }
+    }
+}
+
+/// Hold an IRQ back
+pub fn mask(irq: u8) {
+    if irq as usize >= MAX_IRQS {
+        return;
+    }
+    UNMASKED.fetch_and(!(1 << irq), Ordering::Relaxed);
+    if apic_enabled() {
+        let _ = ioapic::mask(gsi(irq));
+    } else if irq < ISA_IRQS {
+        let pic = PIC::get();
+        unsafe {
+            if irq >= 8 {
+                pic.mask(irq - 8, true);
+            } else {
+                pic.mask(irq, false);
+            }
+        }
+    }
+}
+
+/// Change how an IRQ is signaled, with MADT polarity and trigger flags
+/// Only IRQs routed by the APICs can be changed, the IRQ is left masked.
+pub fn set_mode(irq: u8, flags: u16) -> Result<(), ::common::error::Error> {
+    if irq as usize >= MAX_IRQS {
+        return Err(err!(EINVAL));
+    }
+    if !apic_enabled() {
+        return Err(err!(ENODEV));
+    }
+    UNMASKED.fetch_and(!(1 << irq), Ordering::Relaxed);
+    ioapic::route(
+        gsi(irq),
+        (IRQ_VECTOR_BASE + irq as usize) as u8,
+        flags,
+        DESTINATION.load(Ordering::Relaxed) as u8,
+    )
+}
+
+/// Signal the end of an IRQ, called by its handler
+pub fn eoi(irq: u8) {
+    if apic_enabled() {
+        apic::eoi();
+    } else {
+        unsafe {
+            PIC::eoi(irq >= 8);
+        }
+    }
+}
+
+/// Switch to the APICs where the MADT describes them
+/// Needs the heap, IRQs unmasked before stay unmasked.
+pub fn init() {
+    let madt = match acpi::madt() {
+        Some(madt) if apic::present() => madt,
+        _ => {
+            println!("No APIC found, using the 8259 PIC");
+            return;
+        }
+    };
+    let nmi: ::alloc::vec::Vec<(u8, u16)> = madt
+        .local_nmis
+        .iter()
+        .map(|nmi| (nmi.lint, nmi.flags))
+        .collect();
+    if let Err(e) = ioapic::init(madt) {
+        println!("Failed to set up the I/O APIC: {:?}", e);
+        return;
+    }
+
+    let guard = IrqGuard::new();
+    if madt.flags & acpi::MADT_PCAT_COMPAT != 0 {
+        unsafe {
+            PIC::get().disable();
+        }
+    }
+    if let Err(e) = apic::init(madt.local_apic_address, &nmi) {
+        panic!("Failed to enable the local APIC: {:?}", e);
+    }
+    let apic_id = apic::id() as u8;
+    DESTINATION.store(apic_id as usize, Ordering::Relaxed);
+    for irq in 0..MAX_IRQS as u8 {
+        // An ISA IRQ wired elsewhere owns the pin
+        let gsi = gsi(irq);
+        if madt.overrides.iter().any(|o| o.irq != irq && o.gsi == gsi) {
+            continue;
+        }
+        // Other than ISA ones, interrupts are PCI style
+        let flags = if irq < ISA_IRQS {
+            madt.isa_irq(irq).1
+        } else {
+            acpi::POLARITY_ACTIVE_LOW | acpi::TRIGGER_LEVEL
+        };
+        // Pins the I/O APICs don't have stay unused
+        let _ = ioapic::route(gsi, (IRQ_VECTOR_BASE + irq as usize) as u8, flags, apic_id);
+    }
+    USE_APIC.store(true, Ordering::Relaxed);
+
+    let unmasked = UNMASKED.load(Ordering::Relaxed);
+    for irq in 0..MAX_IRQS as u8 {
+        if unmasked & 1 << irq != 0 {
+            unmask(irq);
+        }
+    }
+    drop(guard);
+    println!("Interrupts routed through the APIC, {} CPUs", madt.local_apics.len());
+}
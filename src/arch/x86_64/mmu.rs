@@ -6,21 +6,31 @@
 //! Virtual memory layout looks like:
 //! [1] [0xFFFFFFFF80000000, 0xFFFFFFFF80600000)
 //! [2] [0xFFFFFFFF80600000, 0xFFFFFFFF80800000)
+//! [3] [0xFFFFFFFFA0000000, 0xFFFFFFFFC0000000): Physical mappings, in 2Mb pages
 //!
 //! We don't really need to modify PDPT entries
 //! after initialization, so we just create the instance
 //! of several page tables and two page directories
 
+use arch::idt::IDT;
+use arch::percpu;
 use core::convert::{From, Into};
-use core::ops::Drop;
-use core::sync::atomic;
+use core::sync::atomic::Ordering;
 use rlibc::memset;
+use sync::{IrqSpinLock, IrqSpinLockGuard};
 
 const PAGETABLE_PHYS: u64 = 0x600000;
 const PAGETABLE_VIRT: u64 = 0xFFFFFFFF80600000;
 const PAGE_SHIFT: u32 = 12;
 const INITIAL_MAPPED: u64 = 3072;
 const MAX_MAPPED: u64 = 16384; // 64Mb
+/// Kernel page directory entries used by map_physical()
+const PHYSMAP_FIRST: usize = 256;
+const PHYSMAP_VIRT: u64 = 0xFFFFFFFFA0000000;
+const LARGE_PAGE_SIZE: u64 = 0x200000;
+/// Page-level write-through and cache disable
+const PTE_PWT: u64 = 1 << 3;
+const PTE_PCD: u64 = 1 << 4;
 
 extern "C" {
     static mut pml4: [u64; 512];
@@ -106,6 +116,15 @@ impl PageTable {
     pub fn get(&self, idx: usize) -> u64 {
         self.v[idx]
     }
+
+    /// Set flag bits of a present entry
+    pub fn set_bits(&mut self, idx: usize, bits: u64) -> bool {
+        if self.v[idx] & 1 == 0 {
+            return false;
+        }
+        self.v[idx] |= bits;
+        true
+    }
 }
 
 /// Virtual address
@@ -254,6 +273,8 @@ pub const HEAP_SIZE: u64 = 0x100000;
 
 /// Physical page usage
 static mut PHYSPAGE_BITMAP: [u64; 1024] = [0; 1024];
+/// References to each allocated physical page
+static mut PHYSPAGE_REFS: [u16; MAX_MAPPED as usize] = [0; MAX_MAPPED as usize];
 /// Page table usage
 static mut PAGETABLE_INUSE: [bool; 1024] = [false; 1024];
 
@@ -282,6 +303,20 @@ fn page_marked(frame: u64) -> bool {
     unsafe { (PHYSPAGE_BITMAP[idx] & 1 << bitoff) != 0 }
 }
 
+/// Get cr0
+#[inline]
+pub unsafe fn cr0() -> u64 {
+    let result: u64;
+    asm!("mov %cr0, $0" : "=r"(result) : : );
+    result
+}
+
+/// Set cr0
+#[inline]
+pub unsafe fn set_cr0(value: u64) {
+    asm!("mov $0, %cr0" : : "r"(value) : "memory" : "volatile");
+}
+
 /// Get cr3
 #[inline]
 pub unsafe fn cr3() -> u64 {
@@ -290,26 +325,31 @@ pub unsafe fn cr3() -> u64 {
     result
 }
 
+/// Get cr2, the faulting address
+#[inline]
+pub unsafe fn cr2() -> u64 {
+    let result: u64;
+    asm!("mov %cr2, $0" : "=r"(result) : : );
+    result
+}
+
 /// Set cr3
+/// It's published first for TLB shootdowns, so the per-CPU data must be set up.
 #[inline]
 pub unsafe fn set_cr3(cr3: u64) {
-    asm!("mov $0, %cr3" : : "r"(cr3) : : );
+    percpu::current().cr3.store(cr3 as usize, Ordering::SeqCst);
+    asm!("mov $0, %cr3" : : "r"(cr3) : "memory" : "volatile");
 }
 
-static MMU_LOCK: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+static MMU_LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
 
 /// An instance of MMU
-pub struct MMU(bool);
+pub struct MMU(IrqSpinLockGuard<'static, ()>);
 
 impl MMU {
     /// Get an instance of MMU
     pub fn get() -> Self {
-        while !MMU_LOCK.compare_and_swap(false, true, atomic::Ordering::Relaxed) {
-            // Do nothing
-        }
-        atomic::fence(atomic::Ordering::Acquire);
-
-        MMU(true)
+        MMU(MMU_LOCK.lock())
     }
 
     /// Virtual address to physical address
@@ -332,22 +372,55 @@ impl MMU {
         for frame in INITIAL_MAPPED..MAX_MAPPED {
             if !page_marked(frame) {
                 mark_page(frame);
+                unsafe {
+                    PHYSPAGE_REFS[frame as usize] = 1;
+                }
                 return Ok(PhysicalAddress::from_pfn(frame));
             }
         }
         Err(err!(ENOMEM))
     }
 
-    /// Free one physical page
+    /// Take another reference to an allocated physical page
+    pub fn ref_phys(&self, addr: PhysicalAddress) -> Result<(), ::common::error::Error> {
+        let frame = addr.pfn();
+        if frame >= MAX_MAPPED || !page_marked(frame) {
+            return Err(err!(EFAULT));
+        }
+        unsafe {
+            match PHYSPAGE_REFS[frame as usize].checked_add(1) {
+                None => Err(err!(EFULL)),
+                Some(refs) => {
+                    PHYSPAGE_REFS[frame as usize] = refs;
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Drop one reference to a physical page,
+    /// the page is freed with its last reference
     pub fn free_phys(&self, addr: PhysicalAddress) -> Result<(), ::common::error::Error> {
         let frame = addr.pfn();
-        match page_marked(frame) {
-            false => Err(err!(EFAULT)),
-            true => {
+        if frame >= MAX_MAPPED || !page_marked(frame) {
+            return Err(err!(EFAULT));
+        }
+        unsafe {
+            PHYSPAGE_REFS[frame as usize] -= 1;
+            if PHYSPAGE_REFS[frame as usize] == 0 {
                 clear_page(frame);
-                Ok(())
             }
         }
+        Ok(())
+    }
+
+    /// Get the number of references to a physical page
+    pub fn phys_refs(&self, addr: PhysicalAddress) -> u16 {
+        let frame = addr.pfn();
+        if frame >= MAX_MAPPED {
+            return 0;
+        }
+        unsafe { PHYSPAGE_REFS[frame as usize] }
     }
 
     /// Allocate one page
@@ -419,6 +492,57 @@ impl MMU {
         Ok(())
     }
 
+    /// Map physical memory outside the kernel image, such as device
+    /// registers and firmware tables, into kernel space
+    /// Mappings are never removed, mapping a range again reuses them.
+    pub fn map_physical(
+        &self,
+        address: u64,
+        size: u64,
+        uncached: bool,
+    ) -> Result<VirtualAddress, ::common::error::Error> {
+        if size == 0 || address.checked_add(size).is_none() {
+            return Err(err!(EINVAL));
+        }
+        let first = address & !(LARGE_PAGE_SIZE - 1);
+        let count = ((address + size - first + LARGE_PAGE_SIZE - 1) / LARGE_PAGE_SIZE) as usize;
+        if count > 512 - PHYSMAP_FIRST {
+            return Err(err!(ENOMEM));
+        }
+        let flags = 0x80 | 0x3 | if uncached { PTE_PCD | PTE_PWT } else { 0 };
+
+        unsafe {
+            // Look for the same pages mapped already, or a free run
+            let mut found = None;
+            for i in PHYSMAP_FIRST..512 - count + 1 {
+                let mapped = (0..count).all(|j| {
+                    kernel_pd.get(i + j) == (first + j as u64 * LARGE_PAGE_SIZE) | flags
+                });
+                if mapped {
+                    found = Some(i);
+                    break;
+                }
+                if found.is_none() && (0..count).all(|j| !kernel_pd.present(i + j)) {
+                    found = Some(i);
+                }
+            }
+            let index = match found {
+                None => return Err(err!(ENOMEM)),
+                Some(index) => index,
+            };
+            for j in 0..count {
+                kernel_pd.v[index + j] = (first + j as u64 * LARGE_PAGE_SIZE) | flags;
+            }
+            let base = PHYSMAP_VIRT + (index - PHYSMAP_FIRST) as u64 * LARGE_PAGE_SIZE;
+            Ok(VirtualAddress(base + address - first))
+        }
+    }
+
+    /// Return the PML4 shared by kernel threads
+    pub fn kernel_pml4(&self) -> VirtualAddress {
+        unsafe { VirtualAddress((&pml4 as *const [u64; 512]) as u64 + KERNEL_BASE) }
+    }
+
     /// Return current PML4 Virtual address
     pub fn pml4(&self) -> *mut PageTable {
         unsafe {
@@ -438,13 +562,29 @@ impl MMU {
     }
 }
 
-impl Drop for MMU {
-    fn drop(&mut self) {
-        MMU_LOCK.store(false, atomic::Ordering::Release);
+/// Page fault handler
+fn page_fault(_vector: u64, error_code: u64) {
+    let address = unsafe { cr2() };
+    ::task::account_page_fault();
+    println!(
+        "Page fault at {:#x}, error code {:#x}, task {}",
+        address,
+        error_code,
+        ::task::current_tid()
+    );
+
+    // Nothing is paged in on demand yet
+    let user = unsafe { ::arch::idt::trap_frame() }.map_or(false, |frame| frame.user_mode());
+    if user {
+        ::task::signal::force(::task::signal::SIGSEGV);
+    } else {
+        panic!("Kernel page fault at {:#x}", address);
     }
 }
 
 pub fn init() {
+    assert!(IDT::get().register_isr(14, page_fault));
+
     // These pages are mapped initially
     for i in 0..INITIAL_MAPPED {
         mark_page(i);
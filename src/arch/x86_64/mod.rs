@@ -1,25 +1,64 @@
+mod acpi;
+mod apic;
 mod context;
+mod exception;
+mod fpu;
 mod gdt;
+mod hpet;
 mod ide;
 mod idt;
 mod io;
+mod ioapic;
+mod irq;
 mod mmu;
+mod msr;
+mod multiboot;
 mod pci;
+mod percpu;
 mod pic;
+mod power;
+mod rtc;
+mod smp;
 mod timer;
+mod tlb;
+mod tsc;
 mod tss;
 
 /* exposed child definitions */
-pub use self::context::Context;
+pub use self::context::{Context, TrapFrame, USER_LIMIT};
+pub use self::hpet::{
+    clock_ns as hpet_clock_ns, counter as hpet_counter, period_fs as hpet_period_fs,
+    present as hpet_present,
+};
+pub use self::idt::{IrqGuard, SYSCALL_VECTOR};
+pub use self::irq::{
+    eoi as eoi_irq, mask as mask_irq, register as register_irq, unmask as unmask_irq,
+    unregister as unregister_irq,
+};
+pub use self::percpu::{count as cpu_count, cpu_id, MAX_CPUS};
+pub use self::power::{power_off, reboot};
+pub use self::rtc::{
+    disable_periodic as disable_rtc_periodic, enable_periodic as enable_rtc_periodic,
+    periodic_ticks as rtc_ticks, read as read_rtc,
+};
+pub use self::smp::send_reschedule;
+pub use self::timer::{
+    clock_ns, hz as tick_hz, set_hz as set_tick_hz, set_source as set_tick_source,
+    source as tick_source, tick_ns, ticks, TickSource,
+};
+pub use self::tsc::frequency as tsc_frequency;
 
 use self::idt::IDT;
+use self::mmu::{PhysicalAddress, MMU};
 use self::timer::Timer;
+use alloc::vec::Vec;
+use rlibc::memset;
+use task::Scheduler;
 
 pub const HEAP_VIRT: u64 = mmu::HEAP_VIRT;
 pub const HEAP_SIZE: u64 = mmu::HEAP_SIZE;
 
 /* exported symbols */
-pub use self::context::store_context;
 pub use self::idt::int_handler;
 
 /// Put a string of bytes to serial port
@@ -47,6 +86,30 @@ pub unsafe fn getb() -> u8 {
     io::inb(0x3F8)
 }
 
+/// Get a byte from serial port if one was received
+pub unsafe fn try_getb() -> Option<u8> {
+    if !serial_received() {
+        return None;
+    }
+    Some(io::inb(0x3F8))
+}
+
+/// Check if the serial port holds a received byte
+pub unsafe fn serial_received() -> bool {
+    (io::inb(0x3F8 + 5) & 0x1) != 0
+}
+
+/// IRQ of the serial port
+pub const SERIAL_IRQ: u8 = 4;
+
+/// Raise SERIAL_IRQ while the serial port holds received bytes
+pub unsafe fn enable_serial_irq() {
+    // Received data available interrupt
+    io::outb(0x3F8 + 1, 0x1);
+    // OUT2 gates the interrupt line
+    io::outb(0x3F8 + 4, io::inb(0x3F8 + 4) | 0x8);
+}
+
 /// Disable interrupt
 pub unsafe fn disable_int() {
     idt::cli();
@@ -62,6 +125,55 @@ pub unsafe fn int_enabled() -> bool {
     idt::check_int()
 }
 
+/// Halt until next interrupt
+pub unsafe fn halt() {
+    idt::hlt();
+}
+
+/// Get the trap frame of the interrupt being handled
+pub unsafe fn trap_frame() -> Option<&'static mut TrapFrame> {
+    idt::trap_frame()
+}
+
+/// Switch from one context to another
+pub unsafe fn switch_context(prev: *mut Context, next: *const Context) {
+    context::switch(prev, next);
+}
+
+/// Allocate physical pages, returns their addresses
+/// The pages are cleared, so they can be handed to any task.
+pub fn alloc_frames(count: usize) -> Result<Vec<u64>, ::common::error::Error> {
+    let mmu = MMU::get();
+    let mut frames: Vec<u64> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let cleared = mmu.alloc_phys().and_then(|paddr| {
+            frames.push(paddr.into());
+            mmu.map_physical(paddr.into(), mmu::PAGE_SIZE, false)
+        });
+        match cleared {
+            Ok(page) => unsafe {
+                memset(Into::<u64>::into(page) as *mut u8, 0, mmu::PAGE_SIZE as usize);
+            },
+            Err(e) => {
+                for frame in frames.iter() {
+                    let _ = mmu.free_phys(PhysicalAddress::new(*frame));
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(frames)
+}
+
+/// Drop references to physical pages from alloc_frames()
+pub fn free_frames(frames: &[u64]) {
+    let mmu = MMU::get();
+    for frame in frames.iter() {
+        mmu.free_phys(PhysicalAddress::new(*frame))
+            .expect("Invalid physical page");
+    }
+}
+
 /// Breakpoint
 pub unsafe fn breakpoint() {
     idt::int3();
@@ -88,29 +200,39 @@ pub fn unregister_timer(idx: usize) -> Result<(), ::common::error::Error> {
 }
 
 /// Register a scheduler
-pub fn register_scheduler(func: fn(u64)) -> Result<(), ::common::error::Error> {
-    Timer::get().register_scheduler(func)
+pub fn register_scheduler(sched: &'static Scheduler) -> Result<(), ::common::error::Error> {
+    Timer::get().register_scheduler(sched)
+}
+
+/// Get the registered scheduler
+pub fn scheduler() -> Option<&'static Scheduler> {
+    Timer::scheduler()
 }
 
 /// Initialize architecture-related configuration
-pub fn init() {
-    gdt::init();
-    tss::init();
+/// boot_info is the physical address of the multiboot information.
+pub fn init(boot_info: u64) {
+    multiboot::init(boot_info);
+    percpu::init();
     idt::init();
+    exception::init();
+    fpu::init();
     pic::init();
     mmu::init();
     timer::init();
-    pci::init();
 }
 
 /// Phase 2 initialization
 pub fn init2() {
+    acpi::init();
+    irq::init();
+    hpet::init();
     ide::init();
+    pci::init();
 }
 
-#[cfg(test)]
-pub fn test() {
-    unsafe {
-        ide::test();
-    }
+/// Start the other processors, each entering the scheduler
+/// Call once tasks are initialized.
+pub fn start_cpus() {
+    smp::init();
 }
@@ -0,0 +1,21 @@
+//! Model specific registers
+
+pub const IA32_APIC_BASE: u32 = 0x1b;
+pub const IA32_TSC_DEADLINE: u32 = 0x6e0;
+pub const IA32_GS_BASE: u32 = 0xc0000101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xc0000102;
+
+/// Read a model specific register
+#[inline]
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    asm!("rdmsr" : "={eax}"(low), "={edx}"(high) : "{ecx}"(msr) : : "volatile");
+    (high as u64) << 32 | low as u64
+}
+
+/// Write a model specific register
+#[inline]
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    asm!("wrmsr" : : "{ecx}"(msr), "{eax}"(value as u32), "{edx}"((value >> 32) as u32) : : "volatile");
+}
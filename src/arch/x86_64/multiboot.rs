@@ -0,0 +1,62 @@
+//! Multiboot2 boot information
+//!
+//! The boot loader leaves a list of tags in low memory, which
+//! stays mapped at KERNEL_BASE and is never handed out as frames.
+//! Only the copies of the ACPI RSDP are used.
+
+use core::ptr;
+
+use super::acpi;
+use super::mmu::KERNEL_BASE;
+
+/// Physical memory mapped at KERNEL_BASE from boot
+const BOOT_MAPPED: u64 = 0x600000;
+
+const TAG_END: u32 = 0;
+/// Copy of the ACPI 1.0 RSDP
+const TAG_OLD_RSDP: u32 = 14;
+/// Copy of the ACPI 2.0 RSDP
+const TAG_NEW_RSDP: u32 = 15;
+
+/// Type and size in front of each tag
+const TAG_HEADER_SIZE: u64 = 8;
+
+/// Read a u32 at a physical address below BOOT_MAPPED
+unsafe fn read(address: u64) -> u32 {
+    ptr::read((address + KERNEL_BASE) as *const u32)
+}
+
+/// Walk the boot information at a physical address
+pub fn init(info: u64) {
+    if info == 0 || info + TAG_HEADER_SIZE > BOOT_MAPPED {
+        return;
+    }
+    let total = unsafe { read(info) } as u64;
+    if info + total > BOOT_MAPPED {
+        println!("Boot information at {:#x} out of reach", info);
+        return;
+    }
+
+    let mut old_rsdp = None;
+    let mut new_rsdp = None;
+    let mut offset = TAG_HEADER_SIZE;
+    while offset + TAG_HEADER_SIZE <= total {
+        let tag = info + offset;
+        let (kind, size) = unsafe { (read(tag), read(tag + 4) as u64) };
+        if kind == TAG_END || size < TAG_HEADER_SIZE {
+            break;
+        }
+        match kind {
+            TAG_OLD_RSDP => old_rsdp = Some(tag + TAG_HEADER_SIZE),
+            TAG_NEW_RSDP => new_rsdp = Some(tag + TAG_HEADER_SIZE),
+            _ => {}
+        };
+        // Tags are 8 bytes aligned
+        offset += (size + 7) & !7;
+    }
+
+    // The 2.0 RSDP leads to the XSDT
+    if let Some(rsdp) = new_rsdp.or(old_rsdp) {
+        acpi::set_rsdp(rsdp);
+    }
+}
@@ -0,0 +1,389 @@
+//! A PCI function and its configuration header
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::ecam;
+use super::{pci_readcfg, pci_writecfg};
+
+// Configuration header, common part
+pub const PCI_VENDOR_ID: u32 = 0x00;
+pub const PCI_COMMAND: u32 = 0x04;
+pub const PCI_CLASS_REVISION: u32 = 0x08;
+pub const PCI_HEADER_TYPE: u32 = 0x0e;
+pub const PCI_BAR0: u32 = 0x10;
+pub const PCI_CAPABILITIES: u32 = 0x34;
+pub const PCI_INTERRUPT_LINE: u32 = 0x3c;
+/// Extended capabilities start past the legacy header
+pub const PCI_EXTENDED_CAPABILITIES: u32 = 0x100;
+// Bridge header
+pub const PCI_SECONDARY_BUS: u32 = 0x19;
+
+pub const COMMAND_IO: u16 = 1 << 0;
+pub const COMMAND_MEMORY: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+pub const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+/// In the status register, the capability list is valid
+const STATUS_CAPABILITIES: u32 = 1 << 20;
+
+pub const HEADER_DEVICE: u8 = 0x00;
+pub const HEADER_BRIDGE: u8 = 0x01;
+pub const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+/// Class and subclass of PCI-to-PCI bridges
+pub const CLASS_BRIDGE: u8 = 0x06;
+pub const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_TYPE_MASK: u32 = 0x6;
+const BAR_TYPE_64: u32 = 0x4;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// Capability lists are walked no further than this
+const MAX_CAPABILITIES: usize = 48;
+const MAX_EXTENDED_CAPABILITIES: usize = 960;
+/// Capability of PCI Express functions
+const CAP_EXPRESS: u8 = 0x10;
+
+/// Location of a function on the bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Address {
+    pub fn new(bus: u8, device: u8, function: u8) -> Self {
+        Address {
+            bus: bus,
+            device: device,
+            function: function,
+        }
+    }
+
+    /// Read a dword of the configuration space
+    pub fn read(&self, offset: u32) -> u32 {
+        unsafe {
+            pci_readcfg(
+                self.bus as u32,
+                self.device as u32,
+                self.function as u32,
+                offset,
+            )
+        }
+    }
+
+    /// Write a dword of the configuration space
+    pub fn write(&self, offset: u32, value: u32) {
+        unsafe {
+            pci_writecfg(
+                self.bus as u32,
+                self.device as u32,
+                self.function as u32,
+                offset,
+                value,
+            )
+        }
+    }
+
+    pub fn read_u16(&self, offset: u32) -> u16 {
+        (self.read(offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn read_u8(&self, offset: u32) -> u8 {
+        (self.read(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    pub fn write_u16(&self, offset: u32, value: u16) {
+        let shift = (offset & 2) * 8;
+        let old = self.read(offset & !3);
+        self.write(
+            offset & !3,
+            old & !(0xffff << shift) | (value as u32) << shift,
+        );
+    }
+
+    pub fn write_u8(&self, offset: u32, value: u8) {
+        let shift = (offset & 3) * 8;
+        let old = self.read(offset & !3);
+        self.write(
+            offset & !3,
+            old & !(0xff << shift) | (value as u32) << shift,
+        );
+    }
+
+    /// Whether a function answers at this address
+    pub fn present(&self) -> bool {
+        self.read(PCI_VENDOR_ID) & 0xffff != 0xffff
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register
+#[derive(Clone, Copy, Debug)]
+pub enum Bar {
+    Io {
+        port: u16,
+        size: u32,
+    },
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Takes two registers, the second one has no Bar
+        wide: bool,
+    },
+}
+
+impl Bar {
+    /// Address of the window, a port for I/O
+    pub fn address(&self) -> u64 {
+        match *self {
+            Bar::Io { port, .. } => port as u64,
+            Bar::Memory { address, .. } => address,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Io { size, .. } => size as u64,
+            Bar::Memory { size, .. } => size,
+        }
+    }
+
+    pub fn is_io(&self) -> bool {
+        match *self {
+            Bar::Io { .. } => true,
+            Bar::Memory { .. } => false,
+        }
+    }
+}
+
+/// A capability in the configuration space
+#[derive(Clone, Copy, Debug)]
+pub struct Capability {
+    pub id: u8,
+    /// Offset of the capability header
+    pub offset: u8,
+}
+
+/// A PCI Express capability in the extended configuration space
+#[derive(Clone, Copy, Debug)]
+pub struct ExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+/// A PCI function found by the scan
+#[derive(Clone, Debug)]
+pub struct Device {
+    pub address: Address,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    /// Layout of the header, without the multi-function bit
+    pub header_type: u8,
+    pub interrupt_line: u8,
+    /// INTA# to INTD# as 1 to 4, 0 for none
+    pub interrupt_pin: u8,
+    pub bars: [Option<Bar>; 6],
+    pub capabilities: Vec<Capability>,
+    /// Empty without ECAM or for conventional PCI functions
+    pub extended_capabilities: Vec<ExtendedCapability>,
+}
+
+impl Device {
+    /// Read the header of a function, which must be present
+    pub fn probe(address: Address) -> Self {
+        let id = address.read(PCI_VENDOR_ID);
+        let class = address.read(PCI_CLASS_REVISION);
+        let interrupt = address.read(PCI_INTERRUPT_LINE);
+        let mut device = Device {
+            address: address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: address.read_u8(PCI_HEADER_TYPE) & !HEADER_MULTIFUNCTION,
+            interrupt_line: interrupt as u8,
+            interrupt_pin: (interrupt >> 8) as u8,
+            bars: [None; 6],
+            capabilities: Vec::new(),
+            extended_capabilities: Vec::new(),
+        };
+        device.read_bars();
+        device.read_capabilities();
+        device.read_extended_capabilities();
+        device
+    }
+
+    /// Whether the function is a PCI-to-PCI bridge
+    pub fn is_bridge(&self) -> bool {
+        self.header_type == HEADER_BRIDGE
+            && self.class == CLASS_BRIDGE
+            && self.subclass == SUBCLASS_PCI_BRIDGE
+    }
+
+    /// Bus behind a bridge
+    pub fn secondary_bus(&self) -> Option<u8> {
+        if self.header_type != HEADER_BRIDGE {
+            return None;
+        }
+        Some(self.address.read_u8(PCI_SECONDARY_BUS))
+    }
+
+    /// Find a capability by its ID
+    pub fn capability(&self, id: u8) -> Option<u8> {
+        self.capabilities
+            .iter()
+            .find(|cap| cap.id == id)
+            .map(|cap| cap.offset)
+    }
+
+    /// Find an extended capability by its ID
+    pub fn extended_capability(&self, id: u16) -> Option<u16> {
+        self.extended_capabilities
+            .iter()
+            .find(|cap| cap.id == id)
+            .map(|cap| cap.offset)
+    }
+
+    /// Set bits of the command register
+    pub fn enable(&self, bits: u16) {
+        let command = self.address.read_u16(PCI_COMMAND);
+        self.address.write_u16(PCI_COMMAND, command | bits);
+    }
+
+    /// Clear bits of the command register
+    pub fn disable(&self, bits: u16) {
+        let command = self.address.read_u16(PCI_COMMAND);
+        self.address.write_u16(PCI_COMMAND, command & !bits);
+    }
+
+    /// Decode and size the BARs
+    /// Decoding is turned off while sizing so the probe values
+    /// can't claim addresses of other devices.
+    fn read_bars(&mut self) {
+        let count = match self.header_type {
+            HEADER_DEVICE => 6,
+            HEADER_BRIDGE => 2,
+            _ => 0,
+        };
+        let address = self.address;
+        let command = address.read_u16(PCI_COMMAND);
+        address.write_u16(PCI_COMMAND, command & !(COMMAND_IO | COMMAND_MEMORY));
+
+        let mut index = 0;
+        while index < count {
+            let offset = PCI_BAR0 + index as u32 * 4;
+            let value = address.read(offset);
+            address.write(offset, !0);
+            let mask = address.read(offset);
+            address.write(offset, value);
+
+            if value & BAR_IO != 0 {
+                let mask = mask & !0x3 & 0xffff;
+                if mask != 0 {
+                    self.bars[index] = Some(Bar::Io {
+                        port: (value & !0x3) as u16,
+                        size: (!mask & 0xffff) + 1,
+                    });
+                }
+                index += 1;
+                continue;
+            }
+
+            let wide = value & BAR_TYPE_MASK == BAR_TYPE_64 && index + 1 < count;
+            let (value, mask) = if wide {
+                let high = address.read(offset + 4);
+                address.write(offset + 4, !0);
+                let high_mask = address.read(offset + 4);
+                address.write(offset + 4, high);
+                (
+                    (high as u64) << 32 | value as u64,
+                    (high_mask as u64) << 32 | mask as u64,
+                )
+            } else {
+                (value as u64, mask as u64 | 0xffffffff_00000000)
+            };
+            let mask = mask & !0xf;
+            if mask != 0xffffffff_00000000 && mask != 0 {
+                self.bars[index] = Some(Bar::Memory {
+                    address: value & !0xf,
+                    size: !mask + 1,
+                    prefetchable: value & BAR_PREFETCHABLE as u64 != 0,
+                    wide: wide,
+                });
+            }
+            index += if wide { 2 } else { 1 };
+        }
+
+        address.write_u16(PCI_COMMAND, command);
+    }
+
+    /// Walk the capability list
+    fn read_capabilities(&mut self) {
+        if self.address.read(PCI_COMMAND) & STATUS_CAPABILITIES == 0 {
+            return;
+        }
+        let mut offset = self.address.read_u8(PCI_CAPABILITIES) & 0xfc;
+        while offset != 0 && self.capabilities.len() < MAX_CAPABILITIES {
+            let header = self.address.read(offset as u32);
+            self.capabilities.push(Capability {
+                id: header as u8,
+                offset: offset,
+            });
+            offset = (header >> 8) as u8 & 0xfc;
+        }
+    }
+
+    /// Walk the extended capability list of PCI Express functions
+    fn read_extended_capabilities(&mut self) {
+        if !ecam::enabled() || self.capability(CAP_EXPRESS).is_none() {
+            return;
+        }
+        let mut offset = PCI_EXTENDED_CAPABILITIES;
+        while offset >= PCI_EXTENDED_CAPABILITIES
+            && self.extended_capabilities.len() < MAX_EXTENDED_CAPABILITIES
+        {
+            let header = self.address.read(offset);
+            if header == 0 || header == !0 {
+                break;
+            }
+            self.extended_capabilities.push(ExtendedCapability {
+                id: header as u16,
+                version: (header >> 16 & 0xf) as u8,
+                offset: offset as u16,
+            });
+            offset = header >> 20 & 0xffc;
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x} rev {:02x}",
+            self.address,
+            self.vendor_id,
+            self.device_id,
+            self.class,
+            self.subclass,
+            self.prog_if,
+            self.revision
+        )
+    }
+}
@@ -0,0 +1,172 @@
+//! PCI drivers
+//!
+//! A driver lists the functions it handles in a match table. Functions
+//! found by the scan are offered to the registered drivers in order, the
+//! first whose probe succeeds is bound to the function.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::{Once, RwLock};
+
+use super::device::{Address, Device};
+use super::devices;
+
+/// Matches functions by ID or class, None matches anything
+#[derive(Clone, Copy, Debug)]
+pub struct Match {
+    pub vendor_id: Option<u16>,
+    pub device_id: Option<u16>,
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub prog_if: Option<u8>,
+}
+
+impl Match {
+    /// Match a vendor and device ID
+    pub const fn id(vendor_id: u16, device_id: u16) -> Self {
+        Match {
+            vendor_id: Some(vendor_id),
+            device_id: Some(device_id),
+            class: None,
+            subclass: None,
+            prog_if: None,
+        }
+    }
+
+    /// Match a class and subclass
+    pub const fn class(class: u8, subclass: u8) -> Self {
+        Match {
+            vendor_id: None,
+            device_id: None,
+            class: Some(class),
+            subclass: Some(subclass),
+            prog_if: None,
+        }
+    }
+
+    pub fn matches(&self, device: &Device) -> bool {
+        self.vendor_id.map_or(true, |id| id == device.vendor_id)
+            && self.device_id.map_or(true, |id| id == device.device_id)
+            && self.class.map_or(true, |class| class == device.class)
+            && self.subclass.map_or(true, |subclass| subclass == device.subclass)
+            && self.prog_if.map_or(true, |prog_if| prog_if == device.prog_if)
+    }
+}
+
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+    /// Functions the driver may handle
+    fn matches(&self) -> &'static [Match];
+    /// Take over a function, an error leaves it to other drivers
+    fn probe(&self, device: &Device) -> Result<(), ::common::error::Error>;
+    /// Let go of a function bound by probe()
+    fn remove(&self, device: &Device);
+}
+
+struct Registry {
+    drivers: Vec<&'static Driver>,
+    bound: BTreeMap<Address, &'static Driver>,
+}
+
+/// Held across probe() and remove(), which must not register drivers
+static REGISTRY: Once<RwLock<Registry>> = Once::new();
+
+fn registry() -> &'static RwLock<Registry> {
+    REGISTRY.call_once(|| {
+        RwLock::new(Registry {
+            drivers: Vec::new(),
+            bound: BTreeMap::new(),
+        })
+    })
+}
+
+fn same(a: &'static Driver, b: &'static Driver) -> bool {
+    a as *const Driver as *const u8 == b as *const Driver as *const u8
+}
+
+/// Try a driver on a function, binding it on success
+fn try_probe(registry: &mut Registry, driver: &'static Driver, device: &Device) -> bool {
+    if !driver.matches().iter().any(|m| m.matches(device)) {
+        return false;
+    }
+    match driver.probe(device) {
+        Ok(()) => {
+            println!("PCI {} bound to {}", device.address, driver.name());
+            registry.bound.insert(device.address, driver);
+            true
+        }
+        Err(e) => {
+            println!("{} failed to probe {}: {:?}", driver.name(), device.address, e);
+            false
+        }
+    }
+}
+
+/// Offer a function to the drivers, true if one took it
+/// Used as the attach callback of the scan.
+pub fn attach(device: &Device) -> bool {
+    let mut registry = registry().write();
+    if registry.bound.contains_key(&device.address) {
+        return true;
+    }
+    let drivers = registry.drivers.clone();
+    drivers
+        .into_iter()
+        .any(|driver| try_probe(&mut registry, driver, device))
+}
+
+/// Unbind a function from its driver
+pub fn detach(device: &Device) {
+    let mut registry = registry().write();
+    if let Some(driver) = registry.bound.remove(&device.address) {
+        driver.remove(device);
+    }
+}
+
+/// Add a driver, probing the functions no driver took yet
+pub fn register(driver: &'static Driver) -> Result<(), ::common::error::Error> {
+    let mut registry = registry().write();
+    if registry.drivers.iter().any(|&other| same(other, driver)) {
+        return Err(err!(EAGAIN));
+    }
+    registry.drivers.push(driver);
+    for device in devices() {
+        if !registry.bound.contains_key(&device.address) {
+            try_probe(&mut registry, driver, device);
+        }
+    }
+    Ok(())
+}
+
+/// Remove a driver, unbinding its functions
+pub fn unregister(driver: &'static Driver) -> Result<(), ::common::error::Error> {
+    let mut registry = registry().write();
+    let index = match registry.drivers.iter().position(|&other| same(other, driver)) {
+        Some(index) => index,
+        None => return Err(err!(ENOENT)),
+    };
+    registry.drivers.remove(index);
+
+    let addresses: Vec<Address> = registry
+        .bound
+        .iter()
+        .filter(|&(_, &bound)| same(bound, driver))
+        .map(|(&address, _)| address)
+        .collect();
+    for address in addresses {
+        registry.bound.remove(&address);
+        if let Some(device) = devices().iter().find(|device| device.address == address) {
+            driver.remove(device);
+        }
+    }
+    Ok(())
+}
+
+/// Name of the driver bound to a function
+pub fn driver_of(address: Address) -> Option<&'static str> {
+    registry()
+        .read()
+        .bound
+        .get(&address)
+        .map(|driver| driver.name())
+}
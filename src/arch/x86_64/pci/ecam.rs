@@ -0,0 +1,67 @@
+//! PCI Express enhanced configuration access
+//!
+//! The MCFG table gives a memory window holding the 4KB configuration
+//! space of every function on a range of buses. Only segment 0 is used,
+//! the legacy ports can't reach other segments anyway.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arch::acpi;
+use arch::mmu::MMU;
+
+/// Size of the configuration space of a function
+pub const CONFIG_SIZE: u32 = 0x1000;
+
+/// Kernel address where bus 0 would be, 0 without ECAM
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static START_BUS: AtomicUsize = AtomicUsize::new(0);
+static END_BUS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether configuration space is memory mapped
+pub fn enabled() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+/// Kernel address of a register, None where ECAM doesn't reach
+pub fn address(bus: u32, dev: u32, func: u32, offset: u32) -> Option<u64> {
+    let base = BASE.load(Ordering::Relaxed) as u64;
+    if base == 0
+        || (bus as usize) < START_BUS.load(Ordering::Relaxed)
+        || (bus as usize) > END_BUS.load(Ordering::Relaxed)
+        || offset >= CONFIG_SIZE
+    {
+        return None;
+    }
+    Some(base + ((bus << 20) | (dev << 15) | (func << 12) | (offset & 0xffc)) as u64)
+}
+
+/// Map the window of segment 0
+pub fn init() {
+    let mcfg = match acpi::mcfg() {
+        Some(mcfg) => mcfg,
+        None => return,
+    };
+    let entry = match mcfg.entries.iter().find(|entry| entry.segment == 0) {
+        Some(entry) => *entry,
+        None => return,
+    };
+    let first = entry.address + ((entry.start_bus as u64) << 20);
+    let size = (entry.end_bus as u64 - entry.start_bus as u64 + 1) << 20;
+    let mapped: u64 = match MMU::get().map_physical(first, size, true) {
+        Ok(mapped) => mapped.into(),
+        Err(e) => {
+            println!("Failed to map PCI Express configuration space: {:?}", e);
+            return;
+        }
+    };
+    START_BUS.store(entry.start_bus as usize, Ordering::Relaxed);
+    END_BUS.store(entry.end_bus as usize, Ordering::Relaxed);
+    BASE.store(
+        (mapped - ((entry.start_bus as u64) << 20)) as usize,
+        Ordering::Release,
+    );
+    println!(
+        "PCI Express configuration at {:#x}, buses {} to {}",
+        entry.address, entry.start_bus, entry.end_bus
+    );
+}
@@ -0,0 +1,199 @@
+//! PCI bus
+//!
+//! The buses are walked from the host bridges down through every
+//! PCI-to-PCI bridge at boot, and the functions found are kept in a
+//! table drivers look their devices up in. Each function found is
+//! offered to the registered drivers.
+//!
+//! Configuration space is read through the ECAM window the MCFG table
+//! describes, or through the legacy ports limited to 256 bytes.
+
+mod device;
+mod driver;
+mod ecam;
+mod msi;
+
+use alloc::vec::Vec;
+use arch::io;
+use spin::Once;
+use sync::IrqSpinLock;
+
+pub use self::device::{
+    Address, Bar, Capability, Device, ExtendedCapability, COMMAND_BUS_MASTER,
+    COMMAND_INTX_DISABLE, COMMAND_IO, COMMAND_MEMORY,
+};
+pub use self::ecam::{enabled as extended_config, CONFIG_SIZE};
+pub use self::msi::{alloc_vector, eoi as msi_eoi, free_vector, CAP_MSI, CAP_MSIX};
+pub use self::driver::{attach, detach, driver_of, register, unregister, Driver, Match};
+use self::device::{HEADER_MULTIFUNCTION, PCI_HEADER_TYPE};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+/// Size of the configuration space the legacy ports reach
+const LEGACY_CONFIG_SIZE: u32 = 0x100;
+
+/// Serializes use of the address and data ports
+static CONFIG_LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
+/// Functions found at boot
+static DEVICES: Once<Vec<Device>> = Once::new();
+
+/// Read from PCI config space
+/// Offsets past what can be reached read as all ones.
+pub unsafe fn pci_readcfg(bus: u32, dev: u32, func: u32, offset: u32) -> u32 {
+    if let Some(address) = ecam::address(bus, dev, func, offset) {
+        return (address as *const u32).read_volatile();
+    }
+    if offset >= LEGACY_CONFIG_SIZE {
+        return !0;
+    }
+    let address = 0x80000000u32 | (bus << 16) | (dev << 11) | (func << 8) | (offset & 0xfc);
+    let _lock = CONFIG_LOCK.lock();
+    io::outl(CONFIG_ADDRESS, address);
+    return io::inl(CONFIG_DATA);
+}
+
+/// Write to PCI config space
+pub unsafe fn pci_writecfg(bus: u32, dev: u32, func: u32, offset: u32, value: u32) {
+    if let Some(address) = ecam::address(bus, dev, func, offset) {
+        (address as *mut u32).write_volatile(value);
+        return;
+    }
+    if offset >= LEGACY_CONFIG_SIZE {
+        return;
+    }
+    let address = 0x80000000u32 | (bus << 16) | (dev << 11) | (func << 8) | (offset & 0xfc);
+    let _lock = CONFIG_LOCK.lock();
+    io::outl(CONFIG_ADDRESS, address);
+    io::outl(CONFIG_DATA, value);
+}
+
+pub struct PCIBus {
+    busno: u32,
+}
+
+// https://wiki.osdev.org/PCI#The_PCI_Bus
+impl PCIBus {
+    pub fn new(busno: u32) -> Self {
+        Self { busno: busno }
+    }
+
+    /// scan the devices on the bus and the buses behind its bridges
+    /// Returns the number of devices found and of functions attached.
+    pub fn scan<T>(&self, attach: &mut T) -> (usize, usize)
+    where
+        T: FnMut(&Device) -> bool,
+    {
+        let mut devcnt: usize = 0;
+        let mut attached: usize = 0;
+
+        for dev in 0..32 {
+            let address = Address::new(self.busno as u8, dev, 0);
+            // non-existing device
+            if !address.present() {
+                continue;
+            }
+            devcnt += 1;
+
+            let funcs = if address.read_u8(PCI_HEADER_TYPE) & HEADER_MULTIFUNCTION != 0 {
+                8
+            } else {
+                1
+            };
+
+            for func in 0..funcs {
+                let address = Address::new(self.busno as u8, dev, func);
+                if !address.present() {
+                    continue;
+                }
+
+                let device = Device::probe(address);
+                if attach(&device) {
+                    attached += 1;
+                }
+
+                if !device.is_bridge() {
+                    continue;
+                }
+                match device.secondary_bus() {
+                    // An unconfigured bridge, or one looping back
+                    Some(bus) if bus as u32 > self.busno => {
+                        let (found, bound) = PCIBus::new(bus as u32).scan(attach);
+                        devcnt += found;
+                        attached += bound;
+                    }
+                    _ => println!("PCI bridge {} has no bus behind it", device.address),
+                }
+            }
+        }
+
+        (devcnt, attached)
+    }
+}
+
+/// Get the functions found at boot
+pub fn devices() -> &'static [Device] {
+    DEVICES.try().map_or(&[], |devices| &devices[..])
+}
+
+/// Find a function by its address
+pub fn find(address: Address) -> Option<&'static Device> {
+    devices().iter().find(|device| device.address == address)
+}
+
+/// Find the functions with a vendor and device ID
+pub fn find_id(vendor_id: u16, device_id: u16) -> Vec<&'static Device> {
+    devices()
+        .iter()
+        .filter(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+        .collect()
+}
+
+/// Find the functions of a class and subclass
+pub fn find_class(class: u8, subclass: u8) -> Vec<&'static Device> {
+    devices()
+        .iter()
+        .filter(|device| device.class == class && device.subclass == subclass)
+        .collect()
+}
+
+/// Walk the buses, record the functions and bind drivers to them
+/// Needs the heap.
+pub fn init() {
+    ecam::init();
+    let mut bound = 0;
+    DEVICES.call_once(|| {
+        let mut devices = Vec::new();
+        {
+            let mut record = |device: &Device| {
+                println!("PCI {}", device);
+                for (index, bar) in device.bars.iter().enumerate() {
+                    if let Some(ref bar) = *bar {
+                        println!(
+                            "    BAR{} {} {:#x} size {:#x}",
+                            index,
+                            if bar.is_io() { "I/O" } else { "memory" },
+                            bar.address(),
+                            bar.size()
+                        );
+                    }
+                }
+                devices.push(device.clone());
+                attach(device)
+            };
+
+            // A multi-function host bridge has one bus per function
+            let host = Address::new(0, 0, 0);
+            if host.read_u8(PCI_HEADER_TYPE) & HEADER_MULTIFUNCTION == 0 {
+                bound += PCIBus::new(0).scan(&mut record).1;
+            } else {
+                for func in 0..8 {
+                    if Address::new(0, 0, func).present() {
+                        bound += PCIBus::new(func as u32).scan(&mut record).1;
+                    }
+                }
+            }
+        }
+        devices
+    });
+    println!("{} PCI functions, {} bound", devices().len(), bound);
+}
@@ -0,0 +1,269 @@
+//! Message signaled interrupts
+//!
+//! A function with MSI or MSI-X writes its message straight to a local
+//! APIC, so each one gets a vector of its own instead of sharing an IRQ
+//! line. Vectors come from the range between the IRQs and the vectors
+//! the local APIC uses itself.
+
+use arch::apic;
+use arch::idt::IDT;
+use arch::irq::{IRQ_VECTOR_BASE, MAX_IRQS};
+use arch::mmu::MMU;
+use sync::IrqSpinLock;
+
+use super::device::{Bar, Device, COMMAND_INTX_DISABLE, COMMAND_MEMORY};
+
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_MSIX: u8 = 0x11;
+
+const MSI_VECTOR_BASE: usize = IRQ_VECTOR_BASE + MAX_IRQS;
+const MSI_VECTOR_END: usize = apic::TIMER_VECTOR;
+
+/// Messages are writes to this window, with the destination APIC ID
+const MSI_ADDRESS: u64 = 0xfee00000;
+const MSI_DESTINATION_SHIFT: u64 = 12;
+
+// MSI capability, offsets from its header
+const MSI_CONTROL: u32 = 0x2;
+const MSI_ADDRESS_LOW: u32 = 0x4;
+const MSI_ADDRESS_HIGH: u32 = 0x8;
+const MSI_DATA_32: u32 = 0x8;
+const MSI_DATA_64: u32 = 0xc;
+const MSI_MASK_32: u32 = 0xc;
+const MSI_MASK_64: u32 = 0x10;
+
+const MSI_ENABLE: u16 = 1 << 0;
+const MSI_MULTIPLE_ENABLE: u16 = 0x7 << 4;
+const MSI_64BIT: u16 = 1 << 7;
+const MSI_PER_VECTOR_MASK: u16 = 1 << 8;
+
+// MSI-X capability, offsets from its header
+const MSIX_CONTROL: u32 = 0x2;
+const MSIX_TABLE: u32 = 0x4;
+
+const MSIX_TABLE_SIZE: u16 = 0x7ff;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_BIR: u32 = 0x7;
+
+// MSI-X table entries
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_ADDRESS_LOW: u64 = 0x0;
+const MSIX_ENTRY_ADDRESS_HIGH: u64 = 0x4;
+const MSIX_ENTRY_DATA: u64 = 0x8;
+const MSIX_ENTRY_CONTROL: u64 = 0xc;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Vectors in use, a bit per vector from MSI_VECTOR_BASE
+static VECTORS: IrqSpinLock<[u64; 4]> = IrqSpinLock::new([0; 4]);
+
+/// Reserve a vector and install its handler
+/// Handlers acknowledge with eoi().
+pub fn alloc_vector(handler: fn(u64, u64)) -> Result<usize, ::common::error::Error> {
+    let mut vectors = VECTORS.lock();
+    for vector in MSI_VECTOR_BASE..MSI_VECTOR_END {
+        let bit = vector - MSI_VECTOR_BASE;
+        if vectors[bit / 64] & 1 << (bit % 64) != 0 {
+            continue;
+        }
+        if !IDT::get().register_isr(vector, handler) {
+            continue;
+        }
+        vectors[bit / 64] |= 1 << (bit % 64);
+        return Ok(vector);
+    }
+    Err(err!(EFULL))
+}
+
+/// Give back a vector from alloc_vector()
+pub fn free_vector(vector: usize) {
+    if vector < MSI_VECTOR_BASE || vector >= MSI_VECTOR_END {
+        return;
+    }
+    let mut vectors = VECTORS.lock();
+    let bit = vector - MSI_VECTOR_BASE;
+    if vectors[bit / 64] & 1 << (bit % 64) != 0 {
+        IDT::get().unregister_isr(vector);
+        vectors[bit / 64] &= !(1 << (bit % 64));
+    }
+}
+
+/// Signal the end of a message signaled interrupt
+pub fn eoi() {
+    apic::eoi();
+}
+
+/// Address and data of the message raising vector on this CPU
+fn message(vector: usize) -> (u64, u32) {
+    (
+        MSI_ADDRESS | (apic::id() as u64) << MSI_DESTINATION_SHIFT,
+        vector as u32,
+    )
+}
+
+/// Kernel address of the MSI-X table
+fn msix_table(device: &Device, cap: u32) -> Result<u64, ::common::error::Error> {
+    let table = device.address.read(cap + MSIX_TABLE);
+    let size = msix_count_at(device, cap) as u64 * MSIX_ENTRY_SIZE;
+    match device.bars.get((table & MSIX_BIR) as usize) {
+        Some(&Some(Bar::Memory { address, .. })) => {
+            let mapped = try!(MMU::get().map_physical(
+                address + (table & !MSIX_BIR) as u64,
+                size,
+                true
+            ));
+            Ok(mapped.into())
+        }
+        _ => Err(err!(ENODEV)),
+    }
+}
+
+fn msix_count_at(device: &Device, cap: u32) -> usize {
+    (device.address.read_u16(cap + MSIX_CONTROL) & MSIX_TABLE_SIZE) as usize + 1
+}
+
+impl Device {
+    /// Number of MSI-X table entries, 0 without MSI-X
+    pub fn msix_count(&self) -> usize {
+        match self.capability(CAP_MSIX) {
+            Some(cap) => msix_count_at(self, cap as u32),
+            None => 0,
+        }
+    }
+
+    /// Deliver the function's interrupt as a single MSI message
+    /// Returns the vector handler is installed on.
+    pub fn enable_msi(&self, handler: fn(u64, u64)) -> Result<usize, ::common::error::Error> {
+        let cap = match self.capability(CAP_MSI) {
+            Some(cap) if apic::enabled() => cap as u32,
+            _ => return Err(err!(ENODEV)),
+        };
+        let vector = try!(alloc_vector(handler));
+        let (address, data) = message(vector);
+        let config = &self.address;
+        let control = config.read_u16(cap + MSI_CONTROL);
+
+        config.write(cap + MSI_ADDRESS_LOW, address as u32);
+        if control & MSI_64BIT != 0 {
+            config.write(cap + MSI_ADDRESS_HIGH, (address >> 32) as u32);
+            config.write_u16(cap + MSI_DATA_64, data as u16);
+        } else {
+            config.write_u16(cap + MSI_DATA_32, data as u16);
+        }
+        if control & MSI_PER_VECTOR_MASK != 0 {
+            let mask = if control & MSI_64BIT != 0 {
+                MSI_MASK_64
+            } else {
+                MSI_MASK_32
+            };
+            config.write(cap + mask, 0);
+        }
+        // One vector only
+        config.write_u16(
+            cap + MSI_CONTROL,
+            control & !MSI_MULTIPLE_ENABLE | MSI_ENABLE,
+        );
+        self.enable(COMMAND_INTX_DISABLE);
+        Ok(vector)
+    }
+
+    /// Go back to the IRQ line, freeing the MSI vector
+    pub fn disable_msi(&self) {
+        let cap = match self.capability(CAP_MSI) {
+            Some(cap) => cap as u32,
+            None => return,
+        };
+        let control = self.address.read_u16(cap + MSI_CONTROL);
+        if control & MSI_ENABLE == 0 {
+            return;
+        }
+        self.address
+            .write_u16(cap + MSI_CONTROL, control & !MSI_ENABLE);
+        self.disable(COMMAND_INTX_DISABLE);
+        let data = if control & MSI_64BIT != 0 {
+            MSI_DATA_64
+        } else {
+            MSI_DATA_32
+        };
+        free_vector(self.address.read_u16(cap + data) as u8 as usize);
+    }
+
+    /// Deliver an MSI-X table entry as a message
+    /// Returns the vector handler is installed on. Entries not
+    /// enabled stay masked.
+    pub fn enable_msix(
+        &self,
+        entry: usize,
+        handler: fn(u64, u64),
+    ) -> Result<usize, ::common::error::Error> {
+        let cap = match self.capability(CAP_MSIX) {
+            Some(cap) if apic::enabled() => cap as u32,
+            _ => return Err(err!(ENODEV)),
+        };
+        let count = msix_count_at(self, cap);
+        if entry >= count {
+            return Err(err!(EINVAL));
+        }
+        let table = try!(msix_table(self, cap));
+        // Nothing can fail once the function is switched over
+        let vector = try!(alloc_vector(handler));
+        let control = self.address.read_u16(cap + MSIX_CONTROL);
+        unsafe {
+            if control & MSIX_ENABLE == 0 {
+                // Turn MSI-X on with every entry masked
+                self.address
+                    .write_u16(cap + MSIX_CONTROL, control | MSIX_ENABLE | MSIX_FUNCTION_MASK);
+                for index in 0..count as u64 {
+                    let entry_control =
+                        (table + index * MSIX_ENTRY_SIZE + MSIX_ENTRY_CONTROL) as *mut u32;
+                    entry_control.write_volatile(entry_control.read_volatile() | MSIX_ENTRY_MASKED);
+                }
+                self.enable(COMMAND_INTX_DISABLE | COMMAND_MEMORY);
+            }
+
+            let (address, data) = message(vector);
+            let base = table + entry as u64 * MSIX_ENTRY_SIZE;
+            ((base + MSIX_ENTRY_ADDRESS_LOW) as *mut u32).write_volatile(address as u32);
+            ((base + MSIX_ENTRY_ADDRESS_HIGH) as *mut u32).write_volatile((address >> 32) as u32);
+            ((base + MSIX_ENTRY_DATA) as *mut u32).write_volatile(data);
+            let entry_control = (base + MSIX_ENTRY_CONTROL) as *mut u32;
+            entry_control.write_volatile(entry_control.read_volatile() & !MSIX_ENTRY_MASKED);
+
+            let control = self.address.read_u16(cap + MSIX_CONTROL);
+            self.address
+                .write_u16(cap + MSIX_CONTROL, control & !MSIX_FUNCTION_MASK);
+            Ok(vector)
+        }
+    }
+
+    /// Go back to the IRQ line, freeing the vectors of the enabled entries
+    pub fn disable_msix(&self) {
+        let cap = match self.capability(CAP_MSIX) {
+            Some(cap) => cap as u32,
+            None => return,
+        };
+        let control = self.address.read_u16(cap + MSIX_CONTROL);
+        if control & MSIX_ENABLE == 0 {
+            return;
+        }
+        if let Ok(table) = msix_table(self, cap) {
+            for index in 0..msix_count_at(self, cap) as u64 {
+                let base = table + index * MSIX_ENTRY_SIZE;
+                unsafe {
+                    let entry_control = (base + MSIX_ENTRY_CONTROL) as *mut u32;
+                    let value = entry_control.read_volatile();
+                    if value & MSIX_ENTRY_MASKED == 0 {
+                        entry_control.write_volatile(value | MSIX_ENTRY_MASKED);
+                        let data = ((base + MSIX_ENTRY_DATA) as *const u32).read_volatile();
+                        free_vector(data as u8 as usize);
+                    }
+                }
+            }
+        }
+        self.address.write_u16(
+            cap + MSIX_CONTROL,
+            control & !(MSIX_ENABLE | MSIX_FUNCTION_MASK),
+        );
+        self.disable(COMMAND_INTX_DISABLE);
+    }
+}
@@ -0,0 +1,161 @@
+//! Per-CPU data
+//!
+//! Each CPU has its own GDT, TSS and boot stack, kept in a PerCpu
+//! which the GS base points to while running in the kernel.
+//! Interrupts from user mode swap the user GS base out with swapgs.
+
+use alloc::boxed::Box;
+use arch::gdt;
+use arch::context::TrapFrame;
+use arch::mmu::{cr3, MMU, PAGE_SIZE};
+use arch::msr::{wrmsr, IA32_GS_BASE, IA32_KERNEL_GS_BASE};
+use arch::tss::{ltr, TSS};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Maximum number of CPUs brought up
+pub const MAX_CPUS: usize = 16;
+/// Number of pages of the boot stack of application processors
+const AP_STACK_PAGES: usize = 4;
+
+extern "C" {
+    static mut init_stack_end: [u64; 1];
+}
+
+/// Data owned by one CPU
+#[repr(C)]
+pub struct PerCpu {
+    /// Address of this structure, read through %gs:0
+    this: *mut PerCpu,
+    /// Index of the CPU, read through %gs:8, 0 is the boot CPU
+    pub index: usize,
+    /// Local APIC ID
+    pub apic_id: u32,
+    /// Top of the boot stack, used by the idle task
+    pub stack_top: u64,
+    /// Trap frame of the interrupt being handled
+    pub trap_frame: *mut TrapFrame,
+    /// Address space loaded, for TLB shootdowns
+    pub cr3: AtomicUsize,
+    /// FPU state currently loaded in this CPU
+    pub fpu_owner: *mut u8,
+    /// FPU state of the running context
+    pub fpu_current: *mut u8,
+    pub tss: TSS,
+    gdt: [u64; gdt::GDT_ENTRIES],
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        PerCpu {
+            this: 0 as *mut PerCpu,
+            index: 0,
+            apic_id: 0,
+            stack_top: 0,
+            trap_frame: 0 as *mut TrapFrame,
+            cr3: AtomicUsize::new(0),
+            fpu_owner: 0 as *mut u8,
+            fpu_current: 0 as *mut u8,
+            tss: TSS::new(),
+            gdt: [0; gdt::GDT_ENTRIES],
+        }
+    }
+}
+
+static mut BOOT_CPU: PerCpu = PerCpu::new();
+static mut CPUS: [*mut PerCpu; MAX_CPUS] = [0 as *mut PerCpu; MAX_CPUS];
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the data of the running CPU
+/// Interrupts must be disabled while the reference is used,
+/// unless the task is pinned to this CPU.
+pub fn current() -> &'static mut PerCpu {
+    unsafe {
+        let cpu: *mut PerCpu;
+        asm!("mov %gs:0, $0" : "=r"(cpu) : : : "volatile");
+        &mut *cpu
+    }
+}
+
+/// Index of the running CPU
+pub fn cpu_id() -> usize {
+    unsafe {
+        let index: usize;
+        asm!("mov %gs:8, $0" : "=r"(index) : : : "volatile");
+        index
+    }
+}
+
+/// Number of CPUs set up so far
+pub fn count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Get the data of a CPU by its index
+pub fn get(index: usize) -> Option<&'static PerCpu> {
+    if index >= count() {
+        return None;
+    }
+    unsafe { CPUS[index].as_ref() }
+}
+
+/// Load the GDT and TSS of a CPU and point its GS base at it
+/// Run on the CPU itself.
+pub unsafe fn load(cpu: *mut PerCpu) {
+    let cpu = &mut *cpu;
+    gdt::load(&mut cpu.gdt, &cpu.tss);
+    ltr(gdt::GDT_TSS);
+    wrmsr(IA32_GS_BASE, cpu as *mut PerCpu as u64);
+    wrmsr(IA32_KERNEL_GS_BASE, 0);
+}
+
+/// Set up the data of an application processor
+pub fn new_ap(apic_id: u32) -> Result<*mut PerCpu, ::common::error::Error> {
+    let index = count();
+    if index >= MAX_CPUS {
+        return Err(err!(ENOMEM));
+    }
+    let stack = try!(MMU::get().alloc_contiguous(AP_STACK_PAGES));
+    let stack_top = stack.add(AP_STACK_PAGES as u64 * PAGE_SIZE);
+
+    let cpu = Box::into_raw(Box::new(PerCpu::new()));
+    unsafe {
+        (*cpu).this = cpu;
+        (*cpu).index = index;
+        (*cpu).apic_id = apic_id;
+        (*cpu).stack_top = stack_top;
+        (*cpu).tss.privilege_stack_table[0] = stack_top;
+        (*cpu).tss.interrupt_stack_table[0] = stack_top;
+        CPUS[index] = cpu;
+    }
+    CPU_COUNT.store(index + 1, Ordering::Release);
+    Ok(cpu)
+}
+
+/// Forget the last CPU set up, which failed to start
+/// Its data is leaked, the CPU may still be running.
+pub fn discard_ap(cpu: *mut PerCpu) {
+    unsafe {
+        let index = (*cpu).index;
+        if index + 1 == count() {
+            CPUS[index] = null_mut();
+            CPU_COUNT.store(index, Ordering::Release);
+        }
+    }
+}
+
+/// Set up the boot CPU
+pub fn init() {
+    unsafe {
+        let cpu = &mut BOOT_CPU as *mut PerCpu;
+        let stack_top = &init_stack_end as *const _ as u64;
+        BOOT_CPU.this = cpu;
+        BOOT_CPU.stack_top = stack_top;
+        BOOT_CPU.tss.privilege_stack_table[0] = stack_top;
+        BOOT_CPU.tss.interrupt_stack_table[0] = stack_top;
+        BOOT_CPU.cr3.store(cr3() as usize, Ordering::SeqCst);
+        CPUS[0] = cpu;
+        CPU_COUNT.store(1, Ordering::Release);
+        load(cpu);
+    }
+}
@@ -1,6 +1,5 @@
 use arch::io;
-use core::ops::Drop;
-use core::sync::atomic;
+use sync::{IrqSpinLock, IrqSpinLockGuard};
 
 const PIC_ISR_START: u8 = 32;
 
@@ -26,17 +25,14 @@ const ICW4_BUF_MASTER: u8 = 0xC; /* Buffered mode/master */
 const ICW4_SFNM: u8 = 0x10; /* Special fully nested (not) */
 
 /// An instance of the PIC
-pub struct PIC(bool);
+pub struct PIC(IrqSpinLockGuard<'static, ()>);
 
-static PIC_LOCK: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+static PIC_LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
 
 impl PIC {
     /// Get a locked instance of local PIC
     pub fn get() -> Self {
-        while !PIC_LOCK.compare_and_swap(false, true, atomic::Ordering::Relaxed) {}
-        atomic::fence(atomic::Ordering::Acquire);
-
-        PIC(true)
+        PIC(PIC_LOCK.lock())
     }
 
     /// Send EOI command
@@ -97,12 +93,6 @@ impl PIC {
     }
 }
 
-impl Drop for PIC {
-    fn drop(&mut self) {
-        PIC_LOCK.store(false, atomic::Ordering::Release);
-    }
-}
-
 pub fn init() {
     unsafe {
         PIC::get().remap(PIC_ISR_START, PIC_ISR_START + 8);
@@ -0,0 +1,157 @@
+//! Power off and reboot
+//!
+//! ACPI is tried first, then whatever else the machine may understand.
+
+use arch::idt;
+use arch::io;
+
+use super::acpi::{self, GenericAddress, SPACE_IO, SPACE_MEMORY, SPACE_PCI};
+use super::mmu::MMU;
+use super::pci;
+
+/// PM1 control bits
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 7;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+const KBC_STATUS: u16 = 0x64;
+const KBC_COMMAND: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 0x02;
+const KBC_PULSE_RESET: u8 = 0xfe;
+
+/// QEMU's isa-debug-exit device, when configured at this port
+const QEMU_DEBUG_EXIT: u16 = 0xf4;
+/// Shutdown ports of QEMU, older QEMU and Bochs, and VirtualBox
+const EMULATOR_SHUTDOWN: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+/// Write a register of the FADT
+unsafe fn write_register(register: &GenericAddress, value: u64) {
+    match register.space {
+        SPACE_IO => match register.bit_width {
+            16 => io::outw(register.address as u16, value as u16),
+            32 => io::outl(register.address as u16, value as u32),
+            _ => io::outb(register.address as u16, value as u8),
+        },
+        SPACE_MEMORY => {
+            if let Ok(address) = MMU::get().map_physical(register.address, 8, true) {
+                let address: u64 = address.into();
+                match register.bit_width {
+                    16 => (address as *mut u16).write_volatile(value as u16),
+                    32 => (address as *mut u32).write_volatile(value as u32),
+                    64 => (address as *mut u64).write_volatile(value),
+                    _ => (address as *mut u8).write_volatile(value as u8),
+                }
+            }
+        }
+        SPACE_PCI => {
+            // Bus 0, device, function and offset packed in the address
+            let device = (register.address >> 32 & 0xffff) as u32;
+            let function = (register.address >> 16 & 0xffff) as u32;
+            let offset = (register.address & 0xffff) as u32;
+            let shift = (offset & 3) * 8;
+            let old = pci::pci_readcfg(0, device, function, offset & !3);
+            let new = (old & !(0xff << shift)) | (value as u32 & 0xff) << shift;
+            pci::pci_writecfg(0, device, function, offset & !3, new);
+        }
+        _ => {}
+    }
+}
+
+unsafe fn read_pm1(register: &GenericAddress) -> u16 {
+    match register.space {
+        SPACE_IO => io::inw(register.address as u16),
+        _ => 0,
+    }
+}
+
+/// Enter ACPI mode unless the firmware already did
+unsafe fn enable_acpi(fadt: &acpi::Fadt) {
+    let pm1a = match fadt.pm1a_control {
+        None => return,
+        Some(ref pm1a) => pm1a,
+    };
+    if read_pm1(pm1a) & PM1_SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    io::outb(fadt.smi_command as u16, fadt.acpi_enable);
+    for _ in 0..1000000 {
+        if read_pm1(pm1a) & PM1_SCI_EN != 0 {
+            break;
+        }
+    }
+}
+
+/// Enter the S5 state through the PM1 control blocks
+unsafe fn acpi_power_off() {
+    let fadt = match acpi::fadt() {
+        None => return,
+        Some(fadt) => fadt,
+    };
+    let (type_a, type_b) = match acpi::sleep_type(b"_S5_") {
+        None => return,
+        Some(types) => types,
+    };
+    enable_acpi(fadt);
+    if let Some(ref pm1b) = fadt.pm1b_control {
+        let value = PM1_SLP_EN | (type_b as u16) << PM1_SLP_TYP_SHIFT;
+        let old = read_pm1(pm1b) & !(PM1_SLP_TYP_MASK << PM1_SLP_TYP_SHIFT);
+        write_register(pm1b, (old | value) as u64);
+    }
+    if let Some(ref pm1a) = fadt.pm1a_control {
+        let value = PM1_SLP_EN | (type_a as u16) << PM1_SLP_TYP_SHIFT;
+        let old = read_pm1(pm1a) & !(PM1_SLP_TYP_MASK << PM1_SLP_TYP_SHIFT);
+        write_register(pm1a, (old | value) as u64);
+    }
+}
+
+/// Stop this CPU for good
+fn hang() -> ! {
+    loop {
+        unsafe {
+            idt::cli();
+            idt::hlt();
+        }
+    }
+}
+
+/// Turn the machine off, or halt if nothing works
+pub fn power_off() -> ! {
+    println!("Powering off");
+    unsafe {
+        idt::cli();
+        acpi_power_off();
+        for &(port, value) in EMULATOR_SHUTDOWN.iter() {
+            io::outw(port, value);
+        }
+        io::outb(QEMU_DEBUG_EXIT, 0);
+    }
+    println!("Power off failed, halting");
+    hang()
+}
+
+/// Restart the machine
+pub fn reboot() -> ! {
+    println!("Rebooting");
+    unsafe {
+        idt::cli();
+        if let Some(fadt) = acpi::fadt() {
+            if let (true, Some(ref register)) = (fadt.can_reset(), fadt.reset_register) {
+                write_register(register, fadt.reset_value as u64);
+            }
+        }
+
+        // Pulse the reset line through the keyboard controller
+        for _ in 0..100000 {
+            if io::inb(KBC_STATUS) & KBC_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        io::outb(KBC_COMMAND, KBC_PULSE_RESET);
+
+        // Fault with no IDT, which makes a triple fault
+        idt::load_null();
+        idt::int3();
+    }
+    hang()
+}
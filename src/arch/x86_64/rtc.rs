@@ -0,0 +1,239 @@
+//! CMOS real-time clock
+
+use arch::io;
+use arch::irq;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::IrqSpinLock;
+use time::DateTime;
+
+use super::acpi;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Set in the index to keep NMIs disabled
+const CMOS_NMI_DISABLE: u8 = 0x80;
+
+// Registers
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+/// Century register, unless the FADT names another
+const RTC_CENTURY: u8 = 0x32;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+const RTC_STATUS_C: u8 = 0x0c;
+
+const STATUS_A_UPDATING: u8 = 0x80;
+const STATUS_A_RATE: u8 = 0x0f;
+const STATUS_B_PERIODIC: u8 = 0x40;
+const STATUS_B_BINARY: u8 = 0x04;
+const STATUS_B_24HOUR: u8 = 0x02;
+/// PM flag of the hour register in 12 hour mode
+const HOUR_PM: u8 = 0x80;
+
+const RTC_IRQ: u8 = 8;
+/// Base frequency of the periodic interrupt
+const RTC_BASE_HZ: u64 = 32768;
+
+static CMOS_LOCK: IrqSpinLock<()> = IrqSpinLock::new(());
+static mut CALLBACK: Option<fn(u64)> = None;
+/// Periodic interrupts taken
+static PERIODIC_TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Read a CMOS register, the CMOS lock must be held
+unsafe fn read_cmos(register: u8) -> u8 {
+    io::outb(CMOS_INDEX, CMOS_NMI_DISABLE | register);
+    io::inb(CMOS_DATA)
+}
+
+/// Write a CMOS register, the CMOS lock must be held
+unsafe fn write_cmos(register: u8, value: u8) {
+    io::outb(CMOS_INDEX, CMOS_NMI_DISABLE | register);
+    io::outb(CMOS_DATA, value);
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Hour of the hours register, from 0 to 23
+fn hour_of(register: u8, binary: bool, hour24: bool) -> u32 {
+    let value = register & !HOUR_PM;
+    let hour = if binary { value } else { from_bcd(value) } as u32;
+    if hour24 {
+        return hour;
+    }
+    // 12 AM is midnight and 12 PM is noon
+    if register & HOUR_PM != 0 {
+        hour % 12 + 12
+    } else {
+        hour % 12
+    }
+}
+
+/// Raw clock registers
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Registers([u8; 7]);
+
+/// CMOS index of the century
+fn century_register() -> u8 {
+    match acpi::fadt() {
+        Some(fadt) if fadt.century != 0 => fadt.century,
+        _ => RTC_CENTURY,
+    }
+}
+
+unsafe fn read_registers(century: u8) -> Registers {
+    // Values are inconsistent while an update is in progress
+    while read_cmos(RTC_STATUS_A) & STATUS_A_UPDATING != 0 {}
+    Registers([
+        read_cmos(RTC_SECONDS),
+        read_cmos(RTC_MINUTES),
+        read_cmos(RTC_HOURS),
+        read_cmos(RTC_DAY),
+        read_cmos(RTC_MONTH),
+        read_cmos(RTC_YEAR),
+        read_cmos(century),
+    ])
+}
+
+/// Read the current date from the RTC
+/// The RTC is assumed to run in UTC.
+pub fn read() -> DateTime {
+    let century = century_register();
+    let _cmos = CMOS_LOCK.lock();
+    let (registers, status) = unsafe {
+        // Read until two reads in a row agree,
+        // so an update can't have happened in between
+        let mut registers = read_registers(century);
+        loop {
+            let again = read_registers(century);
+            if again == registers {
+                break;
+            }
+            registers = again;
+        }
+        (registers.0, read_cmos(RTC_STATUS_B))
+    };
+
+    let binary = status & STATUS_B_BINARY != 0;
+    let convert = |value: u8| if binary { value } else { from_bcd(value) };
+    let hour = hour_of(registers[2], binary, status & STATUS_B_24HOUR != 0);
+    let century = match convert(registers[6]) as u32 {
+        century @ 19...21 => century,
+        _ => 20,
+    };
+
+    DateTime {
+        year: century * 100 + convert(registers[5]) as u32,
+        month: convert(registers[4]) as u32,
+        day: convert(registers[3]) as u32,
+        hour: hour,
+        minute: convert(registers[1]) as u32,
+        second: convert(registers[0]) as u32,
+    }
+}
+
+/// Get the periodic interrupts taken
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed) as u64
+}
+
+fn handler(_vector: u64, _error_code: u64) {
+    // The interrupt isn't raised again until status C is read
+    unsafe {
+        let _cmos = CMOS_LOCK.lock();
+        read_cmos(RTC_STATUS_C);
+    }
+    irq::eoi(RTC_IRQ);
+    let tick = PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed) as u64 + 1;
+    if let Some(func) = unsafe { CALLBACK } {
+        func(tick);
+    }
+}
+
+/// Raise IRQ 8 at hz, a power of two from 2 to 8192, calling func
+pub fn enable_periodic(hz: u64, func: fn(u64)) -> Result<(), ::common::error::Error> {
+    if !hz.is_power_of_two() || hz < 2 || hz > 8192 {
+        return Err(err!(EINVAL));
+    }
+    // hz = 32768 >> (rate - 1)
+    let rate = (RTC_BASE_HZ / hz).trailing_zeros() as u8 + 1;
+
+    let cmos = CMOS_LOCK.lock();
+    unsafe {
+        if CALLBACK.is_some() {
+            return Err(err!(EAGAIN));
+        }
+        CALLBACK = Some(func);
+    }
+    if !irq::register(RTC_IRQ, handler) {
+        unsafe {
+            CALLBACK = None;
+        }
+        return Err(err!(EAGAIN));
+    }
+    unsafe {
+        let status_a = read_cmos(RTC_STATUS_A);
+        write_cmos(RTC_STATUS_A, (status_a & !STATUS_A_RATE) | rate);
+        let status_b = read_cmos(RTC_STATUS_B);
+        write_cmos(RTC_STATUS_B, status_b | STATUS_B_PERIODIC);
+        // Drop a stale interrupt, or none is ever raised
+        read_cmos(RTC_STATUS_C);
+    }
+    drop(cmos);
+
+    irq::unmask(RTC_IRQ);
+    Ok(())
+}
+
+/// Stop the periodic interrupt
+pub fn disable_periodic() {
+    irq::mask(RTC_IRQ);
+    {
+        let _cmos = CMOS_LOCK.lock();
+        unsafe {
+            let status_b = read_cmos(RTC_STATUS_B);
+            write_cmos(RTC_STATUS_B, status_b & !STATUS_B_PERIODIC);
+            CALLBACK = None;
+        }
+    }
+    irq::unregister(RTC_IRQ);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bcd() {
+        assert_eq!(from_bcd(0x00), 0);
+        assert_eq!(from_bcd(0x09), 9);
+        assert_eq!(from_bcd(0x10), 10);
+        assert_eq!(from_bcd(0x59), 59);
+    }
+
+    #[test]
+    fn hour_24() {
+        assert_eq!(hour_of(0x00, false, true), 0);
+        assert_eq!(hour_of(0x23, false, true), 23);
+        assert_eq!(hour_of(23, true, true), 23);
+    }
+
+    #[test]
+    fn hour_12() {
+        // 12 AM, 1 AM, 11 AM
+        assert_eq!(hour_of(0x12, false, false), 0);
+        assert_eq!(hour_of(0x01, false, false), 1);
+        assert_eq!(hour_of(0x11, false, false), 11);
+        // 12 PM, 1 PM, 11 PM
+        assert_eq!(hour_of(HOUR_PM | 0x12, false, false), 12);
+        assert_eq!(hour_of(HOUR_PM | 0x01, false, false), 13);
+        assert_eq!(hour_of(HOUR_PM | 0x11, false, false), 23);
+        assert_eq!(hour_of(HOUR_PM | 12, true, false), 12);
+        assert_eq!(hour_of(12, true, false), 0);
+    }
+}
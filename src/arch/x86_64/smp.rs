@@ -0,0 +1,158 @@
+//! Application processor startup
+//!
+//! Each processor listed in the MADT is woken with INIT-SIPI-SIPI into
+//! the trampoline, which enters long mode on a temporary page table and
+//! calls ap_entry() on the boot stack set up for the processor.
+
+use alloc::vec::Vec;
+use arch::acpi;
+use arch::apic;
+use arch::fpu;
+use arch::idt::IDT;
+use arch::mmu::{set_cr3, KERNEL_BASE, MMU, PAGE_SIZE};
+use arch::percpu::{self, PerCpu};
+use arch::timer;
+use arch::tlb;
+use core::ptr::{copy_nonoverlapping, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Physical address the trampoline is copied to, page aligned below 1MB
+const TRAMPOLINE_BASE: u64 = 0x8000;
+/// How long to wait for a processor, in PIT calibration periods (1s)
+const START_TIMEOUT: usize = 100;
+
+extern "C" {
+    static trampoline_start: u8;
+    static trampoline_end: u8;
+    static trampoline_cr3: u64;
+    static trampoline_stack: u64;
+    static trampoline_entry: u64;
+    static trampoline_arg: u64;
+    static init_pdpt: u8;
+}
+
+/// Set by a processor once it runs on its own GDT and stack
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Address of a trampoline variable in the copy
+unsafe fn slot(var: &u64) -> *mut u64 {
+    let offset = var as *const u64 as u64 - &trampoline_start as *const u8 as u64;
+    (KERNEL_BASE + TRAMPOLINE_BASE + offset) as *mut u64
+}
+
+/// Reschedule interrupt, sent when a task is queued on this CPU
+fn reschedule(_vector: u64, _error_code: u64) {
+    apic::eoi();
+    ::task::reschedule();
+}
+
+/// Ask another CPU to reschedule
+pub fn send_reschedule(index: usize) {
+    if let Some(cpu) = percpu::get(index) {
+        apic::send_ipi(cpu.apic_id, apic::RESCHEDULE_VECTOR);
+    }
+}
+
+/// First code of an application processor in the kernel
+extern "C" fn ap_entry(cpu: *mut PerCpu) -> ! {
+    unsafe {
+        percpu::load(cpu);
+        set_cr3(MMU::get().kernel_pml4().sub(KERNEL_BASE));
+    }
+    IDT::get().flush();
+    fpu::init_cpu();
+
+    let nmi = acpi::madt().map_or(Vec::new(), |madt| madt.nmi_lints(apic::id()));
+    if let Err(e) = apic::init_ap(&nmi) {
+        panic!("Failed to enable the local APIC: {:?}", e);
+    }
+    STARTED.store(true, Ordering::Release);
+
+    if let Err(e) = timer::start_local() {
+        println!("CPU {} runs without a timer: {:?}", percpu::cpu_id(), e);
+    }
+    ::task::start_cpu()
+}
+
+/// Start the application processors
+/// Needs the heap, the local APIC and the scheduler.
+pub fn init() {
+    let madt = match acpi::madt() {
+        Some(madt) if apic::enabled() => madt,
+        _ => return,
+    };
+    let boot_id = apic::id();
+    percpu::current().apic_id = boot_id;
+    let cpus: Vec<_> = madt
+        .cpus()
+        .into_iter()
+        .filter(|cpu| cpu.apic_id != boot_id)
+        .collect();
+    if cpus.is_empty() {
+        return;
+    }
+    timer::init_local();
+    tlb::init();
+    assert!(IDT::get().register_isr(apic::RESCHEDULE_VECTOR, reschedule));
+
+    let page = match MMU::get().alloc_page() {
+        Ok(page) => page,
+        Err(e) => {
+            println!("Failed to allocate the trampoline page table: {:?}", e);
+            return;
+        }
+    };
+    unsafe {
+        // Kernel mappings, plus low memory identity mapped
+        // for the trampoline to turn paging on
+        let table = Into::<u64>::into(page) as *mut u64;
+        let kernel = Into::<u64>::into(MMU::get().kernel_pml4()) as *const u64;
+        copy_nonoverlapping(kernel, table, 512);
+        *table = &init_pdpt as *const u8 as u64 | 3;
+
+        let size = &trampoline_end as *const u8 as usize - &trampoline_start as *const u8 as usize;
+        copy_nonoverlapping(
+            &trampoline_start as *const u8,
+            (KERNEL_BASE + TRAMPOLINE_BASE) as *mut u8,
+            size,
+        );
+        write_volatile(slot(&trampoline_cr3), page.sub(KERNEL_BASE));
+        write_volatile(slot(&trampoline_entry), ap_entry as usize as u64);
+    }
+
+    for lapic in cpus.iter() {
+        let cpu = match percpu::new_ap(lapic.apic_id) {
+            Ok(cpu) => cpu,
+            Err(e) => {
+                println!("Not starting CPU {}: {:?}", lapic.apic_id, e);
+                break;
+            }
+        };
+        unsafe {
+            write_volatile(slot(&trampoline_stack), (*cpu).stack_top);
+            write_volatile(slot(&trampoline_arg), cpu as u64);
+        }
+
+        STARTED.store(false, Ordering::Release);
+        if let Err(e) = apic::start_ap(lapic.apic_id, (TRAMPOLINE_BASE / PAGE_SIZE) as u8) {
+            println!("Failed to start CPU {}: {:?}", lapic.apic_id, e);
+            percpu::discard_ap(cpu);
+            break;
+        }
+        let mut waited = 0;
+        while !STARTED.load(Ordering::Acquire) && waited < START_TIMEOUT {
+            timer::pit_wait(timer::CALIBRATE_CYCLES);
+            waited += 1;
+        }
+        if !STARTED.load(Ordering::Acquire) {
+            // A late processor would take the next one's stack,
+            // so don't start any more
+            println!("CPU {} didn't start", lapic.apic_id);
+            percpu::discard_ap(cpu);
+            break;
+        }
+    }
+
+    let _ = MMU::get().free_page(page);
+    println!("{} CPUs online", percpu::count());
+}
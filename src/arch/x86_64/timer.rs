@@ -1,7 +1,13 @@
-use arch::idt::IDT;
+use arch::apic;
+use arch::hpet;
+use arch::idt;
 use arch::io;
-use arch::pic::PIC;
-use spin::Mutex;
+use arch::irq;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::{IrqSpinLock, IrqSpinLockGuard};
+use task::Scheduler;
+
+use super::tsc;
 
 type TimerCallback = fn(u64);
 
@@ -10,23 +16,58 @@ const TIMER_C0_DATA: u16 = 0x40;
 const TIMER_C1_DATA: u16 = 0x41;
 const TIMER_C2_DATA: u16 = 0x42;
 const TIMER_MODE_CTRL: u16 = 0x43;
-const TIMER_WRAP: u64 = 0x100000000;
+/// Input clock of the PIT in Hz
+pub const PIT_FREQUENCY: u64 = 1193182;
+/// Tick rate programmed at boot
+pub const DEFAULT_HZ: u64 = 100;
+/// Speaker control, bit 0 gates channel 2 and bit 5 is its output
+const TIMER_C2_GATE: u16 = 0x61;
+/// Calibration length used by other clocks, in PIT cycles (10ms)
+pub const CALIBRATE_CYCLES: u64 = PIT_FREQUENCY / 100;
+/// The timer raises IRQ 0
+const TIMER_IRQ: u8 = 0;
+/// HPET comparator used for ticks
+const HPET_TIMER: usize = 0;
+
+/// Where timer ticks come from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickSource {
+    /// Channel 0 of the PIT
+    Pit = 0,
+    /// A periodic comparator of the HPET
+    Hpet = 1,
+}
 
-static mut SCHEDULER: Option<TimerCallback> = None;
-static TIMER: Mutex<Timer> = Mutex::new(Timer {
+static mut SCHEDULER: Option<&'static Scheduler> = None;
+static TIMER: IrqSpinLock<Timer> = IrqSpinLock::new(Timer {
     handlers: [None; MAX_CALLBACKS],
-    tick: 0,
 });
 
+/// Tick source, as a TickSource
+static SOURCE: AtomicUsize = AtomicUsize::new(TickSource::Pit as usize);
+/// IRQ ticks arrive on
+static TICK_IRQ: AtomicUsize = AtomicUsize::new(TIMER_IRQ as usize);
+/// Tick rate, the PIT's reload value of 65536 until init()
+static HZ: AtomicUsize = AtomicUsize::new((PIT_FREQUENCY / 65536) as usize);
+/// Nanoseconds between ticks
+static PERIOD_NS: AtomicUsize = AtomicUsize::new((65536 * 1_000_000_000 / PIT_FREQUENCY) as usize);
+/// Ticks since boot
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+/// Nanoseconds since boot at the last tick
+static TICK_NS: AtomicUsize = AtomicUsize::new(0);
+/// TSC value at the last tick
+static TICK_TSC: AtomicUsize = AtomicUsize::new(0);
+/// Odd while the tick handler updates the above
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
 /// Represents an instance of timer handler
 pub struct Timer {
     handlers: [Option<TimerCallback>; MAX_CALLBACKS],
-    tick: u64,
 }
 
 impl Timer {
     /// Get an instance of the timer
-    pub fn get() -> spin::MutexGuard<'static, Timer> {
+    pub fn get() -> IrqSpinLockGuard<'static, Timer> {
         TIMER.lock()
     }
 
@@ -51,65 +92,233 @@ impl Timer {
         Ok(())
     }
 
-    /// Register a scheduler function
-    pub fn register_scheduler(&self, func: fn(u64)) -> Result<(), ::common::error::Error> {
+    /// Register a scheduler
+    pub fn register_scheduler(
+        &self,
+        sched: &'static Scheduler,
+    ) -> Result<(), ::common::error::Error> {
         unsafe {
             if SCHEDULER.is_some() {
                 return Err(err!(EAGAIN));
             }
-            SCHEDULER = Some(func);
+            SCHEDULER = Some(sched);
         }
         Ok(())
     }
+
+    /// Get the registered scheduler
+    pub fn scheduler() -> Option<&'static Scheduler> {
+        unsafe { SCHEDULER }
+    }
 }
 
 fn handler(_vector: u64, _error_code: u64) {
-    // Send EOI to Master PIC
+    irq::eoi(TICK_IRQ.load(Ordering::Relaxed) as u8);
+
+    let tick = advance();
+
+    // TIMER is only held with interrupts disabled,
+    // so it can't be held by the code we interrupted.
+    {
+        let timer = TIMER.lock();
+        for i in 0..MAX_CALLBACKS {
+            // Call the callback, the callback must returns
+            if let Some(ref func) = timer.handlers[i] {
+                func(tick);
+            }
+        }
+    }
+
+    ::time::tick();
+
+    // Charge the tick to whoever was running
+    let user = unsafe { idt::trap_frame() }.map_or(false, |frame| frame.user_mode());
+    ::task::account_tick(user);
+
+    // Now do the scheduler thing
+    // It's totally fine if this does not returns
     unsafe {
-        PIC::eoi(false);
+        if let Some(sched) = SCHEDULER {
+            sched.schedule(tick);
+        }
     }
+}
 
-    // Try lock the TIMER global
-    // If we cannot get a locked instance of TIMER,
-    // we can't just wait here since we're in the
-    // middle of an ISR.
-    let mut tick: u64 = 0;
-    match TIMER.try_lock() {
-        None => {
-            // Nothing we can do now, TIMER is occupied
+/// Count a tick and note when it happened
+fn advance() -> u64 {
+    SEQUENCE.fetch_add(1, Ordering::Release);
+    let tick = TICKS.load(Ordering::Relaxed) + 1;
+    TICKS.store(tick, Ordering::Relaxed);
+    TICK_NS.fetch_add(tick_ns() as usize, Ordering::Relaxed);
+    TICK_TSC.store(tsc::rdtsc() as usize, Ordering::Relaxed);
+    SEQUENCE.fetch_add(1, Ordering::Release);
+    tick as u64
+}
+
+/// Get the ticks elapsed since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed) as u64
+}
+
+/// Get the tick rate in Hz
+pub fn hz() -> u64 {
+    HZ.load(Ordering::Relaxed) as u64
+}
+
+/// Nanoseconds between two timer ticks
+pub fn tick_ns() -> u64 {
+    PERIOD_NS.load(Ordering::Relaxed) as u64
+}
+
+/// Get the source of timer ticks
+pub fn source() -> TickSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => TickSource::Hpet,
+        _ => TickSource::Pit,
+    }
+}
+
+/// Get the nanoseconds elapsed since boot
+/// Interpolated with the TSC between ticks, never goes backwards.
+pub fn clock_ns() -> u64 {
+    loop {
+        let sequence = SEQUENCE.load(Ordering::Acquire);
+        if sequence & 1 != 0 {
+            continue;
+        }
+        let base = TICK_NS.load(Ordering::Relaxed) as u64;
+        let tsc_base = TICK_TSC.load(Ordering::Relaxed) as u64;
+        if SEQUENCE.load(Ordering::Acquire) != sequence {
+            continue;
         }
-        Some(mut timer) => {
-            // wrap-adds the tick
-            if timer.tick >= TIMER_WRAP {
-                timer.tick = 0;
-            }
-            timer.tick += 1;
 
-            tick = timer.tick;
+        let frequency = tsc::frequency();
+        if frequency == 0 || tsc_base == 0 {
+            return base;
+        }
+        let elapsed = tsc::rdtsc().saturating_sub(tsc_base);
+        let offset = elapsed / frequency * 1_000_000_000
+            + elapsed % frequency * 1_000_000_000 / frequency;
+        // Stay below the next tick, which may be late
+        return base + offset.min(tick_ns() - 1);
+    }
+}
 
-            for i in 0..MAX_CALLBACKS {
-                // Call the callback, the callback must returns
-                if let Some(ref func) = timer.handlers[i] {
-                    func(timer.tick);
-                }
+/// Start a tick source at a rate, called with TIMER held
+fn program(source: TickSource, hz: u64) -> Result<(), ::common::error::Error> {
+    let period = match source {
+        TickSource::Pit => {
+            // The divisor is 16 bits wide, 0 stands for 65536
+            if hz == 0 || PIT_FREQUENCY / hz > 65536 || PIT_FREQUENCY / hz < 2 {
+                return Err(err!(EINVAL));
+            }
+            let divisor = PIT_FREQUENCY / hz;
+            unsafe {
+                io::outb(TIMER_MODE_CTRL, 0x36);
+                io::outb(TIMER_C0_DATA, divisor as u8);
+                io::outb(TIMER_C0_DATA, (divisor >> 8) as u8);
             }
+            divisor * 1_000_000_000 / PIT_FREQUENCY
         }
+        TickSource::Hpet => try!(hpet::start_timer(HPET_TIMER, hpet::TimerMode::Periodic(hz))),
     };
+    HZ.store(hz as usize, Ordering::Relaxed);
+    PERIOD_NS.store(period as usize, Ordering::Relaxed);
+    Ok(())
+}
 
-    // Now do the scheduler thing
-    // It's totally fine if this does not returns
-    unsafe {
-        if let Some(ref handler) = SCHEDULER {
-            handler(tick);
+/// Program the tick rate
+pub fn set_hz(hz: u64) -> Result<(), ::common::error::Error> {
+    let _timer = TIMER.lock();
+    program(source(), hz)
+}
+
+/// Switch the source of timer ticks, keeping the rate
+pub fn set_source(new: TickSource) -> Result<(), ::common::error::Error> {
+    let irq = match new {
+        TickSource::Pit => TIMER_IRQ,
+        TickSource::Hpet => try!(hpet::timer_irq(HPET_TIMER)),
+    };
+    let old_irq = TICK_IRQ.load(Ordering::Relaxed) as u8;
+    if irq != old_irq && !irq::register(irq, handler) {
+        return Err(err!(EAGAIN));
+    }
+
+    {
+        let _timer = TIMER.lock();
+        let old = source();
+        if old == new {
+            return Ok(());
+        }
+        if let Err(e) = program(new, hz()) {
+            if irq != old_irq {
+                irq::unregister(irq);
+            }
+            return Err(e);
         }
+        if old == TickSource::Hpet {
+            hpet::stop_timer(HPET_TIMER);
+        }
+        if irq != old_irq {
+            irq::mask(old_irq);
+        }
+        TICK_IRQ.store(irq as usize, Ordering::Relaxed);
+        SOURCE.store(new as usize, Ordering::Relaxed);
+        irq::unmask(irq);
+    }
+
+    if irq != old_irq {
+        irq::unregister(old_irq);
     }
+    Ok(())
 }
 
-pub fn init() {
+/// Busy wait for a number of PIT cycles, up to 65535, on channel 2
+/// Used to calibrate other clocks before interrupts are up.
+pub fn pit_wait(cycles: u64) {
+    unsafe {
+        // Channel 2 counts down once in mode 0, speaker off
+        let gate = io::inb(TIMER_C2_GATE) & !0x03;
+        io::outb(TIMER_C2_GATE, gate);
+        io::outb(TIMER_MODE_CTRL, 0xb0);
+        io::outb(TIMER_C2_DATA, cycles as u8);
+        io::outb(TIMER_C2_DATA, (cycles >> 8) as u8);
+
+        // Raising the gate starts the count, the output goes high at 0
+        io::outb(TIMER_C2_GATE, gate | 0x01);
+        while io::inb(TIMER_C2_GATE) & 0x20 == 0 {}
+        io::outb(TIMER_C2_GATE, gate);
+    }
+}
+
+/// Local APIC timer handler of application processors
+/// They only schedule, the boot CPU keeps the time.
+fn local_handler(_vector: u64, _error_code: u64) {
+    apic::eoi();
+
+    let user = unsafe { idt::trap_frame() }.map_or(false, |frame| frame.user_mode());
+    ::task::account_tick(user);
+
     unsafe {
-        io::outb(TIMER_MODE_CTRL, 0x36);
-        io::outb(TIMER_C0_DATA, 0);
-        io::outb(TIMER_C0_DATA, 0);
+        if let Some(sched) = SCHEDULER {
+            sched.schedule(ticks());
+        }
     }
-    assert!(IDT::get().register_isr(32, handler));
+}
+
+/// Tick on this application processor at the current rate
+pub fn start_local() -> Result<(), ::common::error::Error> {
+    apic::start_timer(apic::TIMER_VECTOR, apic::TimerMode::Periodic(hz()))
+}
+
+/// Prepare the local timers of application processors
+pub fn init_local() {
+    assert!(idt::IDT::get().register_isr(apic::TIMER_VECTOR, local_handler));
+}
+
+pub fn init() {
+    tsc::init();
+    assert!(set_hz(DEFAULT_HZ).is_ok());
+    assert!(irq::register(TIMER_IRQ, handler));
+    irq::unmask(TIMER_IRQ);
 }
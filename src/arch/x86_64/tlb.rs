@@ -0,0 +1,114 @@
+//! TLB shootdown
+//!
+//! Entries removed from a page table are invalidated on every CPU
+//! which has the address space loaded, before their pages are reused.
+//! Other CPUs are asked with an interprocessor interrupt and waited
+//! for, one shootdown at a time.
+
+use arch::apic;
+use arch::idt::{IrqGuard, IDT};
+use arch::mmu::{cr3, set_cr3, PAGE_SIZE};
+use arch::percpu;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use sync::IrqSpinLock;
+
+/// Above this many pages the whole TLB is flushed
+const FLUSH_ALL_PAGES: usize = 32;
+
+/// Held while a shootdown is in flight
+static BUSY: IrqSpinLock<()> = IrqSpinLock::new(());
+/// Address space, first page and number of pages to invalidate
+static SPACE: AtomicUsize = AtomicUsize::new(0);
+static START: AtomicUsize = AtomicUsize::new(0);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+/// CPUs which haven't invalidated yet, one bit each
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+#[inline]
+unsafe fn invlpg(address: u64) {
+    asm!("invlpg ($0)" : : "r"(address) : "memory" : "volatile");
+}
+
+/// Invalidate pages on this CPU
+pub fn invalidate(address: u64, count: usize) {
+    unsafe {
+        if count > FLUSH_ALL_PAGES {
+            set_cr3(cr3());
+        } else {
+            for i in 0..count {
+                invlpg(address + i as u64 * PAGE_SIZE);
+            }
+        }
+    }
+}
+
+/// Handle the shootdown in flight if this CPU is asked to
+fn service() {
+    let bit = 1 << percpu::cpu_id();
+    if PENDING.load(Ordering::Acquire) & bit == 0 {
+        return;
+    }
+    if unsafe { cr3() } == SPACE.load(Ordering::Relaxed) as u64 {
+        invalidate(
+            START.load(Ordering::Relaxed) as u64,
+            COUNT.load(Ordering::Relaxed),
+        );
+    }
+    PENDING.fetch_and(!bit, Ordering::Release);
+}
+
+fn handler(_vector: u64, _error_code: u64) {
+    apic::eoi();
+    service();
+}
+
+/// Invalidate pages of the address space at space on all CPUs
+/// The page table entries must be cleared already, and no lock
+/// another CPU may spin on with interrupts disabled must be held.
+pub fn shootdown(space: u64, address: u64, count: usize) {
+    let _irq = IrqGuard::new();
+    if unsafe { cr3() } == space {
+        invalidate(address, count);
+    }
+    if percpu::count() < 2 {
+        return;
+    }
+
+    // Keep answering the others while waiting for our turn
+    let _busy = loop {
+        match BUSY.try_lock() {
+            Some(busy) => break busy,
+            None => service(),
+        };
+    };
+    SPACE.store(space as usize, Ordering::Relaxed);
+    START.store(address as usize, Ordering::Relaxed);
+    COUNT.store(count, Ordering::Relaxed);
+
+    // A CPU loading the space after this point
+    // can't see the cleared entries anymore
+    fence(Ordering::SeqCst);
+    let this = percpu::cpu_id();
+    let mut targets = 0;
+    for index in 0..percpu::count() {
+        match percpu::get(index) {
+            Some(cpu) if index != this && cpu.cr3.load(Ordering::SeqCst) == space as usize => {
+                targets |= 1 << index;
+            }
+            _ => {}
+        }
+    }
+    PENDING.store(targets, Ordering::Release);
+    for index in 0..percpu::count() {
+        if targets & 1 << index != 0 {
+            if let Some(cpu) = percpu::get(index) {
+                apic::send_ipi(cpu.apic_id, apic::TLB_VECTOR);
+            }
+        }
+    }
+    while PENDING.load(Ordering::Acquire) != 0 {}
+}
+
+pub fn init() {
+    assert!(IDT::get().register_isr(apic::TLB_VECTOR, handler));
+}
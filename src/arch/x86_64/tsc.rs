@@ -0,0 +1,38 @@
+//! Time stamp counter
+
+use arch::fpu::cpuid;
+
+use super::timer::{self, CALIBRATE_CYCLES, PIT_FREQUENCY};
+
+/// TSC ticks per second, 0 if there's no usable TSC
+static mut FREQUENCY: u64 = 0;
+
+/// Read the time stamp counter
+#[inline]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdtsc" : "={eax}"(low), "={edx}"(high) : : : "volatile");
+    }
+    (high as u64) << 32 | low as u64
+}
+
+/// Get the TSC frequency in Hz, 0 if unknown
+pub fn frequency() -> u64 {
+    unsafe { FREQUENCY }
+}
+
+/// Measure the TSC frequency against the PIT
+pub fn init() {
+    unsafe {
+        let (_, _, _, edx) = cpuid(1, 0);
+        if edx & (1 << 4) == 0 {
+            return;
+        }
+        let start = rdtsc();
+        timer::pit_wait(CALIBRATE_CYCLES);
+        let end = rdtsc();
+        FREQUENCY = (end - start) * PIT_FREQUENCY / CALIBRATE_CYCLES;
+    }
+}
@@ -1,8 +1,4 @@
-use spin::RwLock;
-
-extern "C" {
-    static mut init_stack_end: [u64; 1];
-}
+use arch::percpu;
 
 #[repr(C, packed)]
 pub struct TSS {
@@ -18,23 +14,13 @@ pub struct TSS {
     pub iomap_base: u16,
 }
 
-pub static TSS_SC: RwLock<TSS> = RwLock::new(TSS {
-    reserved_1: 0,
-    reserved_2: 0,
-    reserved_3: 0,
-    reserved_4: 0,
-    privilege_stack_table: [0; 3],
-    interrupt_stack_table: [0; 7],
-    iomap_base: 0,
-});
-
 /// Load TR register
 pub unsafe fn ltr(seg: u16) {
     asm!("ltr $0" : : "r"(seg) : : );
 }
 
 impl TSS {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         TSS {
             reserved_1: 0,
             privilege_stack_table: [0; 3],
@@ -47,11 +33,7 @@ impl TSS {
     }
 }
 
-pub fn init() {
-    let mut tss = TSS_SC.write();
-    unsafe {
-        tss.privilege_stack_table[0] = &init_stack_end as *const _ as u64;
-        tss.interrupt_stack_table[0] = &init_stack_end as *const _ as u64;
-        ltr(super::gdt::GDT_TSS);
-    }
+/// Set the stack used when entering ring 0 on this CPU
+pub fn set_kernel_stack(rsp: u64) {
+    percpu::current().tss.privilege_stack_table[0] = rsp;
 }
@@ -1,15 +1,24 @@
+/// Errors, numbered as seen by user space
+/// The numbers are part of the system call ABI, don't change them.
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
-    EFAIL,
-    ENOMEM,
-    EFAULT,
-    EFULL,
-    ENOENT,
-    EAGAIN,
-    EIO,
-    EBADFS,
-    EINVAL,
+    EFAIL = 1,
+    ENOMEM = 2,
+    EFAULT = 3,
+    EFULL = 4,
+    ENOENT = 5,
+    EAGAIN = 6,
+    EIO = 7,
+    EBADFS = 8,
+    EINVAL = 9,
+    ENOSYS = 10,
+    EINTR = 11,
+    EPERM = 12,
+    ETIMEDOUT = 13,
+    EPIPE = 14,
+    EBADF = 15,
+    ENODEV = 16,
 }
 
 impl Error {
@@ -24,7 +33,18 @@ impl Error {
             Error::EIO => "I/O Error",
             Error::EBADFS => "Bad filesystem",
             Error::EINVAL => "Invalid argument",
-            _ => "Uncategorized error",
+            Error::ENOSYS => "Function not implemented",
+            Error::EINTR => "Interrupted",
+            Error::EPERM => "Operation not permitted",
+            Error::ETIMEDOUT => "Timed out",
+            Error::EPIPE => "Peer closed",
+            Error::EBADF => "Bad handle",
+            Error::ENODEV => "No such device",
         }
     }
+
+    /// Error number, as returned by system calls
+    pub fn errno(&self) -> i64 {
+        *self as u8 as i64
+    }
 }
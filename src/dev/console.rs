@@ -0,0 +1,93 @@
+use arch;
+use dev::Device;
+use sync::{IrqSpinLock, WaitQueue};
+
+/// Tasks waiting for input
+static INPUT: WaitQueue = WaitQueue::new();
+/// Held while checking for input before sleeping on INPUT
+static RECEIVE: IrqSpinLock<()> = IrqSpinLock::new(());
+
+/// The serial console
+pub struct Console;
+
+impl Console {
+    pub fn new() -> Self {
+        Console
+    }
+}
+
+/// Input arrived, readers fetch it
+fn interrupt(_vector: u64, _error_code: u64) {
+    {
+        let _receive = RECEIVE.lock();
+        INPUT.wake_all();
+    }
+    arch::eoi_irq(arch::SERIAL_IRQ);
+}
+
+/// Wake readers on the serial port's interrupt
+pub fn init() {
+    if !arch::register_irq(arch::SERIAL_IRQ, interrupt) {
+        println!("Console input is polled, IRQ {} is taken", arch::SERIAL_IRQ);
+        return;
+    }
+    unsafe {
+        arch::enable_serial_irq();
+    }
+    arch::unmask_irq(arch::SERIAL_IRQ);
+}
+
+/// Sleep until the serial port holds input
+/// A signal ends the wait with EINTR.
+fn wait_input() -> Result<(), ::common::error::Error> {
+    // Checked under RECEIVE, so the interrupt can't come in between
+    let receive = RECEIVE.lock();
+    if unsafe { arch::serial_received() } {
+        return Ok(());
+    }
+    INPUT.wait_interruptible(receive)
+}
+
+impl Device for Console {
+    /// Read what's received, EAGAIN if nothing is
+    /// Stops after a line feed. Readers wait with read_wait(),
+    /// so writers aren't held up by the device lock meanwhile.
+    fn read(&mut self, data: &mut [u8], _offset: u64) -> Result<usize, ::common::error::Error> {
+        let mut count = 0;
+        while count < data.len() {
+            match unsafe { arch::try_getb() } {
+                Some(b) => {
+                    data[count] = b;
+                    count += 1;
+                    if b == b'\n' {
+                        break;
+                    }
+                }
+                None if count > 0 => break,
+                None => return Err(err!(EAGAIN)),
+            }
+        }
+        Ok(count)
+    }
+
+    fn write(&mut self, data: &[u8], _offset: u64) -> Result<usize, ::common::error::Error> {
+        for b in data.iter() {
+            unsafe {
+                arch::putb(*b);
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn ioctl(&mut self, _ops: u64, _data: usize) -> Result<usize, ::common::error::Error> {
+        Err(err!(EINVAL))
+    }
+
+    fn seekable(&self) -> bool {
+        false
+    }
+
+    fn read_wait(&self) -> Option<fn() -> Result<(), ::common::error::Error>> {
+        Some(wait_input)
+    }
+}
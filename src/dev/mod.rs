@@ -1,20 +1,36 @@
-use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::sync::Arc;
 use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use sync::Mutex;
 
+mod console;
 mod device;
 
+pub use self::console::Console;
 pub use self::device::Device;
 
-pub static DEVICE_MAP: Once<RwLock<BTreeMap<String, Box<device::Device>>>> = Once::new();
+type DeviceMap = BTreeMap<String, Arc<Mutex<device::Device>>>;
 
-pub fn devices() -> RwLockReadGuard<'static, BTreeMap<String, Box<device::Device>>> {
+pub static DEVICE_MAP: Once<RwLock<DeviceMap>> = Once::new();
+
+pub fn devices() -> RwLockReadGuard<'static, DeviceMap> {
     DEVICE_MAP.call_once(|| RwLock::new(BTreeMap::new())).read()
 }
 
-pub fn devices_mut() -> RwLockWriteGuard<'static, BTreeMap<String, Box<device::Device>>> {
+pub fn devices_mut() -> RwLockWriteGuard<'static, DeviceMap> {
     DEVICE_MAP
         .call_once(|| RwLock::new(BTreeMap::new()))
         .write()
 }
+
+/// Get a device by name
+pub fn get(name: &str) -> Option<Arc<Mutex<device::Device>>> {
+    devices().get(name).cloned()
+}
+
+pub fn init() {
+    use alloc::prelude::*;
+    devices_mut().insert("console".to_string(), Arc::new(Mutex::new(Console::new())));
+    console::init();
+}
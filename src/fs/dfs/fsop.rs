@@ -1,6 +1,5 @@
 use alloc::sync::Arc;
 use common::bitmap::Bitmap;
-use common::consts::SEEK_SET;
 use core::mem::{transmute, zeroed};
 use dev::Device;
 use fs::dfs::vnop::DDirectory;
@@ -49,14 +48,15 @@ impl DFS {
     /// Mount the file system
     pub fn mount(dev: &mut Device, option: u64) -> Result<u64, ::common::error::Error> {
         // Read in the super block
-        let _ = try!(dev.seek(SEEK_SET, 0));
         let mut block: DSuperBlock = unsafe { zeroed() };
         {
             use core::mem::size_of;
             assert_eq!(size_of::<DSuperBlock>(), 512);
         }
-        if try!(dev.read(unsafe { transmute::<&mut DSuperBlock, &mut [u8; 512]>(&mut block) }))
-            != 512
+        if try!(dev.read(
+            unsafe { transmute::<&mut DSuperBlock, &mut [u8; 512]>(&mut block) },
+            0
+        )) != 512
         {
             return Err(err!(EIO));
         }
@@ -69,9 +69,9 @@ impl DFS {
         let fmblock_cnt = align!(block.blockno, (BLOCK_SIZE * 8)) / (BLOCK_SIZE * 8);
         let mut data: [u8; 512] = [0; 512];
         let mut bitmap = Bitmap::new(block.blockno as usize);
-        try!(dev.seek(SEEK_SET, 2 * (BLOCK_SIZE as u64)));
         for i in 0..fmblock_cnt {
-            if try!(dev.read(&mut data)) != 512 {
+            let position = (2 + i as u64) * (BLOCK_SIZE as u64);
+            if try!(dev.read(&mut data, position)) != 512 {
                 return Err(err!(EIO));
             }
             bitmap.set_direct(&data, (BLOCK_SIZE * i) as usize);
@@ -83,8 +83,11 @@ impl DFS {
             use core::mem::size_of;
             assert_eq!(size_of::<DINode>(), 512);
         }
-        try!(dev.seek(SEEK_SET, BLOCK_SIZE as u64));
-        if try!(dev.read(unsafe { transmute::<&mut DINode, &mut [u8; 512]>(&mut inode) })) != 512 {
+        if try!(dev.read(
+            unsafe { transmute::<&mut DINode, &mut [u8; 512]>(&mut inode) },
+            BLOCK_SIZE as u64
+        )) != 512
+        {
             return Err(err!(EIO));
         }
         if inode.magic != DINODE_MAGIC {
@@ -108,15 +111,15 @@ impl DFS {
 
 impl FileSystem for DFS {
     fn sync(&mut self) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+        Err(err!(ENOSYS))
     }
 
     fn name(&self) -> &'static str {
-        unimplemented!();
+        "dfs"
     }
 
     fn root(&mut self) -> Arc<VNode> {
-        unimplemented!();
+        self.root_node.clone()
     }
 
     fn unmount(&mut self) -> Result<(), ::common::error::Error> {
@@ -10,37 +10,40 @@ pub struct DFile {
 
 impl VNode for DFile {
     fn stat(&self) -> Result<Stat, ::common::error::Error> {
-        unimplemented!();
+        Err(err!(ENOSYS))
     }
 
-    fn read(&mut self, data: &mut [u8], offset: u64) -> Result<u64, ::common::error::Error> {
-        unimplemented!();
+    fn read(&self, _data: &mut [u8], _offset: u64) -> Result<u64, ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn write(&mut self, data: &[u8], offset: u64) -> Result<u64, ::common::error::Error> {
-        unimplemented!();
+    fn write(&self, _data: &[u8], _offset: u64) -> Result<u64, ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn chmod(&mut self, mode: u16) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn chmod(&mut self, _mode: u16) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn chown(&mut self, owner: u32) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn chown(&mut self, _owner: u32) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn chgrp(&mut self, group: u32) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn chgrp(&mut self, _group: u32) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
     fn reclaim(&mut self) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+        // Nothing is cached yet
+        Ok(())
     }
 }
 
 impl DFile {
-    pub fn new(inode: &DINode, allocator: Arc<RwLock<BlockAlloc>>) -> Self {
-        unimplemented!();
+    pub fn new(_inode: &DINode, allocator: Arc<RwLock<BlockAlloc>>) -> Self {
+        DFile {
+            allocator: allocator,
+        }
     }
 }
 
@@ -59,35 +62,36 @@ pub struct DDirectory {
 
 impl VNode for DDirectory {
     fn stat(&self) -> Result<Stat, ::common::error::Error> {
-        unimplemented!();
+        Err(err!(ENOSYS))
     }
 
-    fn creat(&mut self, name: &str, option: u32) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn creat(&mut self, _name: &str, _option: u32) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn mkdir(&mut self, name: &str) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn mkdir(&mut self, _name: &str) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn rmdir(&mut self, name: &str) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn rmdir(&mut self, _name: &str) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn chmod(&mut self, mode: u16) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn chmod(&mut self, _mode: u16) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn chown(&mut self, owner: u32) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn chown(&mut self, _owner: u32) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
-    fn chgrp(&mut self, group: u32) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+    fn chgrp(&mut self, _group: u32) -> Result<(), ::common::error::Error> {
+        Err(err!(ENOSYS))
     }
 
     fn reclaim(&mut self) -> Result<(), ::common::error::Error> {
-        unimplemented!();
+        // Nothing is cached yet
+        Ok(())
     }
 }
 
@@ -101,7 +105,9 @@ impl Drop for DDirectory {
 }
 
 impl DDirectory {
-    pub fn new(inode: &DINode, allocator: Arc<RwLock<BlockAlloc>>) -> Self {
-        unimplemented!();
+    pub fn new(_inode: &DINode, allocator: Arc<RwLock<BlockAlloc>>) -> Self {
+        DDirectory {
+            allocator: allocator,
+        }
     }
 }
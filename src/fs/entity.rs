@@ -1,14 +1,23 @@
+use alloc::sync::Arc;
 use core::ops::Drop;
 use fs::Stat;
 
 /// VNode represents an in-memory inode
 pub trait VNode: Sync + Send {
     /// Read from the node
-    fn read(&mut self, data: &mut [u8], offset: u64) -> Result<u64, ::common::error::Error> {
+    fn read(&self, data: &mut [u8], offset: u64) -> Result<u64, ::common::error::Error> {
         return Err(err!(EINVAL));
     }
     /// Write to the node
-    fn write(&mut self, data: &[u8], offset: u64) -> Result<u64, ::common::error::Error> {
+    fn write(&self, data: &[u8], offset: u64) -> Result<u64, ::common::error::Error> {
+        return Err(err!(EINVAL));
+    }
+    /// Find a node in this directory
+    fn lookup(&self, name: &str) -> Result<Arc<VNode>, ::common::error::Error> {
+        return Err(err!(ENOENT));
+    }
+    /// Get the size of the node
+    fn size(&self) -> Result<u64, ::common::error::Error> {
         return Err(err!(EINVAL));
     }
     /// Ioctl
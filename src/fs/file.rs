@@ -0,0 +1,270 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use common::consts::{SEEK_CUR, SEEK_END, SEEK_SET};
+use core::marker::{Send, Sync};
+use core::mem;
+use dev::Device;
+use fs::VNode;
+use sync::Mutex;
+
+/// Maximum open files per task
+pub const MAX_FILES: usize = 64;
+
+// Open flags
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 1;
+pub const O_RDWR: u32 = 2;
+pub const O_ACCMODE: u32 = 3;
+
+/// An open file, shared by the descriptors referring to it
+pub trait File: Sync + Send {
+    /// Read into data, returns 0 at end of file
+    fn read(&self, _data: &mut [u8]) -> Result<usize, ::common::error::Error> {
+        Err(err!(EBADF))
+    }
+    /// Write data, returns how many bytes were written
+    fn write(&self, _data: &[u8]) -> Result<usize, ::common::error::Error> {
+        Err(err!(EBADF))
+    }
+    /// Move the file offset, returns the new offset
+    fn seek(&self, _offset: i64, _whence: u32) -> Result<u64, ::common::error::Error> {
+        Err(err!(EINVAL))
+    }
+}
+
+fn readable(flags: u32) -> bool {
+    flags & O_ACCMODE != O_WRONLY
+}
+
+fn writable(flags: u32) -> bool {
+    flags & O_ACCMODE != O_RDONLY
+}
+
+/// Compute a new offset for seek()
+fn seek_to(
+    current: u64,
+    offset: i64,
+    whence: u32,
+    size: Option<u64>,
+) -> Result<u64, ::common::error::Error> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => current,
+        SEEK_END => match size {
+            None => return Err(err!(EINVAL)),
+            Some(size) => size,
+        },
+        _ => return Err(err!(EINVAL)),
+    };
+    let target = if offset < 0 {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    } else {
+        base.checked_add(offset as u64)
+    };
+    match target {
+        Some(target) if target <= i64::max_value() as u64 => Ok(target),
+        _ => Err(err!(EINVAL)),
+    }
+}
+
+/// A device opened as a file
+pub struct DeviceFile {
+    device: Arc<Mutex<Device>>,
+    flags: u32,
+    offset: Mutex<u64>,
+}
+
+impl DeviceFile {
+    pub fn new(device: Arc<Mutex<Device>>, flags: u32) -> Self {
+        DeviceFile {
+            device: device,
+            flags: flags,
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl File for DeviceFile {
+    fn read(&self, data: &mut [u8]) -> Result<usize, ::common::error::Error> {
+        if !readable(self.flags) {
+            return Err(err!(EBADF));
+        }
+        let mut offset = self.offset.lock();
+        loop {
+            // The device stays unlocked while waiting for input
+            let (result, wait) = {
+                let mut device = self.device.lock();
+                let result = device.read(data, *offset);
+                (result, device.read_wait())
+            };
+            match (result, wait) {
+                (Err(e), Some(wait)) if e == err!(EAGAIN) => try!(wait()),
+                (result, _) => {
+                    let count = try!(result);
+                    *offset += count as u64;
+                    return Ok(count);
+                }
+            };
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, ::common::error::Error> {
+        if !writable(self.flags) {
+            return Err(err!(EBADF));
+        }
+        let mut offset = self.offset.lock();
+        let count = try!(self.device.lock().write(data, *offset));
+        *offset += count as u64;
+        Ok(count)
+    }
+
+    fn seek(&self, offset: i64, whence: u32) -> Result<u64, ::common::error::Error> {
+        if !self.device.lock().seekable() {
+            return Err(err!(EINVAL));
+        }
+        let mut current = self.offset.lock();
+        *current = try!(seek_to(*current, offset, whence, None));
+        Ok(*current)
+    }
+}
+
+/// A file system node opened as a file
+pub struct NodeFile {
+    node: Arc<VNode>,
+    flags: u32,
+    offset: Mutex<u64>,
+}
+
+impl NodeFile {
+    pub fn new(node: Arc<VNode>, flags: u32) -> Self {
+        NodeFile {
+            node: node,
+            flags: flags,
+            offset: Mutex::new(0),
+        }
+    }
+}
+
+impl File for NodeFile {
+    fn read(&self, data: &mut [u8]) -> Result<usize, ::common::error::Error> {
+        if !readable(self.flags) {
+            return Err(err!(EBADF));
+        }
+        let mut offset = self.offset.lock();
+        let count = try!(self.node.read(data, *offset));
+        *offset += count;
+        Ok(count as usize)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize, ::common::error::Error> {
+        if !writable(self.flags) {
+            return Err(err!(EBADF));
+        }
+        let mut offset = self.offset.lock();
+        let count = try!(self.node.write(data, *offset));
+        *offset += count;
+        Ok(count as usize)
+    }
+
+    fn seek(&self, offset: i64, whence: u32) -> Result<u64, ::common::error::Error> {
+        let size = self.node.size().ok();
+        let mut current = self.offset.lock();
+        *current = try!(seek_to(*current, offset, whence, size));
+        Ok(*current)
+    }
+}
+
+/// Files opened by a task, indexed by descriptor
+pub struct FileTable {
+    files: Vec<Option<Arc<File>>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    /// Create a table with stdin, stdout and stderr on the console
+    pub fn stdio() -> Self {
+        let mut table = FileTable::new();
+        if let Some(console) = ::dev::get("console") {
+            let _ = table.insert(Arc::new(DeviceFile::new(console.clone(), O_RDONLY)));
+            let stdout: Arc<File> = Arc::new(DeviceFile::new(console, O_WRONLY));
+            let _ = table.insert(stdout.clone());
+            let _ = table.insert(stdout);
+        }
+        table
+    }
+
+    /// Install a file at the lowest free descriptor
+    pub fn insert(&mut self, file: Arc<File>) -> Result<usize, ::common::error::Error> {
+        match self.files.iter().position(|slot| slot.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                Ok(fd)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(Some(file));
+                Ok(self.files.len() - 1)
+            }
+            None => Err(err!(EFULL)),
+        }
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<File>, ::common::error::Error> {
+        match self.files.get(fd) {
+            Some(&Some(ref file)) => Ok(file.clone()),
+            _ => Err(err!(EBADF)),
+        }
+    }
+
+    /// Remove a descriptor, returns its file
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<File>, ::common::error::Error> {
+        match self.files.get_mut(fd).and_then(|slot| slot.take()) {
+            None => Err(err!(EBADF)),
+            Some(file) => {
+                while let Some(&None) = self.files.last() {
+                    self.files.pop();
+                }
+                Ok(file)
+            }
+        }
+    }
+
+    /// Duplicate a descriptor to the lowest free one
+    pub fn dup(&mut self, fd: usize) -> Result<usize, ::common::error::Error> {
+        let file = try!(self.get(fd));
+        self.insert(file)
+    }
+
+    /// Duplicate a descriptor to new_fd, returns the file it replaced
+    pub fn dup2(
+        &mut self,
+        fd: usize,
+        new_fd: usize,
+    ) -> Result<Option<Arc<File>>, ::common::error::Error> {
+        let file = try!(self.get(fd));
+        if new_fd >= MAX_FILES {
+            return Err(err!(EBADF));
+        }
+        if fd == new_fd {
+            return Ok(None);
+        }
+        while self.files.len() <= new_fd {
+            self.files.push(None);
+        }
+        Ok(mem::replace(&mut self.files[new_fd], Some(file)))
+    }
+
+    /// Copy the table for a child task, sharing the open files
+    pub fn fork(&self) -> FileTable {
+        FileTable {
+            files: self.files.clone(),
+        }
+    }
+
+    /// Take all files out, leaving the table empty
+    pub fn take(&mut self) -> FileTable {
+        mem::replace(self, FileTable::new())
+    }
+}
@@ -5,9 +5,13 @@ use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 mod dfs;
 mod entity;
+mod file;
 mod fs;
 
 pub use self::entity::VNode;
+pub use self::file::{
+    DeviceFile, File, FileTable, NodeFile, MAX_FILES, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY,
+};
 pub use self::fs::FileSystem;
 
 pub struct Stat {
@@ -65,6 +69,34 @@ fn insert<T: FileSystem + Sized + 'static>(fs: T) -> Result<u64, ::common::error
     Err(err!(EFULL))
 }
 
+/// Prefix of device paths
+const DEV_PREFIX: &str = "/dev/";
+
+/// Open a file by absolute path
+/// Devices are found under /dev, other paths are looked up
+/// from the root of the first mounted file system.
+pub fn open(path: &str, flags: u32) -> Result<Arc<File>, ::common::error::Error> {
+    if flags & !O_ACCMODE != 0 || flags & O_ACCMODE == O_ACCMODE || !path.starts_with('/') {
+        return Err(err!(EINVAL));
+    }
+
+    if path.starts_with(DEV_PREFIX) {
+        return match ::dev::get(&path[DEV_PREFIX.len()..]) {
+            None => Err(err!(ENOENT)),
+            Some(device) => Ok(Arc::new(DeviceFile::new(device, flags))),
+        };
+    }
+
+    let mut node = match filesystems_mut().get_mut(&0) {
+        None => return Err(err!(ENOENT)),
+        Some(fs) => fs.root(),
+    };
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        node = try!(node.lookup(name));
+    }
+    Ok(Arc::new(NodeFile::new(node, flags)))
+}
+
 pub fn init() {
     dfs::init();
 }
@@ -0,0 +1,119 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::mem;
+use core::ops::Drop;
+
+use super::port::Port;
+
+/// Send messages to the port
+pub const RIGHT_SEND: u32 = 1 << 0;
+/// Receive messages from the port, held by a single capability
+pub const RIGHT_RECEIVE: u32 = 1 << 1;
+/// Pass the capability on to other tasks
+pub const RIGHT_GRANT: u32 = 1 << 2;
+pub const RIGHT_ALL: u32 = RIGHT_SEND | RIGHT_RECEIVE | RIGHT_GRANT;
+
+const MAX_HANDLES: usize = 256;
+
+/// A reference to a port along with what its holder may do with it
+/// Dropping the capability holding the receive right closes the port.
+pub struct Capability {
+    port: Arc<Port>,
+    rights: u32,
+}
+
+impl Capability {
+    pub fn new(port: Arc<Port>, rights: u32) -> Self {
+        Capability {
+            port: port,
+            rights: rights,
+        }
+    }
+
+    pub fn port(&self) -> &Arc<Port> {
+        &self.port
+    }
+
+    pub fn rights(&self) -> u32 {
+        self.rights
+    }
+
+    /// Check if the capability has all the rights
+    pub fn allows(&self, rights: u32) -> bool {
+        self.rights & rights == rights
+    }
+
+    /// Derive a capability with a subset of the rights
+    /// The receive right can't be duplicated.
+    pub fn derive(&self, rights: u32) -> Result<Capability, ::common::error::Error> {
+        if rights & RIGHT_RECEIVE != 0 || !self.allows(rights) {
+            return Err(err!(EPERM));
+        }
+        Ok(Capability::new(self.port.clone(), rights))
+    }
+}
+
+impl Drop for Capability {
+    fn drop(&mut self) {
+        if self.rights & RIGHT_RECEIVE != 0 {
+            self.port.close();
+        }
+    }
+}
+
+/// Capabilities held by a task, indexed by handle
+pub struct HandleTable {
+    map: BTreeMap<u32, Capability>,
+    next_handle: u32,
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        HandleTable {
+            map: BTreeMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Install a capability, returns its handle
+    pub fn insert(&mut self, capability: Capability) -> Result<u32, ::common::error::Error> {
+        if self.full() {
+            return Err(err!(EFULL));
+        }
+        let mut handle = self.next_handle;
+        while self.map.contains_key(&handle) {
+            handle = (handle + 1) % MAX_HANDLES as u32;
+        }
+        self.next_handle = (handle + 1) % MAX_HANDLES as u32;
+        self.map.insert(handle, capability);
+        Ok(handle)
+    }
+
+    pub fn get(&self, handle: u32) -> Result<&Capability, ::common::error::Error> {
+        match self.map.get(&handle) {
+            None => Err(err!(EBADF)),
+            Some(capability) => Ok(capability),
+        }
+    }
+
+    pub fn remove(&mut self, handle: u32) -> Result<Capability, ::common::error::Error> {
+        match self.map.remove(&handle) {
+            None => Err(err!(EBADF)),
+            Some(capability) => Ok(capability),
+        }
+    }
+
+    /// Take all capabilities out, leaving the table empty
+    pub fn take(&mut self) -> HandleTable {
+        mem::replace(self, HandleTable::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Check if insert() would fail with EFULL
+    pub fn full(&self) -> bool {
+        self.map.len() >= MAX_HANDLES
+    }
+}
@@ -0,0 +1,221 @@
+//! Message-passing IPC
+//!
+//! Tasks talk through ports, message queues referenced by capabilities
+//! in the per-task handle table. The creator of a port holds its receive
+//! right; others obtain send rights by looking up the port's name or by
+//! being passed a capability in a message. Passing a capability moves it
+//! out of the sender's handle table. Bulk data goes through shared memory,
+//! byte streams through pipes.
+
+mod handle;
+pub mod pipe;
+mod port;
+pub mod shm;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+use sync::IrqSpinLock;
+use task;
+
+pub use self::handle::{
+    Capability, HandleTable, RIGHT_ALL, RIGHT_GRANT, RIGHT_RECEIVE, RIGHT_SEND,
+};
+pub use self::port::{Message, Port, MAX_MESSAGE_SIZE, MAX_QUEUED};
+
+/// Longest port or shared memory name
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Named ports
+static REGISTRY: Once<IrqSpinLock<BTreeMap<String, Weak<Port>>>> = Once::new();
+
+fn registry() -> &'static IrqSpinLock<BTreeMap<String, Weak<Port>>> {
+    REGISTRY.call_once(|| IrqSpinLock::new(BTreeMap::new()))
+}
+
+fn unregister(name: &str) {
+    let mut registry = registry().lock();
+    let stale = match registry.get(name) {
+        None => false,
+        Some(port) => port.upgrade().map_or(true, |port| port.closed()),
+    };
+    if stale {
+        registry.remove(name);
+    }
+}
+
+/// A received message, with the passed capability installed
+pub struct Received {
+    pub sender: u64,
+    pub data: Vec<u8>,
+    pub handle: Option<u32>,
+}
+
+/// Run f on the handle table of the current task
+fn with_handles<T, F: FnOnce(&mut HandleTable) -> Result<T, ::common::error::Error>>(
+    f: F,
+) -> Result<T, ::common::error::Error> {
+    let tasks = task::tasks();
+    match tasks.current() {
+        None => Err(err!(EPERM)),
+        Some(current_lock) => f(&mut current_lock.write().handles),
+    }
+}
+
+/// Install a capability in the handle table of the current task
+/// On failure it's handed back, to be dropped with the task list
+/// unlocked since dropping a receive right closes the port.
+fn install(capability: Capability) -> Result<u32, (::common::error::Error, Capability)> {
+    let mut capability = Some(capability);
+    let result = with_handles(|handles| {
+        if handles.full() {
+            return Err(err!(EFULL));
+        }
+        handles.insert(capability.take().unwrap())
+    });
+    // insert() can't fail once there's room
+    result.map_err(|e| (e, capability.take().unwrap()))
+}
+
+/// Get the port behind a handle, if the capability has the rights
+fn port_of(handle: u32, rights: u32) -> Result<Arc<Port>, ::common::error::Error> {
+    with_handles(|handles| {
+        let capability = try!(handles.get(handle));
+        if !capability.allows(rights) {
+            return Err(err!(EPERM));
+        }
+        Ok(capability.port().clone())
+    })
+}
+
+/// Create a port, named ports can be looked up by other tasks
+/// Returns the handle of the receive capability.
+pub fn create(name: Option<String>) -> Result<u32, ::common::error::Error> {
+    if let Some(ref name) = name {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(err!(EINVAL));
+        }
+    }
+    let port = Arc::new(Port::new(name.clone()));
+    if let Some(name) = name {
+        let mut registry = registry().lock();
+        let taken = registry
+            .get(&name)
+            .and_then(|port| port.upgrade())
+            .map_or(false, |port| !port.closed());
+        if taken {
+            return Err(err!(EAGAIN));
+        }
+        registry.insert(name, Arc::downgrade(&port));
+    }
+
+    let capability = Capability::new(port, RIGHT_ALL);
+    install(capability).map_err(|(e, _)| e)
+}
+
+/// Get a send capability for a named port
+pub fn lookup(name: &str) -> Result<u32, ::common::error::Error> {
+    let port = {
+        let registry = registry().lock();
+        registry.get(name).and_then(|port| port.upgrade())
+    };
+    match port {
+        Some(ref port) if !port.closed() => {}
+        _ => return Err(err!(ENOENT)),
+    };
+    let capability = Capability::new(port.unwrap(), RIGHT_SEND | RIGHT_GRANT);
+    install(capability).map_err(|(e, _)| e)
+}
+
+/// Send a message through a handle, optionally passing another handle
+/// The passed handle is only removed once the message is queued. A
+/// port's receive right can't be passed through the port itself.
+/// See Port::send() for the timeout.
+pub fn send(
+    handle: u32,
+    data: Vec<u8>,
+    transfer: Option<u32>,
+    timeout: Option<u64>,
+) -> Result<(), ::common::error::Error> {
+    let port = try!(port_of(handle, RIGHT_SEND));
+    let capability = match transfer {
+        None => None,
+        Some(transfer) if transfer == handle => return Err(err!(EINVAL)),
+        Some(transfer) => Some(try!(with_handles(|handles| {
+            {
+                let capability = try!(handles.get(transfer));
+                if !capability.allows(RIGHT_GRANT) {
+                    return Err(err!(EPERM));
+                }
+                // Queued in its own port, the port would never close
+                if capability.allows(RIGHT_RECEIVE) && Arc::ptr_eq(capability.port(), &port) {
+                    return Err(err!(EINVAL));
+                }
+            }
+            handles.remove(transfer)
+        }))),
+    };
+
+    let message = Message {
+        sender: task::current_tid(),
+        data: data,
+        capability: capability,
+    };
+    match port.send(message, timeout) {
+        Ok(()) => Ok(()),
+        Err((e, message)) => {
+            // Give the capability back, its slot was freed above
+            if let Some(capability) = message.capability {
+                let _ = install(capability);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Receive a message whose payload fits in max_size
+/// See Port::receive() for the timeout. If the passed capability
+/// doesn't fit in the handle table the message stays queued, EFULL.
+pub fn receive(
+    handle: u32,
+    max_size: usize,
+    timeout: Option<u64>,
+) -> Result<Received, ::common::error::Error> {
+    let port = try!(port_of(handle, RIGHT_RECEIVE));
+    let mut message = try!(port.receive(max_size, timeout));
+    let handle = match message.capability.take() {
+        None => None,
+        Some(capability) => match install(capability) {
+            Ok(handle) => Some(handle),
+            Err((e, capability)) => {
+                // Leave the message for a receive with room for the handle
+                message.capability = Some(capability);
+                let _ = port.requeue(message);
+                return Err(e);
+            }
+        },
+    };
+    Ok(Received {
+        sender: message.sender,
+        data: message.data,
+        handle: handle,
+    })
+}
+
+/// Duplicate a handle with a subset of its rights
+pub fn dup(handle: u32, rights: u32) -> Result<u32, ::common::error::Error> {
+    with_handles(|handles| {
+        let capability = try!(try!(handles.get(handle)).derive(rights));
+        handles.insert(capability)
+    })
+}
+
+/// Close a handle, closing the port if it holds the receive right
+pub fn close(handle: u32) -> Result<(), ::common::error::Error> {
+    let capability = try!(with_handles(|handles| handles.remove(handle)));
+    // Dropped with the task list unlocked
+    drop(capability);
+    Ok(())
+}
@@ -0,0 +1,138 @@
+//! Anonymous pipes
+//!
+//! A pipe is a bounded byte buffer with a read end and a write end.
+//! Reads block while it's empty and return 0 once all writers are gone,
+//! writes block while it's full and fail with EPIPE, raising SIGPIPE,
+//! once all readers are gone.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::cmp::min;
+use core::ops::Drop;
+use fs::File;
+use sync::{IrqSpinLock, WaitQueue};
+use task;
+use task::signal;
+
+/// Capacity of a pipe, in bytes
+pub const PIPE_SIZE: usize = 4096;
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+struct Pipe {
+    state: IrqSpinLock<PipeState>,
+    // Readers waiting for data
+    readable: WaitQueue,
+    // Writers waiting for room
+    writable: WaitQueue,
+}
+
+/// Read end of a pipe
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+/// Write end of a pipe
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+/// Create a pipe, returns its read and write ends
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        state: IrqSpinLock::new(PipeState {
+            buffer: VecDeque::with_capacity(PIPE_SIZE),
+            readers: 1,
+            writers: 1,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (PipeReader { pipe: pipe.clone() }, PipeWriter { pipe: pipe })
+}
+
+impl File for PipeReader {
+    /// Read what's available, waiting for at least one byte
+    fn read(&self, data: &mut [u8]) -> Result<usize, ::common::error::Error> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut state = self.pipe.state.lock();
+            if !state.buffer.is_empty() {
+                let count = min(data.len(), state.buffer.len());
+                for (byte, value) in data.iter_mut().zip(state.buffer.drain(..count)) {
+                    *byte = value;
+                }
+                self.pipe.writable.wake_all();
+                return Ok(count);
+            }
+            if state.writers == 0 {
+                return Ok(0);
+            }
+            try!(self.pipe.readable.wait_interruptible(state));
+        }
+    }
+}
+
+impl File for PipeWriter {
+    /// Write all of data, waiting for room as needed
+    /// If the readers go away midway the partial count is returned.
+    fn write(&self, data: &[u8]) -> Result<usize, ::common::error::Error> {
+        let mut written = 0;
+        while written < data.len() {
+            let mut state = self.pipe.state.lock();
+            if state.readers == 0 {
+                // What's written so far counts, the next write fails
+                if written > 0 {
+                    return Ok(written);
+                }
+                drop(state);
+                let _ = signal::send(task::current_tid(), signal::SIGPIPE);
+                return Err(err!(EPIPE));
+            }
+            let room = PIPE_SIZE - state.buffer.len();
+            if room == 0 {
+                // What's written so far counts, the signal is taken anyway
+                match self.pipe.writable.wait_interruptible(state) {
+                    Err(_) if written > 0 => return Ok(written),
+                    Err(e) => return Err(e),
+                    Ok(()) => continue,
+                }
+            }
+            let count = min(room, data.len() - written);
+            state
+                .buffer
+                .extend(data[written..written + count].iter().cloned());
+            written += count;
+            self.pipe.readable.wake_all();
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            // Writers fail from now on
+            self.pipe.writable.wake_all();
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.pipe.state.lock();
+        state.writers -= 1;
+        if state.writers == 0 {
+            // Readers see the end of file
+            self.pipe.readable.wake_all();
+        }
+    }
+}
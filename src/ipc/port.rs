@@ -0,0 +1,174 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use sync::{IrqSpinLock, WaitQueue};
+use time;
+
+use super::handle::Capability;
+
+/// Largest message payload, in bytes
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+/// Messages queued on a port before senders block
+pub const MAX_QUEUED: usize = 32;
+
+pub struct Message {
+    /// TID of the sending task
+    pub sender: u64,
+    pub data: Vec<u8>,
+    /// Capability passed along with the message
+    pub capability: Option<Capability>,
+}
+
+struct PortState {
+    messages: VecDeque<Message>,
+    closed: bool,
+}
+
+/// A message queue
+/// A port is closed when its receive right is dropped,
+/// senders then get EPIPE.
+pub struct Port {
+    name: Option<String>,
+    state: IrqSpinLock<PortState>,
+    // Receivers waiting for a message
+    readable: WaitQueue,
+    // Senders waiting for room
+    writable: WaitQueue,
+}
+
+/// Time left until a deadline, None waits forever
+fn remaining(deadline: Option<u64>) -> Option<u64> {
+    deadline.map(|deadline| deadline.saturating_sub(time::ticks()))
+}
+
+impl Port {
+    pub fn new(name: Option<String>) -> Self {
+        Port {
+            name: name,
+            state: IrqSpinLock::new(PortState {
+                messages: VecDeque::new(),
+                closed: false,
+            }),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| name.as_str())
+    }
+
+    pub fn closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    /// Queue a message
+    /// Waits for room at most timeout ticks, None waits forever
+    /// and zero fails with EAGAIN if the queue is full. A signal
+    /// ends the wait with EINTR.
+    /// The message is handed back along with the error.
+    pub fn send(
+        &self,
+        message: Message,
+        timeout: Option<u64>,
+    ) -> Result<(), (::common::error::Error, Message)> {
+        if message.data.len() > MAX_MESSAGE_SIZE {
+            return Err((err!(EINVAL), message));
+        }
+        let deadline = timeout.map(|timeout| time::ticks() + timeout);
+        loop {
+            let mut state = self.state.lock();
+            if state.closed {
+                return Err((err!(EPIPE), message));
+            }
+            if state.messages.len() < MAX_QUEUED {
+                state.messages.push_back(message);
+                self.readable.wake_one();
+                return Ok(());
+            }
+            let waited = match remaining(deadline) {
+                None => self.writable.wait_interruptible(state),
+                Some(0) if timeout == Some(0) => return Err((err!(EAGAIN), message)),
+                Some(0) => return Err((err!(ETIMEDOUT), message)),
+                Some(ticks) => self
+                    .writable
+                    .wait_timeout_interruptible(state, ticks)
+                    .map(|_| ()),
+            };
+            if let Err(e) = waited {
+                return Err((e, message));
+            }
+        }
+    }
+
+    /// Take the oldest message, as long as its payload fits in max_size
+    /// Waits as send() does. A message which doesn't fit is left
+    /// queued and EFULL is returned.
+    pub fn receive(
+        &self,
+        max_size: usize,
+        timeout: Option<u64>,
+    ) -> Result<Message, ::common::error::Error> {
+        let deadline = timeout.map(|timeout| time::ticks() + timeout);
+        loop {
+            let mut state = self.state.lock();
+            let fits = state.messages.front().map(|message| message.data.len() <= max_size);
+            match fits {
+                Some(true) => {
+                    let message = state.messages.pop_front().unwrap();
+                    self.writable.wake_one();
+                    return Ok(message);
+                }
+                Some(false) => return Err(err!(EFULL)),
+                None => {}
+            };
+            if state.closed {
+                return Err(err!(EPIPE));
+            }
+            match remaining(deadline) {
+                None => try!(self.readable.wait_interruptible(state)),
+                Some(0) if timeout == Some(0) => return Err(err!(EAGAIN)),
+                Some(0) => return Err(err!(ETIMEDOUT)),
+                Some(ticks) => {
+                    try!(self.readable.wait_timeout_interruptible(state, ticks));
+                }
+            };
+        }
+    }
+
+    /// Put a received message back at the head of the queue
+    /// The message is handed back if the port was closed meanwhile.
+    pub fn requeue(&self, message: Message) -> Result<(), Message> {
+        let mut state = self.state.lock();
+        if state.closed {
+            return Err(message);
+        }
+        state.messages.push_front(message);
+        self.readable.wake_one();
+        Ok(())
+    }
+
+    /// Number of queued messages
+    pub fn pending(&self) -> usize {
+        self.state.lock().messages.len()
+    }
+
+    /// Close the port, discarding queued messages and waking all waiters
+    pub fn close(&self) {
+        let messages = {
+            let mut state = self.state.lock();
+            if state.closed {
+                return;
+            }
+            state.closed = true;
+            self.readable.wake_all();
+            self.writable.wake_all();
+            state.messages.split_off(0)
+        };
+        // Capabilities in the messages may close other ports
+        drop(messages);
+        if let Some(ref name) = self.name {
+            super::unregister(name);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Named shared memory
+//!
+//! A shared memory object owns a set of physical pages which tasks map
+//! into their address spaces. Mappings hold their own references to the
+//! pages, so an unlinked object lives on until it's unmapped everywhere.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arch;
+use core::ops::Drop;
+use spin::Once;
+use sync::IrqSpinLock;
+use task;
+
+/// Page size of shared mappings
+pub const PAGE_SIZE: u64 = 0x1000;
+/// Largest shared memory object, in pages
+pub const MAX_PAGES: usize = 128;
+
+pub struct SharedMemory {
+    name: String,
+    // Cleared when allocated
+    frames: Vec<u64>,
+}
+
+impl SharedMemory {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Size in bytes
+    pub fn size(&self) -> u64 {
+        self.frames.len() as u64 * PAGE_SIZE
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        arch::free_frames(&self.frames);
+    }
+}
+
+static OBJECTS: Once<IrqSpinLock<BTreeMap<String, Arc<SharedMemory>>>> = Once::new();
+
+fn objects() -> &'static IrqSpinLock<BTreeMap<String, Arc<SharedMemory>>> {
+    OBJECTS.call_once(|| IrqSpinLock::new(BTreeMap::new()))
+}
+
+fn get(name: &str) -> Result<Arc<SharedMemory>, ::common::error::Error> {
+    match objects().lock().get(name) {
+        None => Err(err!(ENOENT)),
+        Some(object) => Ok(object.clone()),
+    }
+}
+
+/// Create a named object of size bytes, rounded up to pages
+pub fn create(name: String, size: u64) -> Result<(), ::common::error::Error> {
+    if name.is_empty() || name.len() > super::MAX_NAME_LEN {
+        return Err(err!(EINVAL));
+    }
+    if size == 0 || size > MAX_PAGES as u64 * PAGE_SIZE {
+        return Err(err!(EINVAL));
+    }
+    let pages = (align!(size, PAGE_SIZE) / PAGE_SIZE) as usize;
+    if objects().lock().contains_key(&name) {
+        return Err(err!(EAGAIN));
+    }
+
+    let object = Arc::new(SharedMemory {
+        name: name.clone(),
+        frames: try!(arch::alloc_frames(pages)),
+    });
+    let mut objects = objects().lock();
+    if objects.contains_key(&name) {
+        return Err(err!(EAGAIN));
+    }
+    objects.insert(name, object);
+    Ok(())
+}
+
+/// Remove a name, the pages stay as long as they're mapped
+pub fn unlink(name: &str) -> Result<(), ::common::error::Error> {
+    let object = objects().lock().remove(name);
+    match object {
+        None => Err(err!(ENOENT)),
+        // Freed with the registry unlocked
+        Some(object) => {
+            drop(object);
+            Ok(())
+        }
+    }
+}
+
+/// Map a whole object into the current task at address
+/// Returns the address and the size of the mapping.
+pub fn map(name: &str, address: u64, writable: bool) -> Result<(u64, u64), ::common::error::Error> {
+    let object = try!(get(name));
+    let tasks = task::tasks();
+    let current_lock = match tasks.current() {
+        None => return Err(err!(EPERM)),
+        Some(current_lock) => current_lock,
+    };
+    let mut current = current_lock.write();
+    try!(current.context.map_shared(address, &object.frames, writable));
+    Ok((address, object.size()))
+}
+
+/// Unmap size bytes of the current task from address
+pub fn unmap(address: u64, size: u64) -> Result<(), ::common::error::Error> {
+    if size > arch::USER_LIMIT {
+        return Err(err!(EINVAL));
+    }
+    let pages = (align!(size, PAGE_SIZE) / PAGE_SIZE) as usize;
+    let unmapped = {
+        let tasks = task::tasks();
+        let current_lock = match tasks.current() {
+            None => return Err(err!(EPERM)),
+            Some(current_lock) => current_lock,
+        };
+        let unmapped = try!(current_lock.write().context.unmap(address, pages));
+        unmapped
+    };
+    // Other CPUs may spin on the task list with interrupts off
+    unmapped.finish();
+    Ok(())
+}
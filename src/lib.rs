@@ -1,6 +1,7 @@
 #![feature(panic_implementation)]
 #![feature(panic_info_message)]
 #![feature(asm)]
+#![feature(const_fn)]
 #![feature(lang_items)]
 #![feature(alloc)]
 #![feature(alloc_error_handler)]
@@ -22,16 +23,19 @@ mod common;
 mod debug;
 mod dev;
 mod fs;
+mod ipc;
 mod panic;
+mod sync;
+mod syscall;
 mod task;
+mod time;
 
 #[cfg(target_arch = "x86_64")]
 #[path = "arch/x86_64/mod.rs"]
 pub mod arch;
 
-use core::ops::DerefMut;
+use core::alloc::{GlobalAlloc, Layout};
 use linked_list_allocator::LockedHeap;
-use task::Task;
 
 #[panic_implementation]
 #[no_mangle]
@@ -40,8 +44,25 @@ pub fn panic_implementation(info: &::core::panic::PanicInfo) -> ! {
     loop {}
 }
 
+/// The kernel heap
+/// Locked with interrupts disabled, so interrupt handlers such as
+/// the scheduler and timer callbacks may allocate and free.
+struct KernelHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _irq = arch::IrqGuard::new();
+        self.0.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _irq = arch::IrqGuard::new();
+        self.0.dealloc(ptr, layout)
+    }
+}
+
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelHeap = KernelHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 #[no_mangle]
@@ -54,8 +75,6 @@ pub unsafe fn test() {
     let result = arch::getb();
     println!("Get: {}", result);
 
-    let mut ctx1: *mut Task = 0 as *mut Task;
-    let mut ctx2: *mut Task = 0 as *mut Task;
     {
         let mut tasks = task::tasks_mut();
         {
@@ -66,8 +85,8 @@ pub unsafe fn test() {
             task.context.write(address.offset(1), 0x02);
             task.context.write(address.offset(2), 0xeb);
             task.context.write(address.offset(3), 0xfe);
-            task.context.rip = 0x1000;
-            ctx1 = task.deref_mut() as *mut Task;
+            task.context.frame().rip = 0x1000;
+            task::wakeup(&mut task);
         }
 
         {
@@ -76,35 +95,40 @@ pub unsafe fn test() {
             let address2 = task2.context.map(0).expect("Failed to map address") as *mut u8;
             task2.context.write(address2, 0xeb);
             task2.context.write(address2.offset(1), 0xfe);
-            task2.context.rip = 0x1000;
-            ctx2 = task2.deref_mut() as *mut Task;
+            task2.context.frame().rip = 0x1000;
+            task::wakeup(&mut task2);
         }
-
-        task::set_current_tid((*ctx1).tid());
-        (*ctx1).status = task::TaskStatus::Running;
-        (*ctx2).status = task::TaskStatus::Ready;
     }
-    (*ctx1).context.switch_to();
 
     //arch::enable_int();
 }
 
 #[no_mangle]
-pub extern "C" fn kentry() {
+pub extern "C" fn kentry(boot_info: u64) {
     println!("Hello!");
 
     // Initialize architecture-dependent features
-    arch::init();
+    arch::init(boot_info);
     // Initialize heap
     unsafe {
         ALLOCATOR
+            .0
             .lock()
             .init(arch::HEAP_VIRT as usize, arch::HEAP_SIZE as usize);
     }
+    // Set up timers before the timer interrupt runs them
+    time::init();
+    // Register devices
+    dev::init();
     // Second phase architecture-dependent initialization
     arch::init2();
     // Initialize task scheduler
     task::init();
+    // Initialize system calls
+    syscall::init();
+    // Start the other processors
+    arch::start_cpus();
 
-    loop {}
+    // Become the idle task
+    task::idle();
 }
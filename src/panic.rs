@@ -1,3 +1,29 @@
+use arch;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// What the kernel does once a panic is reported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicAction {
+    Halt,
+    PowerOff,
+    Reboot,
+}
+
+static ON_PANIC: AtomicUsize = AtomicUsize::new(PanicAction::Halt as usize);
+
+/// Set what the kernel does on panic, it halts by default
+pub fn set_action(action: PanicAction) {
+    ON_PANIC.store(action as usize, Ordering::Relaxed);
+}
+
+pub fn action() -> PanicAction {
+    match ON_PANIC.load(Ordering::Relaxed) {
+        x if x == PanicAction::PowerOff as usize => PanicAction::PowerOff,
+        x if x == PanicAction::Reboot as usize => PanicAction::Reboot,
+        _ => PanicAction::Halt,
+    }
+}
+
 pub fn panic_handler(info: &::core::panic::PanicInfo) {
     println!("PANIC!");
     if let Some(location) = info.location() {
@@ -11,4 +37,10 @@ pub fn panic_handler(info: &::core::panic::PanicInfo) {
     if let Some(payload) = info.payload().downcast_ref::<&str>() {
         println!("Payload: {}", payload);
     }
+
+    match action() {
+        PanicAction::Halt => {}
+        PanicAction::PowerOff => arch::power_off(),
+        PanicAction::Reboot => arch::reboot(),
+    }
 }
@@ -0,0 +1,57 @@
+use super::{MutexGuard, WaitQueue};
+
+/// A condition variable used along with a Mutex
+pub struct CondVar {
+    queue: WaitQueue,
+}
+
+impl CondVar {
+    pub const fn new() -> Self {
+        CondVar {
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Release the mutex and sleep until notified,
+    /// the mutex is taken again before returning.
+    /// Wakeups may be spurious, check the condition in a loop.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        self.queue.wait(guard);
+        mutex.lock()
+    }
+
+    /// Wait as wait(), but at most timeout ticks
+    /// The flag is false if the timeout expired.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: u64,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex();
+        let woken = self.queue.wait_timeout(guard, timeout);
+        (mutex.lock(), woken)
+    }
+
+    /// Sleep while condition holds
+    pub fn wait_while<'a, T: ?Sized, F: FnMut(&mut T) -> bool>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake a waiting task
+    pub fn notify_one(&self) -> bool {
+        self.queue.wake_one()
+    }
+
+    /// Wake all waiting tasks
+    pub fn notify_all(&self) -> usize {
+        self.queue.wake_all()
+    }
+}
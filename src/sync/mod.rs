@@ -0,0 +1,20 @@
+//! Kernel synchronization primitives
+//!
+//! IrqSpinLock protects data shared with interrupt handlers, it keeps
+//! interrupts disabled while held so the holder can't be preempted.
+//! The other primitives put contending tasks to sleep on a WaitQueue
+//! and must not be used from interrupt handlers.
+
+mod condvar;
+mod mutex;
+mod rwlock;
+mod semaphore;
+mod spinlock;
+mod wait;
+
+pub use self::condvar::CondVar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::Semaphore;
+pub use self::spinlock::{IrqSpinLock, IrqSpinLockGuard};
+pub use self::wait::WaitQueue;
@@ -0,0 +1,85 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut, Drop};
+
+use super::{IrqSpinLock, WaitQueue};
+
+/// A mutual exclusion lock which sleeps while contended
+pub struct Mutex<T: ?Sized> {
+    locked: IrqSpinLock<bool>,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            locked: IrqSpinLock::new(false),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Take the lock, sleeping until it's released
+    pub fn lock(&self) -> MutexGuard<T> {
+        loop {
+            let mut locked = self.locked.lock();
+            if !*locked {
+                *locked = true;
+                return MutexGuard { mutex: self };
+            }
+            self.queue.wait(locked);
+        }
+    }
+
+    /// Take the lock if it's free
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let mut locked = self.locked.lock();
+        if *locked {
+            return None;
+        }
+        *locked = true;
+        Some(MutexGuard { mutex: self })
+    }
+
+    fn unlock(&self) {
+        let mut locked = self.locked.lock();
+        *locked = false;
+        self.queue.wake_one();
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Get the mutex this guard belongs to
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
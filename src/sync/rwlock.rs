@@ -0,0 +1,145 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut, Drop};
+
+use super::{IrqSpinLock, WaitQueue};
+
+struct State {
+    readers: usize,
+    writer: bool,
+    // readers back off while writers wait, so writers don't starve
+    waiting_writers: usize,
+}
+
+/// A reader-writer lock which sleeps while contended
+pub struct RwLock<T: ?Sized> {
+    state: IrqSpinLock<State>,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: IrqSpinLock::new(State {
+                readers: 0,
+                writer: false,
+                waiting_writers: 0,
+            }),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Take a shared lock
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        loop {
+            let mut state = self.state.lock();
+            if !state.writer && state.waiting_writers == 0 {
+                state.readers += 1;
+                return RwLockReadGuard { lock: self };
+            }
+            self.queue.wait(state);
+        }
+    }
+
+    /// Take an exclusive lock
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let mut waiting = false;
+        loop {
+            let mut state = self.state.lock();
+            if !state.writer && state.readers == 0 {
+                if waiting {
+                    state.waiting_writers -= 1;
+                }
+                state.writer = true;
+                return RwLockWriteGuard { lock: self };
+            }
+            if !waiting {
+                state.waiting_writers += 1;
+                waiting = true;
+            }
+            self.queue.wait(state);
+        }
+    }
+
+    /// Take a shared lock if it's available
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let mut state = self.state.lock();
+        if state.writer || state.waiting_writers > 0 {
+            return None;
+        }
+        state.readers += 1;
+        Some(RwLockReadGuard { lock: self })
+    }
+
+    /// Take an exclusive lock if it's available
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let mut state = self.state.lock();
+        if state.writer || state.readers > 0 {
+            return None;
+        }
+        state.writer = true;
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    fn read_unlock(&self) {
+        let mut state = self.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.queue.wake_all();
+        }
+    }
+
+    fn write_unlock(&self) {
+        let mut state = self.state.lock();
+        state.writer = false;
+        self.queue.wake_all();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
@@ -0,0 +1,50 @@
+use super::{IrqSpinLock, WaitQueue};
+
+/// A counting semaphore
+pub struct Semaphore {
+    count: IrqSpinLock<usize>,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Semaphore {
+            count: IrqSpinLock::new(count),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Take a unit, sleeping until one is available
+    pub fn down(&self) {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                *count -= 1;
+                return;
+            }
+            self.queue.wait(count);
+        }
+    }
+
+    /// Take a unit if one is available
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    /// Release a unit and wake a waiter
+    pub fn up(&self) {
+        let mut count = self.count.lock();
+        *count += 1;
+        self.queue.wake_one();
+    }
+
+    /// Get the number of available units
+    pub fn count(&self) -> usize {
+        *self.count.lock()
+    }
+}
@@ -0,0 +1,86 @@
+use arch::IrqGuard;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut, Drop};
+use core::sync::atomic::{spin_loop_hint, AtomicBool, Ordering};
+
+/// A spinlock which disables interrupts while held
+pub struct IrqSpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for IrqSpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for IrqSpinLock<T> {}
+
+pub struct IrqSpinLockGuard<'a, T: ?Sized + 'a> {
+    locked: &'a AtomicBool,
+    data: &'a mut T,
+    // dropped after the lock is released
+    _irq: IrqGuard,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        IrqSpinLock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    /// Disable interrupts and spin until the lock is taken
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let irq = IrqGuard::new();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                spin_loop_hint();
+            }
+        }
+        IrqSpinLockGuard {
+            locked: &self.locked,
+            data: unsafe { &mut *self.data.get() },
+            _irq: irq,
+        }
+    }
+
+    /// Take the lock if it's free
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<T>> {
+        let irq = IrqGuard::new();
+        match self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => Some(IrqSpinLockGuard {
+                locked: &self.locked,
+                data: unsafe { &mut *self.data.get() },
+                _irq: irq,
+            }),
+            Err(_) => None,
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for IrqSpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for IrqSpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.data
+    }
+}
+
+impl<'a, T: ?Sized> Drop for IrqSpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
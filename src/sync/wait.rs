@@ -0,0 +1,202 @@
+use core::marker::Send;
+use core::ptr;
+use task::{self, signal};
+use time;
+
+use super::IrqSpinLock;
+
+/// A task sleeping on a queue
+/// Lives on the stack of the waiting task, which stays put while it
+/// sleeps, and is linked into the queue until woken or it gives up.
+struct Waiter {
+    tid: u64,
+    /// Order of queueing
+    ticket: u64,
+    prev: *mut Waiter,
+    next: *mut Waiter,
+    queued: bool,
+}
+
+/// Waiters in the order they started waiting
+struct Waiters {
+    head: *mut Waiter,
+    tail: *mut Waiter,
+    next_ticket: u64,
+}
+
+// Only touched with the queue locked
+unsafe impl Send for Waiters {}
+
+impl Waiters {
+    unsafe fn push(&mut self, waiter: *mut Waiter) {
+        (*waiter).prev = self.tail;
+        (*waiter).next = ptr::null_mut();
+        if self.tail.is_null() {
+            self.head = waiter;
+        } else {
+            (*self.tail).next = waiter;
+        }
+        self.tail = waiter;
+        (*waiter).queued = true;
+        (*waiter).ticket = self.next_ticket;
+        self.next_ticket += 1;
+    }
+
+    unsafe fn remove(&mut self, waiter: *mut Waiter) {
+        if !(*waiter).queued {
+            return;
+        }
+        if (*waiter).prev.is_null() {
+            self.head = (*waiter).next;
+        } else {
+            (*(*waiter).prev).next = (*waiter).next;
+        }
+        if (*waiter).next.is_null() {
+            self.tail = (*waiter).prev;
+        } else {
+            (*(*waiter).next).prev = (*waiter).prev;
+        }
+        (*waiter).queued = false;
+    }
+
+    /// Take the oldest waiter queued before ticket, returns its TID
+    fn pop(&mut self, ticket: u64) -> Option<u64> {
+        unsafe {
+            let waiter = self.head;
+            if waiter.is_null() || (*waiter).ticket >= ticket {
+                return None;
+            }
+            self.remove(waiter);
+            Some((*waiter).tid)
+        }
+    }
+}
+
+/// A queue of sleeping tasks
+/// Waiters are linked into the queue and keyed by its address in
+/// the tasks, so it must not move while tasks wait on it.
+pub struct WaitQueue {
+    waiters: IrqSpinLock<Waiters>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: IrqSpinLock::new(Waiters {
+                head: 0 as *mut Waiter,
+                tail: 0 as *mut Waiter,
+                next_ticket: 0,
+            }),
+        }
+    }
+
+    fn channel(&self) -> usize {
+        self as *const WaitQueue as usize
+    }
+
+    /// Block the current task and queue it, false if it can't block
+    fn enqueue(&self, waiter: &mut Waiter, timeout: u64, interruptible: bool) -> bool {
+        if !task::sleep_on_timeout(self.channel(), timeout, interruptible) {
+            return false;
+        }
+        waiter.tid = task::current_tid();
+        unsafe { self.waiters.lock().push(waiter) };
+        true
+    }
+
+    /// Unlink a waiter which wasn't woken through the queue
+    fn dequeue(&self, waiter: &mut Waiter) {
+        unsafe { self.waiters.lock().remove(waiter) };
+    }
+
+    /// Put the current task to sleep until woken
+    /// The task is queued before guard is dropped, so a wakeup
+    /// issued right after releasing it isn't lost. Callers must
+    /// check their condition again, wakeups may be spurious.
+    pub fn wait<G>(&self, guard: G) {
+        self.sleep(guard, 0, false);
+    }
+
+    /// Sleep as wait(), but at most timeout ticks
+    /// Returns false if the timeout expired.
+    pub fn wait_timeout<G>(&self, guard: G, timeout: u64) -> bool {
+        if timeout == 0 {
+            return false;
+        }
+        self.sleep(guard, timeout, false)
+    }
+
+    /// Sleep as wait(), EINTR if a signal ended the wait
+    pub fn wait_interruptible<G>(&self, guard: G) -> Result<(), ::common::error::Error> {
+        self.sleep(guard, 0, true);
+        if signal::pending() {
+            return Err(err!(EINTR));
+        }
+        Ok(())
+    }
+
+    /// Sleep as wait_timeout(), EINTR if a signal ended the wait
+    pub fn wait_timeout_interruptible<G>(
+        &self,
+        guard: G,
+        timeout: u64,
+    ) -> Result<bool, ::common::error::Error> {
+        if timeout == 0 {
+            return Ok(false);
+        }
+        let woken = self.sleep(guard, timeout, true);
+        if signal::pending() {
+            return Err(err!(EINTR));
+        }
+        Ok(woken)
+    }
+
+    /// Sleep for at most timeout ticks, 0 for no limit
+    fn sleep<G>(&self, guard: G, timeout: u64, interruptible: bool) -> bool {
+        let deadline = time::ticks() + timeout;
+        let mut waiter = Waiter {
+            tid: 0,
+            ticket: 0,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            queued: false,
+        };
+        let queued = self.enqueue(&mut waiter, timeout, interruptible);
+        drop(guard);
+        task::yield_now();
+        if queued {
+            self.dequeue(&mut waiter);
+        }
+        timeout == 0 || time::ticks() < deadline
+    }
+
+    /// Wake the task which has waited longest
+    /// Cheap if nobody waits, the queue isn't walked.
+    pub fn wake_one(&self) -> bool {
+        self.wake(1) == 1
+    }
+
+    /// Wake all waiting tasks, returns how many were woken
+    pub fn wake_all(&self) -> usize {
+        self.wake(usize::max_value())
+    }
+
+    /// Wake up to count tasks queued so far
+    /// Tasks queueing again meanwhile wait for the next wakeup.
+    fn wake(&self, count: usize) -> usize {
+        let ticket = self.waiters.lock().next_ticket;
+        let mut woken = 0;
+        while woken < count {
+            // Unlocked before waking, the task may run right away
+            let tid = match self.waiters.lock().pop(ticket) {
+                None => break,
+                Some(tid) => tid,
+            };
+            // It may have stopped waiting already
+            if task::wake(tid, self.channel()) {
+                woken += 1;
+            }
+        }
+        woken
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use fs;
+use fs::File;
+use task;
+
+use super::{copy_from_user, copy_to_user, SyscallResult};
+
+/// Largest transfer of a single read or write
+const MAX_IO: u64 = 0x10000;
+/// Longest path
+const MAX_PATH: u64 = 256;
+
+fn file_of(fd: u64) -> Result<Arc<File>, ::common::error::Error> {
+    let tasks = task::tasks();
+    match tasks.current() {
+        None => Err(err!(EBADF)),
+        Some(current_lock) => current_lock.read().files.get(fd as usize),
+    }
+}
+
+/// Run f on the file table of the current task
+fn with_files<T, F: FnOnce(&mut fs::FileTable) -> Result<T, ::common::error::Error>>(
+    f: F,
+) -> Result<T, ::common::error::Error> {
+    let tasks = task::tasks();
+    match tasks.current() {
+        None => Err(err!(EBADF)),
+        Some(current_lock) => f(&mut current_lock.write().files),
+    }
+}
+
+pub fn open(path: u64, len: u64, flags: u64) -> SyscallResult {
+    if len > MAX_PATH || flags > u32::max_value() as u64 {
+        return Err(err!(EINVAL));
+    }
+    let path = try!(String::from_utf8(try!(copy_from_user(path, len))).map_err(|_| err!(EINVAL)));
+    let file = try!(fs::open(&path, flags as u32));
+    // Only a reference is dropped inside if the table is full,
+    // the file is closed with the task list unlocked
+    let fd = with_files(|files| files.insert(file.clone()));
+    drop(file);
+    fd.map(|fd| fd as u64)
+}
+
+/// Move the offset of a file, offset is signed
+pub fn lseek(fd: u64, offset: u64, whence: u64) -> SyscallResult {
+    if whence > u32::max_value() as u64 {
+        return Err(err!(EINVAL));
+    }
+    let file = try!(file_of(fd));
+    file.seek(offset as i64, whence as u32)
+}
+
+pub fn dup(fd: u64) -> SyscallResult {
+    with_files(|files| files.dup(fd as usize)).map(|fd| fd as u64)
+}
+
+pub fn dup2(fd: u64, new_fd: u64) -> SyscallResult {
+    let replaced = try!(with_files(|files| files.dup2(fd as usize, new_fd as usize)));
+    // Closed with the task list unlocked
+    drop(replaced);
+    Ok(new_fd)
+}
+
+pub fn read(fd: u64, buffer: u64, len: u64) -> SyscallResult {
+    let file = try!(file_of(fd));
+    try!(super::check_user(buffer, len, true));
+    let mut data: Vec<u8> = vec![0; if len > MAX_IO { MAX_IO } else { len } as usize];
+    // May block, no lock is held here
+    let count = try!(file.read(&mut data));
+    try!(copy_to_user(buffer, &data[..count]));
+    Ok(count as u64)
+}
+
+pub fn write(fd: u64, buffer: u64, len: u64) -> SyscallResult {
+    let file = try!(file_of(fd));
+    let len = if len > MAX_IO { MAX_IO } else { len };
+    let data = try!(copy_from_user(buffer, len));
+    file.write(&data).map(|count| count as u64)
+}
+
+pub fn close(fd: u64) -> SyscallResult {
+    let file = {
+        let tasks = task::tasks();
+        match tasks.current() {
+            None => return Err(err!(EBADF)),
+            Some(current_lock) => try!(current_lock.write().files.remove(fd as usize)),
+        }
+    };
+    // Dropped with the task list unlocked
+    drop(file);
+    Ok(0)
+}
@@ -0,0 +1,185 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::slice;
+use fs::{self, File};
+use ipc;
+use task;
+use time;
+
+use super::{copy_from_user, copy_to_user, SyscallResult};
+
+/// Handle argument meaning none
+const NO_HANDLE: u64 = !0;
+/// Timeout argument meaning wait forever
+const TIMEOUT_INFINITE: u64 = !0;
+
+// Protection of shared memory mappings
+const PROT_READ: u64 = 1 << 0;
+const PROT_WRITE: u64 = 1 << 1;
+
+/// Filled in by port_receive
+#[repr(C)]
+struct ReceiveInfo {
+    sender: u64,
+    size: u64,
+    /// Handle of the passed capability, or NO_HANDLE
+    handle: u64,
+}
+
+/// Timeouts are given in milliseconds
+fn timeout_of(timeout: u64) -> Option<u64> {
+    match timeout {
+        TIMEOUT_INFINITE => None,
+        ms => Some(time::ms_to_ticks(ms)),
+    }
+}
+
+fn handle_of(handle: u64) -> Result<u32, ::common::error::Error> {
+    if handle > u32::max_value() as u64 {
+        return Err(err!(EBADF));
+    }
+    Ok(handle as u32)
+}
+
+fn name_of(address: u64, len: u64) -> Result<String, ::common::error::Error> {
+    if len as usize > ipc::MAX_NAME_LEN {
+        return Err(err!(EINVAL));
+    }
+    let bytes = try!(copy_from_user(address, len));
+    String::from_utf8(bytes).map_err(|_| err!(EINVAL))
+}
+
+/// Create a port, anonymous if len is 0
+pub fn port_create(name: u64, len: u64) -> SyscallResult {
+    let name = match len {
+        0 => None,
+        len => Some(try!(name_of(name, len))),
+    };
+    ipc::create(name).map(|handle| handle as u64)
+}
+
+pub fn port_lookup(name: u64, len: u64) -> SyscallResult {
+    let name = try!(name_of(name, len));
+    ipc::lookup(&name).map(|handle| handle as u64)
+}
+
+pub fn port_send(handle: u64, buffer: u64, len: u64, transfer: u64, timeout: u64) -> SyscallResult {
+    if len as usize > ipc::MAX_MESSAGE_SIZE {
+        return Err(err!(EINVAL));
+    }
+    let data = try!(copy_from_user(buffer, len));
+    let transfer = match transfer {
+        NO_HANDLE => None,
+        transfer => Some(try!(handle_of(transfer))),
+    };
+    try!(ipc::send(try!(handle_of(handle)), data, transfer, timeout_of(timeout)));
+    Ok(0)
+}
+
+/// Receive a message into buffer, returns its size
+pub fn port_receive(handle: u64, buffer: u64, len: u64, info: u64, timeout: u64) -> SyscallResult {
+    // Check the buffers before a message is taken off the port
+    try!(super::check_user(buffer, len, true));
+    try!(super::check_user(info, size_of::<ReceiveInfo>() as u64, true));
+
+    let received = try!(ipc::receive(
+        try!(handle_of(handle)),
+        len as usize,
+        timeout_of(timeout)
+    ));
+    let size = received.data.len() as u64;
+    try!(copy_to_user(buffer, &received.data));
+    let info_data = ReceiveInfo {
+        sender: received.sender,
+        size: size,
+        handle: received.handle.map_or(NO_HANDLE, |handle| handle as u64),
+    };
+    let info_bytes = unsafe {
+        slice::from_raw_parts(
+            &info_data as *const ReceiveInfo as *const u8,
+            size_of::<ReceiveInfo>(),
+        )
+    };
+    try!(copy_to_user(info, info_bytes));
+    Ok(size)
+}
+
+pub fn handle_dup(handle: u64, rights: u64) -> SyscallResult {
+    if rights > ipc::RIGHT_ALL as u64 {
+        return Err(err!(EINVAL));
+    }
+    ipc::dup(try!(handle_of(handle)), rights as u32).map(|handle| handle as u64)
+}
+
+pub fn handle_close(handle: u64) -> SyscallResult {
+    try!(ipc::close(try!(handle_of(handle))));
+    Ok(0)
+}
+
+pub fn shm_create(name: u64, len: u64, size: u64) -> SyscallResult {
+    let name = try!(name_of(name, len));
+    try!(ipc::shm::create(name, size));
+    Ok(0)
+}
+
+pub fn shm_unlink(name: u64, len: u64) -> SyscallResult {
+    let name = try!(name_of(name, len));
+    try!(ipc::shm::unlink(&name));
+    Ok(0)
+}
+
+/// Map a shared memory object, returns its size
+pub fn shm_map(name: u64, len: u64, address: u64, prot: u64) -> SyscallResult {
+    if prot & !(PROT_READ | PROT_WRITE) != 0 || prot & PROT_READ == 0 {
+        return Err(err!(EINVAL));
+    }
+    let name = try!(name_of(name, len));
+    ipc::shm::map(&name, address, prot & PROT_WRITE != 0).map(|(_, size)| size)
+}
+
+pub fn shm_unmap(address: u64, size: u64) -> SyscallResult {
+    try!(ipc::shm::unmap(address, size));
+    Ok(0)
+}
+
+/// Run f on the file table of the current task
+fn with_files<T, F: FnOnce(&mut fs::FileTable) -> T>(f: F) -> Result<T, ::common::error::Error> {
+    let tasks = task::tasks();
+    match tasks.current() {
+        None => Err(err!(EPERM)),
+        Some(current_lock) => Ok(f(&mut current_lock.write().files)),
+    }
+}
+
+/// Create a pipe, stores the read and write descriptors at fds
+pub fn pipe(fds: u64) -> SyscallResult {
+    try!(super::check_user(fds, 2 * size_of::<u32>() as u64, true));
+    let (reader, writer) = ipc::pipe::pipe();
+    // The table only gets references, so both ends
+    // are dropped here with the task list unlocked
+    let reader: Arc<File> = Arc::new(reader);
+    let writer: Arc<File> = Arc::new(writer);
+
+    let (read_fd, write_fd) = try!(try!(with_files(|files| {
+        let read_fd = try!(files.insert(reader.clone()));
+        match files.insert(writer.clone()) {
+            Ok(write_fd) => Ok((read_fd, write_fd)),
+            Err(e) => {
+                let _ = files.remove(read_fd);
+                Err(e)
+            }
+        }
+    })));
+
+    let values = [read_fd as u32, write_fd as u32];
+    let bytes = unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, size_of::<[u32; 2]>()) };
+    if let Err(e) = copy_to_user(fds, bytes) {
+        let _ = with_files(|files| {
+            let _ = files.remove(read_fd);
+            let _ = files.remove(write_fd);
+        });
+        return Err(e);
+    }
+    Ok(0)
+}
@@ -0,0 +1,138 @@
+//! System calls
+//!
+//! User tasks enter through `int 0x80` with the call number in rax
+//! and arguments in rdi, rsi, rdx, r10, r8 and r9. The result is
+//! returned in rax, negative values are error numbers.
+
+mod file;
+mod ipc;
+mod system;
+mod task;
+
+use alloc::vec::Vec;
+use arch;
+use arch::TrapFrame;
+use core::slice;
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_YIELD: u64 = 1;
+pub const SYS_GETTID: u64 = 2;
+pub const SYS_KILL: u64 = 3;
+pub const SYS_SIGACTION: u64 = 4;
+pub const SYS_SIGPROCMASK: u64 = 5;
+pub const SYS_SIGRETURN: u64 = 6;
+pub const SYS_PORT_CREATE: u64 = 7;
+pub const SYS_PORT_LOOKUP: u64 = 8;
+pub const SYS_PORT_SEND: u64 = 9;
+pub const SYS_PORT_RECEIVE: u64 = 10;
+pub const SYS_HANDLE_DUP: u64 = 11;
+pub const SYS_HANDLE_CLOSE: u64 = 12;
+pub const SYS_SHM_CREATE: u64 = 13;
+pub const SYS_SHM_UNLINK: u64 = 14;
+pub const SYS_SHM_MAP: u64 = 15;
+pub const SYS_SHM_UNMAP: u64 = 16;
+pub const SYS_PIPE: u64 = 17;
+pub const SYS_READ: u64 = 18;
+pub const SYS_WRITE: u64 = 19;
+pub const SYS_CLOSE: u64 = 20;
+pub const SYS_OPEN: u64 = 21;
+pub const SYS_LSEEK: u64 = 22;
+pub const SYS_DUP: u64 = 23;
+pub const SYS_DUP2: u64 = 24;
+pub const SYS_FORK: u64 = 25;
+pub const SYS_SLEEP: u64 = 26;
+pub const SYS_REBOOT: u64 = 27;
+
+type SyscallResult = Result<u64, ::common::error::Error>;
+
+/// Check a user buffer against the address space of the running task
+pub fn check_user(address: u64, len: u64, write: bool) -> Result<(), ::common::error::Error> {
+    let tasks = ::task::tasks();
+    let current_lock = match tasks.current() {
+        None => return Err(err!(EFAULT)),
+        Some(current_lock) => current_lock,
+    };
+    match current_lock
+        .read()
+        .context
+        .user_accessible(address, len, write)
+    {
+        true => Ok(()),
+        false => Err(err!(EFAULT)),
+    }
+}
+
+/// Copy a user buffer into the kernel
+pub fn copy_from_user(address: u64, len: u64) -> Result<Vec<u8>, ::common::error::Error> {
+    try!(check_user(address, len, false));
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    // The task's address space is the active one
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, len as usize) }.to_vec())
+}
+
+/// Copy data out to a user buffer
+pub fn copy_to_user(address: u64, data: &[u8]) -> Result<(), ::common::error::Error> {
+    try!(check_user(address, data.len() as u64, true));
+    if data.is_empty() {
+        return Ok(());
+    }
+    unsafe {
+        slice::from_raw_parts_mut(address as *mut u8, data.len()).copy_from_slice(data);
+    }
+    Ok(())
+}
+
+fn dispatch(frame: &mut TrapFrame) -> SyscallResult {
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+    match frame.rax {
+        SYS_EXIT => task::exit(args[0]),
+        SYS_YIELD => task::yield_now(),
+        SYS_GETTID => task::gettid(),
+        SYS_KILL => task::kill(args[0], args[1]),
+        SYS_SIGACTION => task::sigaction(args[0], args[1], args[2], args[3]),
+        SYS_SIGPROCMASK => task::sigprocmask(args[0], args[1]),
+        SYS_SIGRETURN => task::sigreturn(frame),
+        SYS_PORT_CREATE => ipc::port_create(args[0], args[1]),
+        SYS_PORT_LOOKUP => ipc::port_lookup(args[0], args[1]),
+        SYS_PORT_SEND => ipc::port_send(args[0], args[1], args[2], args[3], args[4]),
+        SYS_PORT_RECEIVE => ipc::port_receive(args[0], args[1], args[2], args[3], args[4]),
+        SYS_HANDLE_DUP => ipc::handle_dup(args[0], args[1]),
+        SYS_HANDLE_CLOSE => ipc::handle_close(args[0]),
+        SYS_SHM_CREATE => ipc::shm_create(args[0], args[1], args[2]),
+        SYS_SHM_UNLINK => ipc::shm_unlink(args[0], args[1]),
+        SYS_SHM_MAP => ipc::shm_map(args[0], args[1], args[2], args[3]),
+        SYS_SHM_UNMAP => ipc::shm_unmap(args[0], args[1]),
+        SYS_PIPE => ipc::pipe(args[0]),
+        SYS_READ => file::read(args[0], args[1], args[2]),
+        SYS_WRITE => file::write(args[0], args[1], args[2]),
+        SYS_CLOSE => file::close(args[0]),
+        SYS_OPEN => file::open(args[0], args[1], args[2]),
+        SYS_LSEEK => file::lseek(args[0], args[1], args[2]),
+        SYS_DUP => file::dup(args[0]),
+        SYS_DUP2 => file::dup2(args[0], args[1]),
+        SYS_FORK => task::fork(frame),
+        SYS_SLEEP => task::sleep(args[0]),
+        SYS_REBOOT => system::reboot(args[0]),
+        _ => Err(err!(ENOSYS)),
+    }
+}
+
+/// System call gate handler
+fn handler(_vector: u64, _error_code: u64) {
+    // Keep the frame, handlers may switch tasks
+    let frame = unsafe { arch::trap_frame() }.expect("System call without trap frame");
+    if !frame.user_mode() {
+        panic!("System call from kernel mode");
+    }
+
+    frame.rax = match dispatch(frame) {
+        Ok(value) => value,
+        Err(e) => (-e.errno()) as u64,
+    };
+}
+
+pub fn init() {
+    assert!(arch::register_isr(arch::SYSCALL_VECTOR, handler));
+}
@@ -0,0 +1,38 @@
+use super::SyscallResult;
+use arch;
+use panic::{self, PanicAction};
+use task;
+
+// Commands of reboot
+const REBOOT_POWER_OFF: u64 = 0;
+const REBOOT_RESTART: u64 = 1;
+// Set what a kernel panic does
+const REBOOT_PANIC_HALT: u64 = 2;
+const REBOOT_PANIC_POWER_OFF: u64 = 3;
+const REBOOT_PANIC_RESTART: u64 = 4;
+
+/// Power off or restart the machine, doesn't return on success
+/// Also sets what happens on panic. Only privileged tasks may,
+/// others get EPERM.
+pub fn reboot(command: u64) -> SyscallResult {
+    if !task::privileged() {
+        return Err(err!(EPERM));
+    }
+    match command {
+        REBOOT_POWER_OFF => arch::power_off(),
+        REBOOT_RESTART => arch::reboot(),
+        REBOOT_PANIC_HALT => {
+            panic::set_action(PanicAction::Halt);
+            Ok(0)
+        }
+        REBOOT_PANIC_POWER_OFF => {
+            panic::set_action(PanicAction::PowerOff);
+            Ok(0)
+        }
+        REBOOT_PANIC_RESTART => {
+            panic::set_action(PanicAction::Reboot);
+            Ok(0)
+        }
+        _ => Err(err!(EINVAL)),
+    }
+}
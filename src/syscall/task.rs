@@ -0,0 +1,106 @@
+use super::SyscallResult;
+use arch::TrapFrame;
+use task;
+use task::signal::{self, SigAction, SigHandler};
+use time;
+
+// Special handler values of sigaction
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+
+// How of sigprocmask
+const SIG_BLOCK: u64 = 0;
+const SIG_UNBLOCK: u64 = 1;
+const SIG_SETMASK: u64 = 2;
+
+pub fn exit(code: u64) -> SyscallResult {
+    task::exit(code);
+}
+
+pub fn yield_now() -> SyscallResult {
+    task::yield_now();
+    Ok(0)
+}
+
+/// Returns the child's TID, the child gets 0
+pub fn fork(frame: &TrapFrame) -> SyscallResult {
+    task::fork(frame)
+}
+
+/// Sleep for a number of milliseconds
+pub fn sleep(ms: u64) -> SyscallResult {
+    try!(time::sleep(ms));
+    Ok(0)
+}
+
+pub fn gettid() -> SyscallResult {
+    Ok(task::current_tid())
+}
+
+pub fn kill(tid: u64, sig: u64) -> SyscallResult {
+    if sig >= signal::NSIG as u64 {
+        return Err(err!(EINVAL));
+    }
+    try!(signal::send(tid, sig as u32));
+    Ok(0)
+}
+
+/// Set the handler of a signal, returns the old handler
+pub fn sigaction(sig: u64, handler: u64, mask: u64, restorer: u64) -> SyscallResult {
+    if sig >= signal::NSIG as u64 {
+        return Err(err!(EINVAL));
+    }
+    let action = SigAction {
+        handler: match handler {
+            SIG_DFL => SigHandler::Default,
+            SIG_IGN => SigHandler::Ignore,
+            entry => {
+                try!(super::check_user(entry, 1, false));
+                try!(super::check_user(restorer, 1, false));
+                SigHandler::Handler(entry)
+            }
+        },
+        mask: mask,
+        restorer: restorer,
+    };
+
+    let tasks = task::tasks();
+    let current_lock = match tasks.current() {
+        None => return Err(err!(EFAULT)),
+        Some(current_lock) => current_lock,
+    };
+    let old = try!(current_lock.write().signals.set_action(sig as u32, action));
+    Ok(match old.handler {
+        SigHandler::Default => SIG_DFL,
+        SigHandler::Ignore => SIG_IGN,
+        SigHandler::Handler(entry) => entry,
+    })
+}
+
+/// Change the blocked signals, returns the old mask
+pub fn sigprocmask(how: u64, set: u64) -> SyscallResult {
+    let tasks = task::tasks();
+    let current_lock = match tasks.current() {
+        None => return Err(err!(EFAULT)),
+        Some(current_lock) => current_lock,
+    };
+    let mut current = current_lock.write();
+    let old = current.signals.blocked();
+    let mask = match how {
+        SIG_BLOCK => old | set,
+        SIG_UNBLOCK => old & !set,
+        SIG_SETMASK => set,
+        _ => return Err(err!(EINVAL)),
+    };
+    current.signals.set_blocked(mask);
+    Ok(old)
+}
+
+/// Return from a signal handler
+pub fn sigreturn(frame: &mut TrapFrame) -> SyscallResult {
+    match signal::restore_frame(frame) {
+        // Keep rax of the interrupted code
+        Ok(()) => Ok(frame.rax),
+        Err(_) => task::exit(128 + signal::SIGSEGV as u64),
+    }
+}
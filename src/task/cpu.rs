@@ -0,0 +1,52 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use arch;
+use core::cell::UnsafeCell;
+use spin::{Once, RwLock};
+
+use super::Task;
+
+/// TID of the idle task
+pub const IDLE_TID: u64 = 0;
+
+/// Per-CPU scheduling state
+/// Only touched by its own CPU, with interrupts disabled.
+pub struct Cpu {
+    /// Running task
+    pub current: Option<Arc<RwLock<Task>>>,
+    /// TID of the running task
+    pub current_tid: u64,
+    /// Task run when nothing else is ready
+    pub idle: Option<Arc<RwLock<Task>>>,
+    /// Terminated task switched away from, its kernel stack can
+    /// only be freed once we're off it. Reaped by finish_switch().
+    pub zombie: Option<Arc<RwLock<Task>>>,
+    /// Task switched away from, it stays on this CPU
+    /// until the switch is done
+    pub prev: Option<Arc<RwLock<Task>>>,
+}
+
+struct CpuCell(UnsafeCell<Cpu>);
+
+unsafe impl Sync for CpuCell {}
+
+static CPUS: Once<Vec<CpuCell>> = Once::new();
+
+/// Get the state of the running CPU
+/// Interrupts must be disabled while the reference is used.
+pub unsafe fn this_cpu() -> &'static mut Cpu {
+    let cpus = CPUS.call_once(|| {
+        (0..arch::MAX_CPUS)
+            .map(|_| {
+                CpuCell(UnsafeCell::new(Cpu {
+                    current: None,
+                    current_tid: IDLE_TID,
+                    idle: None,
+                    zombie: None,
+                    prev: None,
+                }))
+            })
+            .collect()
+    });
+    &mut *cpus[arch::cpu_id()].0.get()
+}
@@ -1,5 +1,6 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use arch::TrapFrame;
 use spin::RwLock;
 
 use super::task::Task;
@@ -20,10 +21,35 @@ impl TaskList {
     }
 
     pub fn current(&self) -> Option<&Arc<RwLock<Task>>> {
-        self.map.get(&super::TASK_ID.read())
+        self.map.get(&super::current_tid())
     }
 
     pub fn new_task(&mut self) -> Result<&Arc<RwLock<Task>>, ::common::error::Error> {
+        let tid = self.alloc_tid();
+        self.insert(Task::new(tid))
+    }
+
+    pub fn new_kernel_task(
+        &mut self,
+        entry: fn(usize),
+        arg: usize,
+    ) -> Result<&Arc<RwLock<Task>>, ::common::error::Error> {
+        let tid = self.alloc_tid();
+        self.insert(Task::new_kernel(tid, entry, arg))
+    }
+
+    /// Create a child of parent, resuming from frame
+    pub fn fork_task(
+        &mut self,
+        parent: &Task,
+        frame: &TrapFrame,
+    ) -> Result<&Arc<RwLock<Task>>, ::common::error::Error> {
+        let tid = self.alloc_tid();
+        let task = try!(parent.fork(tid, frame));
+        self.insert(task)
+    }
+
+    fn alloc_tid(&mut self) -> u64 {
         // Find next TID
         let mut alloc_id = self.next_id;
         loop {
@@ -36,13 +62,21 @@ impl TaskList {
             }
         }
         self.next_id = alloc_id;
+        alloc_id
+    }
 
+    fn insert(&mut self, task: Task) -> Result<&Arc<RwLock<Task>>, ::common::error::Error> {
+        let tid = task.tid();
         assert!(
             self.map
-                .insert(alloc_id, Arc::new(RwLock::new(Task::new(alloc_id))))
+                .insert(tid, Arc::new(RwLock::new(task)))
                 .is_none()
         );
-        Ok(self.map.get(&alloc_id).unwrap())
+        Ok(self.map.get(&tid).unwrap())
+    }
+
+    pub fn get(&self, tid: u64) -> Option<&Arc<RwLock<Task>>> {
+        self.map.get(&tid)
     }
 
     pub fn iter(&self) -> ::alloc::collections::btree_map::Iter<u64, Arc<RwLock<Task>>> {
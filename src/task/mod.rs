@@ -1,32 +1,415 @@
+//! Tasks and scheduling
+//!
+//! Lock discipline: the task list and the tasks in it are only locked
+//! with interrupts disabled, which tasks() and tasks_mut() take care of.
+//! Hence the scheduler, running in the timer interrupt, never spins on
+//! a lock held by the task it preempted. Lock a task only through the
+//! task list guard.
+
+mod cpu;
 mod list;
+pub mod sched;
+pub mod signal;
+mod stats;
 mod switch;
 mod task;
 
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use arch;
+use arch::IrqGuard;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use time;
+
+use self::cpu::{this_cpu, IDLE_TID};
 
 pub use self::list::TaskList;
+pub use self::sched::Scheduler;
+pub use self::stats::{TaskInfo, TaskStats};
 pub use self::task::{Task, TaskStatus};
 
 static TASK_LIST: Once<RwLock<TaskList>> = Once::new();
-static TASK_ID: RwLock<u64> = RwLock::new(0);
+static WAIT_TICKET: AtomicUsize = AtomicUsize::new(0);
+
+/// A lock guard which keeps interrupts disabled
+pub struct Locked<G> {
+    // dropped before interrupts are restored
+    guard: G,
+    _irq: IrqGuard,
+}
+
+impl<G: Deref> Deref for Locked<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Locked<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
 
-pub fn tasks() -> RwLockReadGuard<'static, TaskList> {
-    TASK_LIST.call_once(|| RwLock::new(TaskList::new())).read()
+pub fn tasks() -> Locked<RwLockReadGuard<'static, TaskList>> {
+    let irq = IrqGuard::new();
+    Locked {
+        guard: TASK_LIST.call_once(|| RwLock::new(TaskList::new())).read(),
+        _irq: irq,
+    }
 }
 
-pub fn tasks_mut() -> RwLockWriteGuard<'static, TaskList> {
-    TASK_LIST.call_once(|| RwLock::new(TaskList::new())).write()
+pub fn tasks_mut() -> Locked<RwLockWriteGuard<'static, TaskList>> {
+    let irq = IrqGuard::new();
+    Locked {
+        guard: TASK_LIST.call_once(|| RwLock::new(TaskList::new())).write(),
+        _irq: irq,
+    }
 }
 
 pub fn current_tid() -> u64 {
-    *TASK_ID.read()
+    let _irq = IrqGuard::new();
+    unsafe { this_cpu().current_tid }
+}
+
+/// Mark a task ready and put it on a run queue
+/// An idle CPU picking it up is woken right away.
+pub fn wakeup(task: &mut Task) {
+    let _irq = IrqGuard::new();
+    let sched = arch::scheduler().expect("No scheduler registered");
+    task.status = TaskStatus::Ready;
+    task.sched.cpu = switch::select_cpu(sched, task);
+    sched.enqueue(task);
+    if task.sched.cpu != arch::cpu_id() {
+        arch::send_reschedule(task.sched.cpu);
+    }
+}
+
+/// Leave the idle task if something was queued on this CPU
+/// Called on a reschedule interrupt, busy CPUs switch on their next tick.
+pub fn reschedule() {
+    let _irq = IrqGuard::new();
+    if current_tid() != IDLE_TID {
+        return;
+    }
+    if let Some(sched) = arch::scheduler() {
+        unsafe {
+            switch::schedule(sched, true);
+        }
+    }
+}
+
+/// Give up the CPU, the task stays ready
+pub fn yield_now() {
+    let _irq = IrqGuard::new();
+    if let Some(sched) = arch::scheduler() {
+        unsafe {
+            switch::schedule(sched, false);
+        }
+    }
+}
+
+/// Block the current task on a wait channel
+/// The task keeps running until it yields, if it's woken in
+/// between it's ready again and the yield just reschedules.
+/// The idle task never blocks, it returns false.
+pub fn sleep_on(channel: usize) -> bool {
+    sleep_on_timeout(channel, 0, false)
 }
 
-pub fn set_current_tid(tid: u64) {
-    *TASK_ID.write() = tid;
+/// Block the current task on a wait channel for at most timeout
+/// ticks, as sleep_on(). A zero timeout waits forever.
+/// An interruptible wait ends when a signal arrives, and doesn't
+/// start while one is pending.
+pub fn sleep_on_timeout(channel: usize, timeout: u64, interruptible: bool) -> bool {
+    let tasks = tasks();
+    let current_lock = match tasks.current() {
+        None => return false,
+        Some(current_lock) => current_lock,
+    };
+    let mut current = current_lock.write();
+    if current.tid() == IDLE_TID || current.died() {
+        return false;
+    }
+    if interruptible && current.signals.deliverable() {
+        return false;
+    }
+    current.status = TaskStatus::Blocked;
+    current.wchan = channel;
+    current.interruptible = interruptible;
+    current.wait_ticket = WAIT_TICKET.fetch_add(1, Ordering::Relaxed) as u64;
+    if timeout > 0 {
+        let tid = current.tid();
+        let ticket = current.wait_ticket;
+        current.wait_timer = Some(time::after_ticks(timeout, move || expire_wait(tid, ticket)));
+    }
+    true
+}
+
+/// End a timed wait unless the task was woken meanwhile
+fn expire_wait(tid: u64, ticket: u64) {
+    let tasks = tasks();
+    if let Some(task_lock) = tasks.get(tid) {
+        let mut task = task_lock.write();
+        if task.status == TaskStatus::Blocked && task.wait_ticket == ticket {
+            task.wchan = 0;
+            task.wait_timer = None;
+            wakeup(&mut task);
+        }
+    }
+}
+
+/// Cancel the timer of a timed wait
+fn cancel_wait_timer(task: &mut Task) {
+    if let Some(timer) = task.wait_timer.take() {
+        timer.cancel();
+    }
+}
+
+/// Sleep for a number of ticks, a signal cuts it short with EINTR
+pub fn sleep(timeout: u64) -> Result<(), ::common::error::Error> {
+    if timeout > 0 && sleep_on_timeout(0, timeout, true) {
+        yield_now();
+    }
+    if signal::pending() {
+        return Err(err!(EINTR));
+    }
+    Ok(())
+}
+
+/// End the wait of a blocked task
+fn end_wait(task: &mut Task) {
+    task.wchan = 0;
+    cancel_wait_timer(task);
+    wakeup(task);
+}
+
+/// Wake a task if it still waits on a channel
+pub fn wake(tid: u64, channel: usize) -> bool {
+    let tasks = tasks();
+    let task_lock = match tasks.get(tid) {
+        None => return false,
+        Some(task_lock) => task_lock,
+    };
+    let mut task = task_lock.write();
+    if task.status != TaskStatus::Blocked || task.wchan != channel {
+        return false;
+    }
+    end_wait(&mut task);
+    true
+}
+
+/// Run as the idle task, never returns
+pub fn idle() -> ! {
+    loop {
+        unsafe {
+            arch::enable_int();
+            arch::halt();
+        }
+    }
+}
+
+/// Spawn a kernel thread running entry(arg)
+pub fn spawn(entry: fn(usize), arg: usize) -> Result<u64, ::common::error::Error> {
+    let mut tasks = tasks_mut();
+    let task_lock = try!(tasks.new_kernel_task(entry, arg));
+    let mut task = task_lock.write();
+    wakeup(&mut task);
+    Ok(task.tid())
+}
+
+/// Fork the current user task, the child resumes from frame
+/// with a zero return value. Returns the child's TID.
+pub fn fork(frame: &arch::TrapFrame) -> Result<u64, ::common::error::Error> {
+    let mut child_frame = *frame;
+    child_frame.rax = 0;
+
+    let mut tasks = tasks_mut();
+    let parent_lock = match tasks.current() {
+        None => return Err(err!(EPERM)),
+        Some(current_lock) => current_lock.clone(),
+    };
+    let parent = parent_lock.read();
+    let child_lock = try!(tasks.fork_task(&parent, &child_frame));
+    let mut child = child_lock.write();
+    wakeup(&mut child);
+    Ok(child.tid())
+}
+
+/// Change the scheduling parameters of a task
+fn reschedule_with<F: Fn(&mut Task)>(tid: u64, change: F) -> Result<(), ::common::error::Error> {
+    let sched = arch::scheduler().expect("No scheduler registered");
+    let tasks = tasks();
+    let task_lock = match tasks.get(tid) {
+        None => return Err(err!(ENOENT)),
+        Some(task_lock) => task_lock,
+    };
+    let mut task = task_lock.write();
+
+    // Queued tasks are keyed by their parameters,
+    // so take it off the run queue while changing them.
+    let queued = task.standby();
+    if queued {
+        sched.dequeue(&mut task);
+    }
+    change(&mut task);
+    if queued {
+        if !task.sched.allowed(task.sched.cpu) {
+            task.sched.cpu = switch::select_cpu(sched, &task);
+        }
+        sched.enqueue(&mut task);
+    }
+    Ok(())
+}
+
+/// Set the nice value of a task
+pub fn set_nice(tid: u64, nice: i8) -> Result<(), ::common::error::Error> {
+    if nice < sched::MIN_NICE || nice > sched::MAX_NICE {
+        return Err(err!(EINVAL));
+    }
+    reschedule_with(tid, |task| task.sched.nice = nice)
+}
+
+/// Set the static priority of a task
+pub fn set_priority(tid: u64, priority: u8) -> Result<(), ::common::error::Error> {
+    if priority > sched::MAX_PRIORITY {
+        return Err(err!(EINVAL));
+    }
+    reschedule_with(tid, |task| task.sched.priority = priority)
+}
+
+/// Set the time slice of a task, in ticks
+pub fn set_time_slice(tid: u64, ticks: u64) -> Result<(), ::common::error::Error> {
+    if ticks == 0 {
+        return Err(err!(EINVAL));
+    }
+    reschedule_with(tid, |task| task.sched.time_slice = ticks)
+}
+
+/// Set the CPUs a task may run on, one bit each
+/// A running task moves at its next tick if its CPU is excluded.
+pub fn set_affinity(tid: u64, affinity: u64) -> Result<(), ::common::error::Error> {
+    let online = (0..arch::cpu_count()).any(|cpu| sched::allowed(affinity, cpu));
+    if !online {
+        return Err(err!(EINVAL));
+    }
+    reschedule_with(tid, |task| task.sched.affinity = affinity)
+}
+
+/// Terminate the current task
+/// The task is freed once the scheduler switched away from it.
+pub fn exit(code: u64) -> ! {
+    let resources = {
+        let tasks = tasks();
+        tasks.current().map(|current_lock| {
+            let mut current = current_lock.write();
+            current.terminate(code);
+            (current.handles.take(), current.files.take())
+        })
+    };
+    // Close ports and files held by the task with the task list unlocked
+    drop(resources);
+    yield_now();
+    // Without a scheduler there's nothing else to run
+    idle();
+}
+
+/// Charge one tick to the running task
+/// Called from the timer interrupt.
+pub fn account_tick(user: bool) {
+    let tasks = tasks();
+    if let Some(current_lock) = tasks.current() {
+        let mut current = current_lock.write();
+        if user {
+            current.stats.user_ticks += 1;
+        } else {
+            current.stats.kernel_ticks += 1;
+        }
+    }
+}
+
+/// Count a page fault raised by the running task
+pub fn account_page_fault() {
+    let tasks = tasks();
+    if let Some(current_lock) = tasks.current() {
+        current_lock.write().stats.page_faults += 1;
+    }
+}
+
+/// Check if the running task may act on the whole system
+pub fn privileged() -> bool {
+    let tasks = tasks();
+    tasks
+        .current()
+        .map_or(false, |current_lock| current_lock.read().privileged())
+}
+
+/// Get the snapshot of a task
+pub fn info(tid: u64) -> Option<TaskInfo> {
+    tasks().get(tid).map(|task_lock| task_lock.read().info())
+}
+
+/// Get the snapshots of all tasks
+pub fn list() -> Vec<TaskInfo> {
+    tasks()
+        .iter()
+        .map(|(_, task_lock)| task_lock.read().info())
+        .collect()
+}
+
+/// Print a ps-style listing of all tasks
+pub fn dump() {
+    println!(
+        "{:>5} {:>11} {:>4} {:>4} {:>8} {:>8} {:>7} {:>7} {:>6} {:>6}",
+        "TID", "STATUS", "NI", "PRI", "UTICKS", "KTICKS", "VCSW", "ICSW", "FAULTS", "PEAK"
+    );
+    for info in list() {
+        println!(
+            "{:>5} {:>11} {:>4} {:>4} {:>8} {:>8} {:>7} {:>7} {:>6} {:>6}",
+            info.tid,
+            format!("{:?}", info.status),
+            info.nice,
+            info.priority,
+            info.stats.user_ticks,
+            info.stats.kernel_ticks,
+            info.stats.voluntary_switches,
+            info.stats.involuntary_switches,
+            info.stats.page_faults,
+            info.peak_pages
+        );
+    }
+}
+
+/// Make the code running now the idle task of this CPU
+fn become_idle() {
+    let _irq = IrqGuard::new();
+    let cpu = unsafe { this_cpu() };
+    let mut idle = Task::new_idle(IDLE_TID);
+    idle.status = TaskStatus::Running;
+    idle.on_cpu = true;
+    let idle = Arc::new(RwLock::new(idle));
+    cpu.idle = Some(idle.clone());
+    cpu.current = Some(idle);
+    cpu.current_tid = IDLE_TID;
+}
+
+/// Enter the scheduler on a processor started after init()
+pub fn start_cpu() -> ! {
+    become_idle();
+    println!("CPU {} online", arch::cpu_id());
+    idle();
 }
 
 pub fn init() {
-    arch::register_scheduler(self::switch::switch).expect("Failed to register scheduler");
+    use alloc::boxed::Box;
+
+    // The code running now becomes the idle task
+    become_idle();
+
+    let policy: &'static Scheduler = Box::leak(Box::new(sched::Fair::new()));
+    arch::register_scheduler(policy).expect("Failed to register scheduler");
+    println!("Scheduling policy: {}", policy.name());
 }
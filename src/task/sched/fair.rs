@@ -0,0 +1,129 @@
+use alloc::collections::BTreeMap;
+
+use super::{allowed, RunQueue, RunQueues, Scheduler, MIN_NICE};
+use task::Task;
+
+/// Weight of nice 0
+const NICE_0_WEIGHT: u64 = 1024;
+/// Virtual runtime charged per tick at nice 0
+const TICK_VRUNTIME: u64 = 1 << 10;
+
+/// Weights of nice -20 ~ 19, each step is about 1.25x
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110,
+    87, 70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+fn weight(nice: i8) -> u64 {
+    NICE_TO_WEIGHT[(nice - MIN_NICE) as usize]
+}
+
+struct FairQueue {
+    // (vruntime, tid) -> affinity, leftmost runs next
+    queue: BTreeMap<(u64, u64), u64>,
+    min_vruntime: u64,
+}
+
+impl RunQueue for FairQueue {
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<u64> {
+        let key = match self
+            .queue
+            .iter()
+            .find(|&(_, &affinity)| allowed(affinity, cpu))
+        {
+            None => return None,
+            Some((key, _)) => *key,
+        };
+        self.queue.remove(&key);
+        Some(key.1)
+    }
+}
+
+/// Fair scheduling, modeled after CFS
+/// Each task accumulates virtual runtime inversely proportional
+/// to its weight, the task with the least virtual runtime runs next.
+/// The minimum virtual runtime is tracked per run queue.
+pub struct Fair {
+    queues: RunQueues<FairQueue>,
+}
+
+impl Fair {
+    pub fn new() -> Self {
+        Fair {
+            queues: RunQueues::new(|| FairQueue {
+                queue: BTreeMap::new(),
+                min_vruntime: 0,
+            }),
+        }
+    }
+}
+
+impl Scheduler for Fair {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    fn enqueue(&self, task: &mut Task) {
+        let mut inner = self.queues.lock(task.sched.cpu);
+
+        // Tasks which slept for a long time don't get
+        // to monopolize the CPU, they start from the
+        // current minimum less one slice.
+        let floor = inner
+            .min_vruntime
+            .saturating_sub(task.sched.time_slice * TICK_VRUNTIME);
+        if task.sched.vruntime < floor {
+            task.sched.vruntime = floor;
+        }
+        task.sched.refill();
+        inner
+            .queue
+            .insert((task.sched.vruntime, task.tid()), task.sched.affinity);
+    }
+
+    fn dequeue(&self, task: &mut Task) {
+        self.queues
+            .lock(task.sched.cpu)
+            .queue
+            .remove(&(task.sched.vruntime, task.tid()));
+    }
+
+    fn pick_next(&self, cpu: usize) -> Option<u64> {
+        let mut inner = self.queues.lock(cpu);
+        let key = match inner.queue.keys().next() {
+            None => return None,
+            Some(key) => *key,
+        };
+        inner.queue.remove(&key);
+        if key.0 > inner.min_vruntime {
+            inner.min_vruntime = key.0;
+        }
+        Some(key.1)
+    }
+
+    fn steal(&self, cpu: usize) -> Option<u64> {
+        self.queues.steal(cpu)
+    }
+
+    fn load(&self, cpu: usize) -> usize {
+        self.queues.load(cpu)
+    }
+
+    fn task_tick(&self, task: &mut Task) -> bool {
+        task.sched.vruntime += TICK_VRUNTIME * NICE_0_WEIGHT / weight(task.sched.nice);
+        let expired = task.sched.consume();
+
+        let inner = self.queues.lock(task.sched.cpu);
+        match inner.queue.keys().next() {
+            None => false,
+            // Only preempt once the slice is used up, so
+            // tasks with similar vruntime don't ping-pong.
+            Some(&(vruntime, _)) => expired && vruntime < task.sched.vruntime,
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Scheduling policies
+//!
+//! A policy owns the run queues of ready tasks, one per CPU. The
+//! generic dispatching code in `task::switch` asks the policy whether
+//! the running task should be preempted and which task runs next,
+//! and takes work from other CPUs when there's nothing left to run.
+
+mod fair;
+mod priority;
+mod rr;
+
+use alloc::vec::Vec;
+use arch;
+use spin::{Mutex, MutexGuard};
+
+use super::Task;
+
+pub use self::fair::Fair;
+pub use self::priority::StaticPriority;
+pub use self::rr::RoundRobin;
+
+/// Default time slice, in ticks
+pub const DEFAULT_TIME_SLICE: u64 = 5;
+/// Default static priority, lower value runs first
+pub const DEFAULT_PRIORITY: u8 = 20;
+/// Lowest static priority
+pub const MAX_PRIORITY: u8 = 39;
+/// Nice range
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+/// Affinity allowing every CPU
+pub const ALL_CPUS: u64 = !0;
+
+/// Whether an affinity mask includes a CPU
+pub fn allowed(affinity: u64, cpu: usize) -> bool {
+    cpu < 64 && affinity & 1 << cpu != 0
+}
+
+/// Per-task scheduling parameters and accounting
+pub struct SchedEntity {
+    /// Nice value, used by fair scheduling
+    pub nice: i8,
+    /// Static priority, used by priority scheduling
+    pub priority: u8,
+    /// Ticks granted every time the task is picked
+    pub time_slice: u64,
+    /// Ticks left in the current slice
+    pub remaining: u64,
+    /// Weighted runtime, used by fair scheduling
+    pub vruntime: u64,
+    /// Total ticks the task has been running
+    pub runtime: u64,
+    /// Enqueue sequence, keeps FIFO order among equal keys
+    seq: u64,
+    /// CPU whose run queue holds the task, or which ran it last
+    pub cpu: usize,
+    /// CPUs the task may run on, one bit each
+    pub affinity: u64,
+}
+
+impl SchedEntity {
+    pub fn new() -> Self {
+        SchedEntity {
+            nice: 0,
+            priority: DEFAULT_PRIORITY,
+            time_slice: DEFAULT_TIME_SLICE,
+            remaining: DEFAULT_TIME_SLICE,
+            vruntime: 0,
+            runtime: 0,
+            seq: 0,
+            cpu: 0,
+            affinity: ALL_CPUS,
+        }
+    }
+
+    /// Parameters for a forked child, which starts
+    /// where its parent is in fair scheduling
+    pub fn fork(&self) -> Self {
+        SchedEntity {
+            nice: self.nice,
+            priority: self.priority,
+            time_slice: self.time_slice,
+            remaining: self.time_slice,
+            vruntime: self.vruntime,
+            runtime: 0,
+            seq: 0,
+            cpu: self.cpu,
+            affinity: self.affinity,
+        }
+    }
+
+    /// Whether the task may run on a CPU
+    pub fn allowed(&self, cpu: usize) -> bool {
+        allowed(self.affinity, cpu)
+    }
+
+    /// Consume one tick of the slice
+    /// Returns true if the slice is used up
+    fn consume(&mut self) -> bool {
+        self.runtime += 1;
+        if self.remaining > 0 {
+            self.remaining -= 1;
+        }
+        self.remaining == 0
+    }
+
+    /// Grant a fresh slice
+    fn refill(&mut self) {
+        self.remaining = self.time_slice;
+    }
+}
+
+/// Run queue of one CPU
+pub trait RunQueue {
+    /// Number of queued tasks
+    fn len(&self) -> usize;
+    /// Remove and return the first task allowed on a CPU
+    fn steal(&mut self, cpu: usize) -> Option<u64>;
+}
+
+/// Run queues of a policy, one per CPU
+/// At most one of them is locked at a time.
+pub struct RunQueues<Q> {
+    queues: Vec<Mutex<Q>>,
+}
+
+impl<Q: RunQueue> RunQueues<Q> {
+    pub fn new<F: Fn() -> Q>(new_queue: F) -> Self {
+        RunQueues {
+            queues: (0..arch::MAX_CPUS).map(|_| Mutex::new(new_queue())).collect(),
+        }
+    }
+
+    /// Lock the run queue of a CPU
+    pub fn lock(&self, cpu: usize) -> MutexGuard<Q> {
+        self.queues[cpu].lock()
+    }
+
+    /// Number of tasks queued on a CPU
+    pub fn load(&self, cpu: usize) -> usize {
+        self.lock(cpu).len()
+    }
+
+    /// Take a task allowed on cpu from the busiest other CPU
+    pub fn steal(&self, cpu: usize) -> Option<u64> {
+        let busiest = (0..arch::cpu_count())
+            .filter(|&other| other != cpu)
+            .map(|other| (self.load(other), other))
+            .max();
+        match busiest {
+            Some((load, other)) if load > 0 => self.lock(other).steal(cpu),
+            _ => None,
+        }
+    }
+}
+
+/// Scheduling policy
+pub trait Scheduler: Sync + Send {
+    /// Name of the policy
+    fn name(&self) -> &'static str;
+    /// Put a ready task on the run queue of task.sched.cpu
+    fn enqueue(&self, task: &mut Task);
+    /// Remove a task from its run queue, if it's queued
+    fn dequeue(&self, task: &mut Task);
+    /// Remove and return the next task to run on a CPU
+    fn pick_next(&self, cpu: usize) -> Option<u64>;
+    /// Remove and return a task queued on another CPU
+    /// which may run on cpu
+    fn steal(&self, cpu: usize) -> Option<u64>;
+    /// Number of tasks queued on a CPU
+    fn load(&self, cpu: usize) -> usize;
+    /// Account one tick to the running task
+    /// Returns true if the task should be preempted
+    fn task_tick(&self, task: &mut Task) -> bool;
+
+    /// Called by the timer on every tick
+    fn schedule(&self, tick: u64) {
+        super::switch::switch(self, tick);
+    }
+}
@@ -0,0 +1,102 @@
+use alloc::collections::BTreeMap;
+
+use super::{allowed, RunQueue, RunQueues, Scheduler};
+use task::Task;
+
+struct PriorityQueue {
+    // (priority, sequence) -> (tid, affinity)
+    queue: BTreeMap<(u8, u64), (u64, u64)>,
+    next_seq: u64,
+}
+
+impl RunQueue for PriorityQueue {
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<u64> {
+        let key = match self
+            .queue
+            .iter()
+            .find(|&(_, &(_, affinity))| allowed(affinity, cpu))
+        {
+            None => return None,
+            Some((key, _)) => *key,
+        };
+        self.queue.remove(&key).map(|(tid, _)| tid)
+    }
+}
+
+/// Static priority scheduling
+/// The highest priority (lowest value) ready task always runs,
+/// tasks with equal priority are served round-robin.
+pub struct StaticPriority {
+    queues: RunQueues<PriorityQueue>,
+}
+
+impl StaticPriority {
+    pub fn new() -> Self {
+        StaticPriority {
+            queues: RunQueues::new(|| PriorityQueue {
+                queue: BTreeMap::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+}
+
+impl Scheduler for StaticPriority {
+    fn name(&self) -> &'static str {
+        "static-priority"
+    }
+
+    fn enqueue(&self, task: &mut Task) {
+        let mut inner = self.queues.lock(task.sched.cpu);
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+
+        task.sched.seq = seq;
+        task.sched.refill();
+        inner
+            .queue
+            .insert((task.sched.priority, seq), (task.tid(), task.sched.affinity));
+    }
+
+    fn dequeue(&self, task: &mut Task) {
+        let key = (task.sched.priority, task.sched.seq);
+        let mut inner = self.queues.lock(task.sched.cpu);
+        if inner.queue.get(&key).map(|&(tid, _)| tid) == Some(task.tid()) {
+            inner.queue.remove(&key);
+        }
+    }
+
+    fn pick_next(&self, cpu: usize) -> Option<u64> {
+        let mut inner = self.queues.lock(cpu);
+        let key = match inner.queue.keys().next() {
+            None => return None,
+            Some(key) => *key,
+        };
+        inner.queue.remove(&key).map(|(tid, _)| tid)
+    }
+
+    fn steal(&self, cpu: usize) -> Option<u64> {
+        self.queues.steal(cpu)
+    }
+
+    fn load(&self, cpu: usize) -> usize {
+        self.queues.load(cpu)
+    }
+
+    fn task_tick(&self, task: &mut Task) -> bool {
+        let expired = task.sched.consume();
+        let inner = self.queues.lock(task.sched.cpu);
+        match inner.queue.keys().next() {
+            // Nobody else is waiting
+            None => false,
+            // A more important task is waiting
+            Some(&(priority, _)) if priority < task.sched.priority => true,
+            // Yield to tasks of the same priority
+            Some(&(priority, _)) => expired && priority == task.sched.priority,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use alloc::collections::BTreeMap;
+
+use super::{allowed, RunQueue, RunQueues, Scheduler};
+use task::Task;
+
+/// Ready tasks of a CPU in turn, with their affinity
+struct RoundRobinQueue {
+    // sequence -> (tid, affinity)
+    queue: BTreeMap<u64, (u64, u64)>,
+    next_seq: u64,
+}
+
+impl RunQueue for RoundRobinQueue {
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<u64> {
+        let seq = match self
+            .queue
+            .iter()
+            .find(|&(_, &(_, affinity))| allowed(affinity, cpu))
+        {
+            None => return None,
+            Some((seq, _)) => *seq,
+        };
+        self.queue.remove(&seq).map(|(tid, _)| tid)
+    }
+}
+
+/// Round-robin scheduling
+/// Every task gets its time slice in turn.
+pub struct RoundRobin {
+    queues: RunQueues<RoundRobinQueue>,
+}
+
+impl RoundRobin {
+    pub fn new() -> Self {
+        RoundRobin {
+            queues: RunQueues::new(|| RoundRobinQueue {
+                queue: BTreeMap::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn name(&self) -> &'static str {
+        "round-robin"
+    }
+
+    fn enqueue(&self, task: &mut Task) {
+        let mut inner = self.queues.lock(task.sched.cpu);
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+
+        task.sched.seq = seq;
+        task.sched.refill();
+        inner.queue.insert(seq, (task.tid(), task.sched.affinity));
+    }
+
+    fn dequeue(&self, task: &mut Task) {
+        let seq = task.sched.seq;
+        let mut inner = self.queues.lock(task.sched.cpu);
+        if inner.queue.get(&seq).map(|&(tid, _)| tid) == Some(task.tid()) {
+            inner.queue.remove(&seq);
+        }
+    }
+
+    fn pick_next(&self, cpu: usize) -> Option<u64> {
+        let mut inner = self.queues.lock(cpu);
+        let seq = match inner.queue.keys().next() {
+            None => return None,
+            Some(seq) => *seq,
+        };
+        inner.queue.remove(&seq).map(|(tid, _)| tid)
+    }
+
+    fn steal(&self, cpu: usize) -> Option<u64> {
+        self.queues.steal(cpu)
+    }
+
+    fn load(&self, cpu: usize) -> usize {
+        self.queues.load(cpu)
+    }
+
+    fn task_tick(&self, task: &mut Task) -> bool {
+        task.sched.consume()
+    }
+}
@@ -0,0 +1,382 @@
+//! POSIX-like signals
+//!
+//! Signals are recorded as pending on the target task and
+//! delivered when the task is about to return to user mode.
+//! A user handler runs on the user stack, on top of a frame
+//! holding the interrupted registers, and returns through
+//! its restorer which calls sigreturn.
+
+use arch::{TrapFrame, USER_LIMIT};
+use core::mem::size_of;
+
+use super::task::TaskStatus;
+use super::{end_wait, tasks, wakeup};
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+
+/// Number of signals, signal 0 is not used
+pub const NSIG: usize = 32;
+
+/// Signals which can't be blocked, caught or ignored
+const UNBLOCKABLE: u64 = (1 << SIGKILL) | (1 << SIGSTOP);
+
+/// Bytes below the user stack pointer left untouched
+const RED_ZONE: u64 = 128;
+
+/// Disposition of a signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SigHandler {
+    Default,
+    Ignore,
+    /// Address of the user handler
+    Handler(u64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SigAction {
+    pub handler: SigHandler,
+    /// Signals blocked while the handler runs
+    pub mask: u64,
+    /// Address the handler returns to, calls sigreturn
+    pub restorer: u64,
+}
+
+impl SigAction {
+    pub fn new() -> Self {
+        SigAction {
+            handler: SigHandler::Default,
+            mask: 0,
+            restorer: 0,
+        }
+    }
+}
+
+/// Action taken for a signal without handler
+#[derive(PartialEq, Eq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+fn default_action(sig: u32) -> DefaultAction {
+    match sig {
+        SIGCHLD => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        SIGSTOP | SIGTSTP => DefaultAction::Stop,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Per-task signal state
+pub struct SignalState {
+    pending: u64,
+    blocked: u64,
+    actions: [SigAction; NSIG],
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        SignalState {
+            pending: 0,
+            blocked: 0,
+            actions: [SigAction::new(); NSIG],
+        }
+    }
+
+    /// State of a forked child, which keeps the
+    /// actions and mask but no pending signals
+    pub fn fork(&self) -> Self {
+        SignalState {
+            pending: 0,
+            blocked: self.blocked,
+            actions: self.actions,
+        }
+    }
+
+    /// Mark a signal pending
+    pub fn raise(&mut self, sig: u32) {
+        self.pending |= 1 << sig;
+    }
+
+    /// Check if any signal can be delivered
+    pub fn deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+
+    /// Take the lowest deliverable signal
+    fn dequeue(&mut self) -> Option<u32> {
+        let ready = self.pending & !self.blocked;
+        if ready == 0 {
+            return None;
+        }
+        let sig = ready.trailing_zeros();
+        self.pending &= !(1 << sig);
+        Some(sig)
+    }
+
+    pub fn action(&self, sig: u32) -> SigAction {
+        self.actions[sig as usize]
+    }
+
+    /// Set the action of a signal, returns the old one
+    pub fn set_action(
+        &mut self,
+        sig: u32,
+        action: SigAction,
+    ) -> Result<SigAction, ::common::error::Error> {
+        if !valid(sig) || (1 << sig) & UNBLOCKABLE != 0 {
+            return Err(err!(EINVAL));
+        }
+        let old = self.actions[sig as usize];
+        self.actions[sig as usize] = action;
+        // Discard pending signals which are now ignored
+        if action.handler == SigHandler::Ignore
+            || (action.handler == SigHandler::Default
+                && default_action(sig) == DefaultAction::Ignore)
+        {
+            self.pending &= !(1 << sig);
+        }
+        Ok(old)
+    }
+
+    pub fn blocked(&self) -> u64 {
+        self.blocked
+    }
+
+    pub fn set_blocked(&mut self, mask: u64) {
+        self.blocked = mask & !UNBLOCKABLE & !1;
+    }
+}
+
+/// Check if sig is a valid signal number
+pub fn valid(sig: u32) -> bool {
+    sig > 0 && (sig as usize) < NSIG
+}
+
+/// Check if the running task has a signal to deliver
+/// Interruptible waits return EINTR then.
+pub fn pending() -> bool {
+    let tasks = tasks();
+    tasks
+        .current()
+        .map_or(false, |current_lock| current_lock.read().signals.deliverable())
+}
+
+/// Send a signal to a task
+/// Unprivileged tasks may only signal themselves and their children.
+pub fn send(tid: u64, sig: u32) -> Result<(), ::common::error::Error> {
+    if !valid(sig) {
+        return Err(err!(EINVAL));
+    }
+    let tasks = tasks();
+    let (sender, privileged) = match tasks.current() {
+        None => (0, true),
+        Some(current_lock) => {
+            let current = current_lock.read();
+            (current.tid(), current.privileged())
+        }
+    };
+    let task_lock = match tasks.get(tid) {
+        None => return Err(err!(ENOENT)),
+        Some(task_lock) => task_lock,
+    };
+    let mut task = task_lock.write();
+    if task.is_kernel() || task.died() {
+        return Err(err!(EPERM));
+    }
+    if !privileged && tid != sender && task.parent() != sender {
+        return Err(err!(EPERM));
+    }
+
+    if task.signals.action(sig).handler == SigHandler::Ignore && sig != SIGKILL {
+        return Ok(());
+    }
+    match sig {
+        SIGCONT => task.signals.pending &= !((1 << SIGSTOP) | (1 << SIGTSTP)),
+        SIGSTOP | SIGTSTP => task.signals.pending &= !(1 << SIGCONT),
+        _ => {}
+    };
+    task.signals.raise(sig);
+
+    // Killing or continuing a stopped task makes it runnable,
+    // the signal is handled as soon as it runs.
+    if task.status == TaskStatus::Stopped && (sig == SIGKILL || sig == SIGCONT) {
+        wakeup(&mut task);
+    }
+    // A sleeper returns EINTR and takes the signal on its way out.
+    // SIGKILL ends any wait, waiters which can't fail check their
+    // condition again and the task dies once it's met.
+    let interrupts = task.interruptible || sig == SIGKILL;
+    if task.status == TaskStatus::Blocked && interrupts && task.signals.deliverable() {
+        end_wait(&mut task);
+    }
+    Ok(())
+}
+
+/// Send a signal raised by a fault of the running task
+/// If it's blocked or ignored the task is killed, since
+/// returning to the faulting instruction would fault again.
+pub fn force(sig: u32) {
+    let tasks = tasks();
+    if let Some(current_lock) = tasks.current() {
+        let mut current = current_lock.write();
+        let blocked = current.signals.blocked & (1 << sig) != 0;
+        if blocked || current.signals.action(sig).handler == SigHandler::Ignore {
+            current.signals.actions[sig as usize] = SigAction::new();
+            let mask = current.signals.blocked & !(1 << sig);
+            current.signals.set_blocked(mask);
+        }
+        current.signals.raise(sig);
+    }
+}
+
+/// Saved on the user stack while a handler runs
+#[repr(C)]
+struct SignalFrame {
+    /// Return address of the handler
+    restorer: u64,
+    sig: u64,
+    /// Mask restored by sigreturn
+    blocked: u64,
+    /// Interrupted registers
+    saved: TrapFrame,
+}
+
+/// Build a signal frame on the user stack and redirect the
+/// frame to the handler
+fn setup_frame(frame: &mut TrapFrame, sig: u32, entry: u64, action: &SigAction) -> bool {
+    let tasks = tasks();
+    let current_lock = match tasks.current() {
+        None => return false,
+        Some(current_lock) => current_lock,
+    };
+    let mut current = current_lock.write();
+
+    // Handler is entered as if called, rsp + 8 is 16 bytes aligned
+    let size = size_of::<SignalFrame>() as u64;
+    let top = match frame.rsp.checked_sub(RED_ZONE + size) {
+        None => return false,
+        Some(top) => top,
+    };
+    let address = (top & !0xF) - 8;
+    if !current.context.user_accessible(address, size, true) {
+        return false;
+    }
+
+    let signal_frame = SignalFrame {
+        restorer: action.restorer,
+        sig: sig as u64,
+        blocked: current.signals.blocked,
+        saved: *frame,
+    };
+    unsafe {
+        // The task's address space is the active one
+        *(address as *mut SignalFrame) = signal_frame;
+    }
+
+    let mask = current.signals.blocked | action.mask | (1 << sig);
+    current.signals.set_blocked(mask);
+
+    frame.rip = entry;
+    frame.rsp = address;
+    frame.rdi = sig as u64;
+    true
+}
+
+/// Restore the frame saved by setup_frame()
+/// Called by sigreturn, with the stack pointer just above the restorer.
+pub fn restore_frame(frame: &mut TrapFrame) -> Result<(), ::common::error::Error> {
+    let tasks = tasks();
+    let current_lock = match tasks.current() {
+        None => return Err(err!(EFAULT)),
+        Some(current_lock) => current_lock,
+    };
+    let mut current = current_lock.write();
+
+    let size = size_of::<SignalFrame>() as u64;
+    let address = match frame.rsp.checked_sub(8) {
+        None => return Err(err!(EFAULT)),
+        Some(address) => address,
+    };
+    if address >= USER_LIMIT || !current.context.user_accessible(address, size, false) {
+        return Err(err!(EFAULT));
+    }
+
+    let signal_frame = unsafe { &*(address as *const SignalFrame) };
+    // iretq to a kernel or non-canonical address faults in kernel mode
+    if signal_frame.saved.rip >= USER_LIMIT {
+        return Err(err!(EFAULT));
+    }
+    *frame = signal_frame.saved;
+    frame.make_user();
+    current.signals.set_blocked(signal_frame.blocked);
+    Ok(())
+}
+
+/// Deliver pending signals of the running task
+/// Called before returning to user mode.
+pub fn deliver(frame: &mut TrapFrame) {
+    loop {
+        let (sig, action) = {
+            let tasks = tasks();
+            let current_lock = match tasks.current() {
+                None => return,
+                Some(current_lock) => current_lock,
+            };
+            let mut current = current_lock.write();
+            match current.signals.dequeue() {
+                None => return,
+                Some(sig) => (sig, current.signals.action(sig)),
+            }
+        };
+
+        let handler = match sig {
+            SIGKILL | SIGSTOP => SigHandler::Default,
+            _ => action.handler,
+        };
+        match handler {
+            SigHandler::Ignore => {}
+            SigHandler::Default => match default_action(sig) {
+                DefaultAction::Terminate => super::exit(128 + sig as u64),
+                DefaultAction::Stop => {
+                    {
+                        let tasks = tasks();
+                        if let Some(current_lock) = tasks.current() {
+                            current_lock.write().status = TaskStatus::Stopped;
+                        }
+                    }
+                    // Back here once SIGCONT or SIGKILL arrives
+                    super::yield_now();
+                }
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+            },
+            SigHandler::Handler(entry) => {
+                if !setup_frame(frame, sig, entry, &action) {
+                    // Can't run the handler on a broken stack
+                    super::exit(128 + SIGSEGV as u64);
+                }
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use super::task::TaskStatus;
+
+/// Per-task accounting
+#[derive(Clone, Copy, Debug)]
+pub struct TaskStats {
+    /// Ticks spent in user mode
+    pub user_ticks: u64,
+    /// Ticks spent in kernel mode
+    pub kernel_ticks: u64,
+    /// Times the task has been picked to run
+    pub scheduled: u64,
+    /// Times the task gave up the CPU by itself
+    pub voluntary_switches: u64,
+    /// Times the task was preempted
+    pub involuntary_switches: u64,
+    /// Page faults raised by the task
+    pub page_faults: u64,
+}
+
+impl TaskStats {
+    pub fn new() -> Self {
+        TaskStats {
+            user_ticks: 0,
+            kernel_ticks: 0,
+            scheduled: 0,
+            voluntary_switches: 0,
+            involuntary_switches: 0,
+            page_faults: 0,
+        }
+    }
+
+    /// Total ticks the task has been running
+    pub fn total_ticks(&self) -> u64 {
+        self.user_ticks + self.kernel_ticks
+    }
+}
+
+/// A snapshot of a task, used for listing
+#[derive(Clone, Copy, Debug)]
+pub struct TaskInfo {
+    pub tid: u64,
+    pub status: TaskStatus,
+    pub kernel: bool,
+    pub nice: i8,
+    pub priority: u8,
+    /// Resident user pages
+    pub pages: u64,
+    /// Peak resident user pages
+    pub peak_pages: u64,
+    pub stats: TaskStats,
+}
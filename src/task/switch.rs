@@ -1,87 +1,179 @@
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::ops::DerefMut;
-use core::ptr::null_mut;
+use arch;
+use arch::Context;
 
-use super::task::TaskStatus;
-use super::{set_current_tid, tasks_mut, Task};
+use super::cpu::{this_cpu, IDLE_TID};
+use super::sched::Scheduler;
+use super::task::{Task, TaskStatus};
+use super::{tasks, tasks_mut};
 
-/// Scheduler
-pub fn switch(tick: u64) {
-    // Wait for 5 ticks
-    if tick % 5 != 0 {
-        return;
+/// Dispatcher
+/// Accounts the tick to the running task and switches
+/// to whichever task the policy picks when it's preempted.
+pub fn switch<S: Scheduler + ?Sized>(sched: &S, _tick: u64) {
+    let preempt = {
+        let cpu = unsafe { this_cpu() };
+        match cpu.current {
+            // Tasks are not initialized yet
+            None => return,
+            Some(ref current_lock) => {
+                let mut current = current_lock.write();
+                if current.tid() == IDLE_TID {
+                    // Leave idle as soon as anything is ready
+                    true
+                } else {
+                    current.status != TaskStatus::Running
+                        || !current.sched.allowed(arch::cpu_id())
+                        || sched.task_tick(&mut current)
+                }
+            }
+        }
+    };
+
+    if preempt {
+        unsafe {
+            schedule(sched, true);
+        }
     }
+}
 
-    let mut to_ptr: *mut Task = null_mut();
-    // Find next task
-    // Use round-robin scheduling
+/// Pick the next task and switch to it
+/// The outgoing task is put back on the run queue if it's still running,
+/// tasks which are blocked or terminated must have their status set
+/// before calling this.
+/// Interrupts must be disabled. Returns when the outgoing task is resumed.
+pub unsafe fn schedule<S: Scheduler + ?Sized>(sched: &S, preempted: bool) {
+    let cpu = this_cpu();
+    let cpu_id = arch::cpu_id();
+    let prev_ctx: *mut Context;
+    let next_ctx: *const Context;
     {
-        let mut tasks = tasks_mut();
+        // Only looked up, terminated tasks are removed by finish_switch()
+        let tasks = tasks();
+
+        let prev_lock = cpu.current.take().expect("No running task");
         {
-            // we must have a currently running task
-            // otherwise panic
-            let current = tasks.current();
-            if current.is_none() {
-                panic!("No running task, scheduler aborted.");
+            let mut prev = prev_lock.write();
+            if prev.status == TaskStatus::Running && prev.tid() != IDLE_TID {
+                prev.status = TaskStatus::Ready;
+                // Its affinity may have changed meanwhile
+                if !prev.sched.allowed(cpu_id) {
+                    prev.sched.cpu = select_cpu(sched, &prev);
+                }
+                sched.enqueue(&mut prev);
             }
         }
 
-        // remove terminated tasks
-        {
-            let mut died_tasks: Vec<u64> = Vec::new();
-            for (tid, task_lock) in tasks.iter() {
+        // Find next available task, from other CPUs if none is queued here
+        // Tasks another CPU is still switching away from are put back.
+        let mut next_lock = None;
+        let mut busy = Vec::new();
+        while let Some(tid) = sched.pick_next(cpu_id).or_else(|| sched.steal(cpu_id)) {
+            if let Some(task_lock) = tasks.get(tid) {
                 let task = task_lock.read();
-                if task.died() {
-                    died_tasks.push(*tid);
+                if task.on_cpu && !Arc::ptr_eq(task_lock, &prev_lock) {
+                    busy.push(task_lock.clone());
+                    continue;
+                }
+                if task.standby() {
+                    next_lock = Some(task_lock.clone());
+                    break;
                 }
             }
-            for tid in died_tasks.iter() {
-                tasks.remove(tid);
-            }
         }
+        for task_lock in busy.iter() {
+            sched.enqueue(&mut task_lock.write());
+        }
+        let next_lock = match next_lock {
+            Some(next_lock) => next_lock,
+            None => cpu.idle.clone().expect("No idle task"),
+        };
 
-        // Find next available task
-        {
-            let check_task = |task: &mut Task| -> bool { task.standby() };
+        if Arc::ptr_eq(&prev_lock, &next_lock) {
+            // Picked the running task again
+            next_lock.write().status = TaskStatus::Running;
+            cpu.current = Some(prev_lock);
+            return;
+        }
 
-            let current_id = super::TASK_ID.read();
-            for (tid, task_lock) in tasks.iter() {
-                if *tid > *current_id {
-                    let mut task = task_lock.write();
-                    if check_task(&mut task) {
-                        to_ptr = task.deref_mut() as *mut Task;
-                    }
-                }
+        let prev_died = {
+            let mut prev = prev_lock.write();
+            if preempted {
+                prev.stats.involuntary_switches += 1;
+            } else {
+                prev.stats.voluntary_switches += 1;
             }
+            prev_ctx = &mut prev.context as *mut Context;
+            prev.died()
+        };
+        {
+            let mut next = next_lock.write();
+            next.on_cpu = true;
+            next.sched.cpu = cpu_id;
+            next.status = TaskStatus::Running;
+            next.stats.scheduled += 1;
+            next_ctx = &next.context as *const Context;
+            cpu.current_tid = next.tid();
+        }
 
-            if to_ptr == null_mut() {
-                for (tid, task_lock) in tasks.iter() {
-                    if *tid < *current_id {
-                        let mut task = task_lock.write();
-                        if check_task(&mut task) {
-                            to_ptr = task.deref_mut() as *mut Task;
-                        }
-                    }
-                }
-            }
+        // Both contexts are kept alive by cpu.current and,
+        // either the task list or cpu.zombie.
+        if prev_died {
+            cpu.zombie = Some(prev_lock);
+        } else {
+            cpu.prev = Some(prev_lock);
+        }
+        cpu.current = Some(next_lock);
+    }
 
-            // Set running task status
-            if to_ptr != null_mut() {
-                let current_lock = tasks.current().unwrap();
-                let mut current = current_lock.write();
-                current.status = TaskStatus::Ready;
+    arch::switch_context(prev_ctx, next_ctx);
+    finish_switch();
+}
+
+/// Pick the run queue for a ready task: the CPU it last ran
+/// on unless an allowed one has a shorter queue
+pub fn select_cpu<S: Scheduler + ?Sized>(sched: &S, task: &Task) -> usize {
+    let count = arch::cpu_count();
+    let mut best = match (0..count).find(|&cpu| task.sched.allowed(cpu)) {
+        // Nowhere to go, stay where it was
+        None => return task.sched.cpu.min(count - 1),
+        Some(cpu) => cpu,
+    };
+    if task.sched.cpu < count && task.sched.allowed(task.sched.cpu) {
+        best = task.sched.cpu;
+    }
+    let mut best_load = sched.load(best);
+    for cpu in 0..count {
+        if best_load == 0 {
+            break;
+        }
+        if task.sched.allowed(cpu) {
+            let load = sched.load(cpu);
+            if load < best_load {
+                best = cpu;
+                best_load = load;
             }
         }
     }
+    best
+}
 
-    if to_ptr == null_mut() {
-        println!("No scheduled task, returning to original.");
-    } else {
-        unsafe {
-            println!("Switching to task {}", (*to_ptr).tid());
-            (*to_ptr).status = TaskStatus::Running;
-            set_current_tid((*to_ptr).tid());
-            (*to_ptr).context.switch_to();
-        }
+/// Finish a switch on the stack of the incoming task
+#[no_mangle]
+pub extern "C" fn finish_switch() {
+    let cpu = unsafe { this_cpu() };
+    // Other CPUs may run the previous task from now on
+    if let Some(prev_lock) = cpu.prev.take() {
+        prev_lock.write().on_cpu = false;
+    }
+    // Now it's safe to free the terminated task
+    // A task only terminates itself, this is the one place it's reaped.
+    if let Some(zombie) = cpu.zombie.take() {
+        let tid = zombie.read().tid();
+        let entry = tasks_mut().remove(&tid);
+        // Freed with the task list unlocked, dropping a task may wake others
+        drop(entry);
+        drop(zombie);
     }
 }
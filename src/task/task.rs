@@ -1,11 +1,20 @@
-use arch::Context;
+use arch::{Context, TrapFrame};
+use fs::FileTable;
+use ipc::HandleTable;
+use time::TimerHandle;
+
+use super::sched::SchedEntity;
+use super::signal::SignalState;
+use super::stats::{TaskInfo, TaskStats};
 
 #[repr(u8)]
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TaskStatus {
     Initializing,
     Ready,
     Running,
+    Blocked,
+    Stopped,
     Terminated,
 }
 
@@ -14,10 +23,34 @@ pub struct Task {
     pub context: Context,
     // task ID
     tid: u64,
+    // TID of the forking task, 0 if started by the kernel
+    parent: u64,
     // Status
     pub status: TaskStatus,
     // Exit code
     exit_code: u64,
+    // Scheduling parameters
+    pub sched: SchedEntity,
+    // Accounting
+    pub stats: TaskStats,
+    // Signals
+    pub signals: SignalState,
+    // Wait channel while blocked, 0 if none
+    pub wchan: usize,
+    // Identifies the current wait to its timer
+    pub wait_ticket: u64,
+    // Timer ending a timed wait
+    pub wait_timer: Option<TimerHandle>,
+    // A signal ends the wait
+    pub interruptible: bool,
+    // Running on a CPU, or still being switched away from
+    pub on_cpu: bool,
+    // IPC capabilities
+    pub handles: HandleTable,
+    // Open files
+    pub files: FileTable,
+    // May act on the whole system, e.g. reboot
+    privileged: bool,
 }
 
 impl Task {
@@ -25,11 +58,107 @@ impl Task {
         Task {
             context: Context::new(),
             tid: tid,
+            parent: 0,
+            status: TaskStatus::Initializing,
+            exit_code: 0,
+            sched: SchedEntity::new(),
+            stats: TaskStats::new(),
+            signals: SignalState::new(),
+            wchan: 0,
+            wait_ticket: 0,
+            wait_timer: None,
+            interruptible: false,
+            on_cpu: false,
+            handles: HandleTable::new(),
+            files: FileTable::stdio(),
+            privileged: true,
+        }
+    }
+
+    /// Create a kernel thread
+    pub fn new_kernel(tid: u64, entry: fn(usize), arg: usize) -> Self {
+        Task {
+            context: Context::new_kernel(entry, arg),
+            tid: tid,
+            parent: 0,
             status: TaskStatus::Initializing,
             exit_code: 0,
+            sched: SchedEntity::new(),
+            stats: TaskStats::new(),
+            signals: SignalState::new(),
+            wchan: 0,
+            wait_ticket: 0,
+            wait_timer: None,
+            interruptible: false,
+            on_cpu: false,
+            handles: HandleTable::new(),
+            files: FileTable::new(),
+            privileged: true,
         }
     }
 
+    /// Create the idle task from the running code
+    pub fn new_idle(tid: u64) -> Self {
+        Task {
+            context: Context::new_idle(),
+            tid: tid,
+            parent: 0,
+            status: TaskStatus::Initializing,
+            exit_code: 0,
+            sched: SchedEntity::new(),
+            stats: TaskStats::new(),
+            signals: SignalState::new(),
+            wchan: 0,
+            wait_ticket: 0,
+            wait_timer: None,
+            interruptible: false,
+            on_cpu: false,
+            handles: HandleTable::new(),
+            files: FileTable::new(),
+            privileged: true,
+        }
+    }
+
+    /// Create a child of this task, resuming from frame
+    /// The child shares open files, IPC handles and privileges
+    /// aren't inherited.
+    pub fn fork(&self, tid: u64, frame: &TrapFrame) -> Result<Self, ::common::error::Error> {
+        Ok(Task {
+            context: try!(self.context.fork(frame)),
+            tid: tid,
+            parent: self.tid,
+            status: TaskStatus::Initializing,
+            exit_code: 0,
+            sched: self.sched.fork(),
+            stats: TaskStats::new(),
+            signals: self.signals.fork(),
+            wchan: 0,
+            wait_ticket: 0,
+            wait_timer: None,
+            interruptible: false,
+            on_cpu: false,
+            handles: HandleTable::new(),
+            files: self.files.fork(),
+            privileged: false,
+        })
+    }
+
+    /// Check if task is a kernel thread
+    pub fn is_kernel(&self) -> bool {
+        self.context.is_kernel()
+    }
+
+    /// Check if task was started by the kernel rather than forked
+    pub fn privileged(&self) -> bool {
+        self.privileged
+    }
+
+    /// Mark the task terminated
+    pub fn terminate(&mut self, exit_code: u64) {
+        self.status = TaskStatus::Terminated;
+        self.exit_code = exit_code;
+    }
+
     /// Check if task is terminated
     pub fn died(&self) -> bool {
         self.status == TaskStatus::Terminated
@@ -49,4 +178,23 @@ impl Task {
     pub fn tid(&self) -> u64 {
         self.tid
     }
+
+    /// Get the TID of the task which forked this one, 0 if none
+    pub fn parent(&self) -> u64 {
+        self.parent
+    }
+
+    /// Take a snapshot of the task
+    pub fn info(&self) -> TaskInfo {
+        TaskInfo {
+            tid: self.tid,
+            status: self.status,
+            kernel: self.is_kernel(),
+            nice: self.sched.nice,
+            priority: self.sched.priority,
+            pages: self.context.pages(),
+            peak_pages: self.context.peak_pages(),
+            stats: self.stats,
+        }
+    }
 }
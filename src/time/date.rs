@@ -0,0 +1,140 @@
+//! Calendar dates
+
+/// A UTC date and time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+fn is_leap(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// Whether every field is in range, dates before 1970 aren't
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Seconds since the Unix epoch
+    pub fn to_unix(&self) -> u64 {
+        let mut days = 0;
+        for year in 1970..self.year {
+            days += if is_leap(year) { 366 } else { 365 };
+        }
+        for month in 1..self.month {
+            days += days_in_month(self.year, month) as u64;
+        }
+        days += (self.day - 1) as u64;
+        ((days * 24 + self.hour as u64) * 60 + self.minute as u64) * 60 + self.second as u64
+    }
+
+    /// Date of seconds since the Unix epoch
+    pub fn from_unix(seconds: u64) -> Self {
+        let mut days = seconds / 86400;
+        let rest = (seconds % 86400) as u32;
+        let mut year = 1970;
+        loop {
+            let length = if is_leap(year) { 366 } else { 365 };
+            if days < length {
+                break;
+            }
+            days -= length;
+            year += 1;
+        }
+        let mut month = 1;
+        while days >= days_in_month(year, month) as u64 {
+            days -= days_in_month(year, month) as u64;
+            month += 1;
+        }
+        DateTime {
+            year: year,
+            month: month,
+            day: days as u32 + 1,
+            hour: rest / 3600,
+            minute: rest / 60 % 60,
+            second: rest % 60,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime {
+        DateTime {
+            year: year,
+            month: month,
+            day: day,
+            hour: hour,
+            minute: minute,
+            second: second,
+        }
+    }
+
+    /// Converts both ways
+    fn check(date: DateTime, seconds: u64) {
+        assert!(date.is_valid());
+        assert_eq!(date.to_unix(), seconds);
+        assert_eq!(DateTime::from_unix(seconds), date);
+    }
+
+    #[test]
+    fn epoch() {
+        check(date(1970, 1, 1, 0, 0, 0), 0);
+        check(date(1970, 1, 1, 23, 59, 59), 86399);
+        check(date(1970, 1, 2, 0, 0, 0), 86400);
+    }
+
+    #[test]
+    fn leap_days() {
+        check(date(1972, 2, 29, 0, 0, 0), 68169600);
+        check(date(1972, 3, 1, 0, 0, 0), 68256000);
+        check(date(2024, 2, 29, 12, 0, 0), 1709208000);
+        assert!(!date(2023, 2, 29, 0, 0, 0).is_valid());
+    }
+
+    #[test]
+    fn centuries() {
+        // 2000 is divisible by 400, a leap year
+        check(date(2000, 2, 29, 0, 0, 0), 951782400);
+        check(date(2000, 12, 31, 0, 0, 0), 978220800);
+        // 1900 and 2100 are divisible by 100 only
+        assert!(!date(2100, 2, 29, 0, 0, 0).is_valid());
+        check(date(2100, 2, 28, 0, 0, 0), 4107456000);
+        check(date(2100, 3, 1, 0, 0, 0), 4107542400);
+        assert!(!is_leap(1900));
+        assert!(is_leap(2000));
+        assert!(!is_leap(2100));
+    }
+
+    #[test]
+    fn invalid_fields() {
+        assert!(!date(1969, 12, 31, 0, 0, 0).is_valid());
+        assert!(!date(2020, 13, 1, 0, 0, 0).is_valid());
+        assert!(!date(2020, 4, 31, 0, 0, 0).is_valid());
+        assert!(!date(2020, 1, 1, 24, 0, 0).is_valid());
+    }
+}
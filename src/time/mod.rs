@@ -0,0 +1,151 @@
+//! Time keeping and timers
+//!
+//! The tick count is advanced by the timer interrupt, which then runs
+//! the timers due. The monotonic clock counts nanoseconds from boot,
+//! interpolated between ticks with the TSC where there's one. The
+//! wall clock adds the RTC date read at boot to the monotonic clock.
+//! Timer callbacks run in interrupt context with interrupts
+//! disabled, they must be short and must not sleep.
+
+mod date;
+mod wheel;
+
+use alloc::boxed::Box;
+use arch;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use spin::Once;
+use sync::IrqSpinLock;
+
+use self::wheel::{Entry, Wheel};
+
+pub use self::date::DateTime;
+
+static WHEEL: Once<IrqSpinLock<Wheel>> = Once::new();
+/// Wall clock at boot, in nanoseconds since the Unix epoch
+static BOOT_REALTIME: AtomicUsize = AtomicUsize::new(0);
+
+fn wheel() -> &'static IrqSpinLock<Wheel> {
+    WHEEL.call_once(|| IrqSpinLock::new(Wheel::new(ticks())))
+}
+
+/// Get the ticks elapsed since boot
+pub fn ticks() -> u64 {
+    arch::ticks()
+}
+
+/// Get the monotonic clock, in nanoseconds since boot
+pub fn now() -> u64 {
+    arch::clock_ns()
+}
+
+/// Get the time elapsed since boot
+pub fn uptime() -> Duration {
+    let ns = now();
+    Duration::new(ns / 1_000_000_000, (ns % 1_000_000_000) as u32)
+}
+
+/// Get the wall clock, in nanoseconds since the Unix epoch
+pub fn realtime() -> u64 {
+    BOOT_REALTIME.load(Ordering::Relaxed) as u64 + now()
+}
+
+/// Get the wall clock, in seconds since the Unix epoch
+pub fn unix_time() -> u64 {
+    realtime() / 1_000_000_000
+}
+
+/// Get the current UTC date
+pub fn date() -> DateTime {
+    DateTime::from_unix(unix_time())
+}
+
+/// Set the wall clock, in nanoseconds since the Unix epoch
+/// Only the kernel's notion of time changes, not the RTC.
+pub fn set_realtime(ns: u64) {
+    BOOT_REALTIME.store(ns.saturating_sub(now()) as usize, Ordering::Relaxed);
+}
+
+/// Convert milliseconds to ticks, rounding up
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    let tick_ns = arch::tick_ns();
+    ms.saturating_mul(1_000_000).saturating_add(tick_ns - 1) / tick_ns
+}
+
+/// Convert ticks to milliseconds
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * arch::tick_ns() / 1_000_000
+}
+
+/// Refers to an armed timer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerHandle(u64);
+
+impl TimerHandle {
+    /// Cancel the timer, returns false if it already fired
+    /// A periodic timer cancelled from its own callback isn't re-armed.
+    pub fn cancel(&self) -> bool {
+        wheel().lock().cancel(self.0)
+    }
+}
+
+fn arm(ticks: u64, period: u64, callback: Box<FnMut() + Send>) -> TimerHandle {
+    let mut wheel = wheel().lock();
+    let id = wheel.alloc_id();
+    let expires = wheel.now() + ticks;
+    wheel.insert(Entry {
+        id: id,
+        expires: expires,
+        period: period,
+        callback: callback,
+    });
+    TimerHandle(id)
+}
+
+/// Run callback once, ticks from now
+pub fn after_ticks<F: FnMut() + Send + 'static>(ticks: u64, callback: F) -> TimerHandle {
+    arm(ticks, 0, Box::new(callback))
+}
+
+/// Run callback once, ms milliseconds from now
+pub fn after<F: FnMut() + Send + 'static>(ms: u64, callback: F) -> TimerHandle {
+    after_ticks(ms_to_ticks(ms), callback)
+}
+
+/// Run callback every ms milliseconds until cancelled
+pub fn every<F: FnMut() + Send + 'static>(ms: u64, callback: F) -> TimerHandle {
+    let period = match ms_to_ticks(ms) {
+        0 => 1,
+        period => period,
+    };
+    arm(period, period, Box::new(callback))
+}
+
+/// Sleep for ms milliseconds, EINTR if a signal cuts it short
+pub fn sleep(ms: u64) -> Result<(), ::common::error::Error> {
+    ::task::sleep(ms_to_ticks(ms))
+}
+
+/// Allocate the timer wheel and read the wall clock from the RTC
+pub fn init() {
+    wheel();
+    let date = arch::read_rtc();
+    if date.is_valid() {
+        set_realtime(date.to_unix() * 1_000_000_000);
+    } else {
+        println!("RTC has no valid date: {:?}", date);
+    }
+}
+
+/// Run the timers due
+/// Called from the timer interrupt after the tick count advanced.
+pub fn tick() {
+    let expired = wheel().lock().advance();
+    for mut entry in expired {
+        // Callbacks may arm or cancel timers
+        (entry.callback)();
+        if entry.period > 0 {
+            wheel().lock().rearm(entry);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Hierarchical timer wheel
+//!
+//! Level n has 64 slots of 64^n ticks each. A timer goes to the lowest
+//! level whose span covers its deadline and is cascaded down a level
+//! each time the level below wraps around, until it expires on level 0.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem;
+
+const LEVEL_BITS: u64 = 6;
+const LEVEL_SIZE: usize = 1 << LEVEL_BITS;
+const LEVELS: usize = 4;
+/// Ticks covered by the whole wheel, later timers wait in overflow
+const WHEEL_SPAN: u64 = 1 << (LEVEL_BITS * LEVELS as u64);
+
+pub type Callback = Box<FnMut() + Send>;
+
+pub struct Entry {
+    pub id: u64,
+    pub expires: u64,
+    /// Ticks between runs of a periodic timer, 0 if one-shot
+    pub period: u64,
+    pub callback: Callback,
+}
+
+/// Where a timer is
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Slot(usize, usize),
+    Overflow,
+    /// Its callback is being run
+    Running,
+}
+
+pub struct Wheel {
+    now: u64,
+    levels: Vec<Vec<Vec<Entry>>>,
+    overflow: Vec<Entry>,
+    locations: BTreeMap<u64, Location>,
+    next_id: u64,
+}
+
+impl Wheel {
+    pub fn new(now: u64) -> Self {
+        let mut levels = Vec::with_capacity(LEVELS);
+        for _ in 0..LEVELS {
+            let mut slots = Vec::with_capacity(LEVEL_SIZE);
+            for _ in 0..LEVEL_SIZE {
+                slots.push(Vec::new());
+            }
+            levels.push(slots);
+        }
+        Wheel {
+            now: now,
+            levels: levels,
+            overflow: Vec::new(),
+            locations: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Current tick of the wheel
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn alloc_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Number of armed timers
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn insert(&mut self, mut entry: Entry) {
+        // Expired timers fire on the next tick
+        if entry.expires <= self.now {
+            entry.expires = self.now + 1;
+        }
+        self.place(entry);
+    }
+
+    /// Put a timer in the slot its deadline falls in
+    /// One due now goes to the current slot, which advance() empties next.
+    fn place(&mut self, entry: Entry) {
+        let delta = entry.expires.saturating_sub(self.now);
+        let location = if delta >= WHEEL_SPAN {
+            Location::Overflow
+        } else {
+            let mut level = 0;
+            while delta >= 1 << (LEVEL_BITS * (level as u64 + 1)) {
+                level += 1;
+            }
+            let slot = ((entry.expires >> (LEVEL_BITS * level as u64)) as usize) & (LEVEL_SIZE - 1);
+            Location::Slot(level, slot)
+        };
+        self.locations.insert(entry.id, location);
+        match location {
+            Location::Slot(level, slot) => self.levels[level][slot].push(entry),
+            _ => self.overflow.push(entry),
+        };
+    }
+
+    /// Remove a timer, returns false if it's not armed
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let list = match self.locations.remove(&id) {
+            None => return false,
+            Some(Location::Running) => return true,
+            Some(Location::Slot(level, slot)) => &mut self.levels[level][slot],
+            Some(Location::Overflow) => &mut self.overflow,
+        };
+        list.retain(|entry| entry.id != id);
+        true
+    }
+
+    /// Re-arm a periodic timer after its callback ran,
+    /// unless it was cancelled meanwhile
+    pub fn rearm(&mut self, mut entry: Entry) {
+        if self.locations.get(&entry.id) != Some(&Location::Running) {
+            return;
+        }
+        entry.expires += entry.period;
+        self.insert(entry);
+    }
+
+    /// Advance by one tick, returns the expired timers
+    /// Periodic timers are marked running and must be handed to rearm().
+    pub fn advance(&mut self) -> Vec<Entry> {
+        self.now += 1;
+
+        // Cascade higher levels whose slot boundary is reached
+        let mut level = 1;
+        while level < LEVELS && self.now & ((1 << (LEVEL_BITS * level as u64)) - 1) == 0 {
+            let slot = ((self.now >> (LEVEL_BITS * level as u64)) as usize) & (LEVEL_SIZE - 1);
+            let entries = mem::replace(&mut self.levels[level][slot], Vec::new());
+            for entry in entries {
+                self.place(entry);
+            }
+            level += 1;
+        }
+        if self.now & (WHEEL_SPAN - 1) == 0 {
+            let entries = mem::replace(&mut self.overflow, Vec::new());
+            for entry in entries {
+                self.place(entry);
+            }
+        }
+
+        let slot = (self.now as usize) & (LEVEL_SIZE - 1);
+        let expired = mem::replace(&mut self.levels[0][slot], Vec::new());
+        for entry in expired.iter() {
+            if entry.period > 0 {
+                self.locations.insert(entry.id, Location::Running);
+            } else {
+                self.locations.remove(&entry.id);
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arm(wheel: &mut Wheel, expires: u64) -> u64 {
+        let id = wheel.alloc_id();
+        wheel.insert(Entry {
+            id: id,
+            expires: expires,
+            period: 0,
+            callback: Box::new(|| {}),
+        });
+        id
+    }
+
+    /// Advance to tick, returns when each timer fired and its deadline
+    fn run(wheel: &mut Wheel, tick: u64) -> Vec<(u64, u64, u64)> {
+        let mut fired = Vec::new();
+        while wheel.now() < tick {
+            for entry in wheel.advance() {
+                fired.push((wheel.now(), entry.id, entry.expires));
+            }
+        }
+        fired
+    }
+
+    #[test]
+    fn slots_by_level() {
+        let mut wheel = Wheel::new(0);
+        let near = arm(&mut wheel, 5);
+        let far = arm(&mut wheel, 100);
+        assert!(wheel.levels[0][5].iter().any(|entry| entry.id == near));
+        assert!(wheel.levels[1][1].iter().any(|entry| entry.id == far));
+        assert_eq!(run(&mut wheel, 200), vec![(5, near, 5), (100, far, 100)]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn expired_fires_next_tick() {
+        let mut wheel = Wheel::new(10);
+        let id = arm(&mut wheel, 3);
+        assert_eq!(run(&mut wheel, 20), vec![(11, id, 11)]);
+    }
+
+    #[test]
+    fn cascade_on_slot_boundary() {
+        let mut wheel = Wheel::new(0);
+        let ids: Vec<u64> = [64, 128, 4096, 4097].iter().map(|&t| arm(&mut wheel, t)).collect();
+        let fired = run(&mut wheel, 5000);
+        assert_eq!(
+            fired,
+            vec![
+                (64, ids[0], 64),
+                (128, ids[1], 128),
+                (4096, ids[2], 4096),
+                (4097, ids[3], 4097),
+            ]
+        );
+    }
+
+    #[test]
+    fn cancel_removes() {
+        let mut wheel = Wheel::new(0);
+        let id = arm(&mut wheel, 70);
+        assert!(wheel.cancel(id));
+        assert!(!wheel.cancel(id));
+        assert!(run(&mut wheel, 200).is_empty());
+    }
+}